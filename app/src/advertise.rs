@@ -0,0 +1,85 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use log::info;
+use oci_spec::runtime::Spec;
+use crate::utils::oci_utils;
+
+/// Annotation restricting the ports a sender binds for network transfers, as `first-last`.
+pub const PORT_RANGE_ANNOTATION: &str = "transfer.port_range";
+
+/// Suffix of the file next to a bundle advertising the address its network transfer is bound to.
+pub const ADDRESS_SUFFIX: &str = ".addr";
+
+/// Ports a sender may bind, both ends included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    pub first: u16,
+    pub last: u16,
+}
+
+impl PortRange {
+    pub fn parse(range: &str) -> Option<PortRange> {
+        let (first, last) = range.split_once('-')?;
+        let range = PortRange { first: first.trim().parse().ok()?, last: last.trim().parse().ok()? };
+        (range.first <= range.last).then_some(range)
+    }
+
+    /// Reads the range from the `transfer.port_range` annotation of the sender.
+    pub fn from_spec(spec: &Spec) -> Option<PortRange> {
+        PortRange::parse(&oci_utils::get_wasm_annotations(spec, PORT_RANGE_ANNOTATION))
+    }
+}
+
+/// Whether the sender binds a port of its own choosing rather than the port of `address`: port
+/// `0` requests an ephemeral port, a port range picks the first free port of the range.
+pub fn is_dynamic(address: &str, range: Option<PortRange>) -> bool {
+    range.is_some() || address.rsplit_once(':').map_or(false, |(_, port)| port == "0")
+}
+
+/// Binds the listener of a network transfer on the host of `address`, on the first free port of
+/// `range` or on an ephemeral port.
+pub fn bind(address: &str, range: Option<PortRange>) -> io::Result<TcpListener> {
+    let host = address.rsplit_once(':').map_or(address, |(host, _)| host);
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    let range = match range {
+        Some(range) => range,
+        None => return TcpListener::bind((host, 0)),
+    };
+    for port in range.first..=range.last {
+        match TcpListener::bind((host, port)) {
+            Ok(listener) => return Ok(listener),
+            Err(e) if e.kind() == ErrorKind::AddrInUse => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(ErrorKind::AddrInUse, format!("no free port in {}-{}", range.first, range.last)))
+}
+
+fn address_file(bundle_path: &str) -> String {
+    format!("{}{}", bundle_path.trim_end_matches('/'), ADDRESS_SUFFIX)
+}
+
+/// Advertises the address a sender is bound to next to its bundle. The file is replaced
+/// atomically so receivers never read a partial address.
+pub fn advertise(bundle_path: &str, address: SocketAddr) -> io::Result<()> {
+    let path = address_file(bundle_path);
+    let staging = format!("{}.tmp", path);
+    fs::write(&staging, address.to_string())?;
+    fs::rename(&staging, &path)?;
+    info!("advertised transfer address {} in {}", address, path);
+    Ok(())
+}
+
+/// Withdraws the address of a sender once its transfer has been served.
+pub fn withdraw(bundle_path: &str) {
+    let _ = fs::remove_file(address_file(bundle_path));
+}
+
+/// Returns the address advertised next to the bundle of a sender, if it is serving a transfer.
+pub fn resolve(bundle_path: &str) -> Option<String> {
+    let address = fs::read_to_string(address_file(bundle_path)).ok()?;
+    let address = address.trim();
+    address.to_socket_addrs().ok()?.next()?;
+    Some(address.to_string())
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use rand::Rng;
+use serde::Deserialize;
+use crate::utils::oci_utils;
+
+/// Annotation selecting how a sender spreads transfers over the replicas of a target:
+/// `round-robin` (default), `least-in-flight` or `random`.
+pub const BALANCING_ANNOTATION: &str = "target.balancing";
+
+/// Consecutive failed transfers after which a replica is considered unhealthy.
+pub const UNHEALTHY_AFTER: u32 = 3;
+
+/// Time an unhealthy replica is skipped before it is tried again.
+pub const UNHEALTHY_BACKOFF: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Strategy {
+    #[default]
+    RoundRobin,
+    LeastInFlight,
+    Random,
+}
+
+impl Strategy {
+    pub fn parse(name: &str) -> Option<Strategy> {
+        match name {
+            "round-robin" => Some(Strategy::RoundRobin),
+            "least-in-flight" => Some(Strategy::LeastInFlight),
+            "random" => Some(Strategy::Random),
+            _ => None,
+        }
+    }
+
+    /// Reads the strategy from the `target.balancing` annotation of the sender.
+    pub fn from_spec(spec: &Spec) -> Strategy {
+        Strategy::parse(&oci_utils::get_wasm_annotations(spec, BALANCING_ANNOTATION)).unwrap_or_default()
+    }
+}
+
+/// A replica of a target function.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Replica {
+    /// Container of this node, reached over the Unix socket next to its bundle.
+    Local(String),
+    /// Address served over the network transport.
+    Remote(String),
+}
+
+impl Replica {
+    pub fn is_local(&self) -> bool {
+        matches!(self, Replica::Local(_))
+    }
+
+    /// Returns the Unix socket of a local replica, next to its bundle.
+    pub fn socket_path(&self) -> Option<String> {
+        match self {
+            Replica::Local(container_path) => Some(format!("{}.sock", container_path)),
+            Replica::Remote(_) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ReplicaState {
+    in_flight: usize,
+    failures: u32,
+    unhealthy_until: Option<Instant>,
+}
+
+lazy_static! {
+    static ref REPLICAS: Mutex<HashMap<Replica, ReplicaState>> = Mutex::new(HashMap::new());
+    static ref NEXT: Mutex<HashMap<String, usize>> = Mutex::new(HashMap::new());
+}
+
+/// Whether transfers are sent to the replica, i.e. it has not failed repeatedly of late.
+pub fn is_healthy(replica: &Replica) -> bool {
+    let replicas = REPLICAS.lock().unwrap();
+    replicas
+        .get(replica)
+        .and_then(|state| state.unhealthy_until)
+        .map_or(true, |until| until <= Instant::now())
+}
+
+/// Number of transfers currently sent to the replica.
+pub fn in_flight(replica: &Replica) -> usize {
+    REPLICAS.lock().unwrap().get(replica).map_or(0, |state| state.in_flight)
+}
+
+/// Selects the replica of `target` a transfer is sent to. Healthy replicas are preferred, and
+/// among them local ones; if every replica is unhealthy all of them are candidates again.
+pub fn select(target: &str, replicas: &[Replica], strategy: Strategy) -> Option<Selection> {
+    let healthy: Vec<&Replica> = replicas.iter().filter(|replica| is_healthy(replica)).collect();
+    let mut candidates = if healthy.is_empty() { replicas.iter().collect() } else { healthy };
+    if candidates.iter().any(|replica| replica.is_local()) {
+        candidates.retain(|replica| replica.is_local());
+    }
+    if candidates.is_empty() {
+        return None;
+    }
+
+    let mut states = REPLICAS.lock().unwrap();
+    let index = match strategy {
+        Strategy::RoundRobin => {
+            let mut next = NEXT.lock().unwrap();
+            let counter = next.entry(target.to_string()).or_insert(0);
+            let index = *counter % candidates.len();
+            *counter = counter.wrapping_add(1);
+            index
+        }
+        Strategy::LeastInFlight => (0..candidates.len())
+            .min_by_key(|index| states.get(candidates[*index]).map_or(0, |state| state.in_flight))
+            .unwrap(),
+        Strategy::Random => rand::thread_rng().gen_range(0..candidates.len()),
+    };
+    let replica = candidates[index].clone();
+    states.entry(replica.clone()).or_default().in_flight += 1;
+    info!("selected replica {:?} of {} ({:?})", replica, target, strategy);
+    Some(Selection { replica })
+}
+
+/// A transfer in flight to a replica. Dropping it ends the transfer; reporting its outcome
+/// through [`Selection::failed`] or [`Selection::succeeded`] updates the health of the replica.
+pub struct Selection {
+    replica: Replica,
+}
+
+impl Selection {
+    pub fn replica(&self) -> &Replica {
+        &self.replica
+    }
+
+    pub fn succeeded(self) {
+        if let Some(state) = REPLICAS.lock().unwrap().get_mut(&self.replica) {
+            state.failures = 0;
+            state.unhealthy_until = None;
+        }
+    }
+
+    /// Reports the outcome of the transfer, see [`Selection::succeeded`] and [`Selection::failed`].
+    pub fn report(self, succeeded: bool) {
+        match succeeded {
+            true => self.succeeded(),
+            false => self.failed(),
+        }
+    }
+
+    pub fn failed(self) {
+        if let Some(state) = REPLICAS.lock().unwrap().get_mut(&self.replica) {
+            state.failures += 1;
+            if state.failures >= UNHEALTHY_AFTER {
+                warn!("replica {:?} failed {} transfers in a row, skipping it", self.replica, state.failures);
+                state.unhealthy_until = Some(Instant::now() + UNHEALTHY_BACKOFF);
+            }
+        }
+    }
+}
+
+impl Drop for Selection {
+    fn drop(&mut self) {
+        if let Some(state) = REPLICAS.lock().unwrap().get_mut(&self.replica) {
+            state.in_flight -= 1;
+        }
+    }
+}
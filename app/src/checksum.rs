@@ -0,0 +1,121 @@
+use std::fmt;
+use std::io::{self, ErrorKind};
+use oci_spec::runtime::Spec;
+use xxhash_rust::xxh3::Xxh3;
+use crate::utils::oci_utils;
+
+/// Annotation selecting the checksum senders append to their payloads, `crc32c` or `xxh3`.
+pub const CHECKSUM_ANNOTATION: &str = "transfer.checksum";
+
+/// Prefix of the trailer carrying the checksum after the payload.
+pub const TRAILER_MAGIC: &[u8; 4] = b"RRCK";
+
+/// Length of the trailer: magic, algorithm (u8), checksum (u64, little-endian).
+pub const TRAILER_LEN: usize = 13;
+
+/// Acknowledgements of a network transfer sent back by the receiver after verifying it.
+pub const ACK_OK: u8 = 0;
+pub const ACK_MISMATCH: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Crc32c,
+    Xxh3,
+}
+
+impl Algorithm {
+    /// Reads the algorithm from the `transfer.checksum` annotation, `None` if checksums are off.
+    pub fn from_spec(spec: &Spec) -> Option<Algorithm> {
+        match oci_utils::get_wasm_annotations(spec, CHECKSUM_ANNOTATION).as_str() {
+            "crc32c" => Some(Algorithm::Crc32c),
+            "xxh3" => Some(Algorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Algorithm::Crc32c => 1,
+            Algorithm::Xxh3 => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Algorithm> {
+        match id {
+            1 => Some(Algorithm::Crc32c),
+            2 => Some(Algorithm::Xxh3),
+            _ => None,
+        }
+    }
+
+    /// Computes the checksum of the concatenation of `segments`.
+    pub fn checksum(self, segments: &[&[u8]]) -> u64 {
+        match self {
+            Algorithm::Crc32c => segments
+                .iter()
+                .fold(0, |crc, segment| crc32c::crc32c_append(crc, segment)) as u64,
+            Algorithm::Xxh3 => {
+                let mut hasher = Xxh3::new();
+                segments.iter().for_each(|segment| hasher.update(segment));
+                hasher.digest()
+            }
+        }
+    }
+}
+
+/// Error of a payload whose content does not match the checksum of its trailer.
+#[derive(Debug)]
+pub struct ChecksumMismatch;
+
+impl fmt::Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "payload does not match its checksum")
+    }
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+pub fn mismatch_error() -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, ChecksumMismatch)
+}
+
+pub fn is_mismatch(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |inner| inner.is::<ChecksumMismatch>())
+}
+
+/// Returns the trailer to send after the concatenation of `segments`.
+pub fn trailer(algorithm: Algorithm, segments: &[&[u8]]) -> Vec<u8> {
+    let mut trailer = Vec::with_capacity(TRAILER_LEN);
+    trailer.extend_from_slice(TRAILER_MAGIC);
+    trailer.push(algorithm.id());
+    trailer.extend_from_slice(&algorithm.checksum(segments).to_le_bytes());
+    trailer
+}
+
+/// Appends the trailer of `algorithm` to a payload, the payload is returned as is without one.
+pub fn seal(mut payload: Vec<u8>, algorithm: Option<Algorithm>) -> Vec<u8> {
+    if let Some(algorithm) = algorithm {
+        let trailer = trailer(algorithm, &[payload.as_slice()]);
+        payload.extend_from_slice(&trailer);
+    }
+    payload
+}
+
+/// Strips and verifies the trailer of a received payload, whose sender announced it through
+/// [`crate::framing::CHECKSUM`]. A payload without a valid trailer does not match.
+pub fn verify(mut data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if data.len() < TRAILER_LEN {
+        return Err(mismatch_error());
+    }
+    let split = data.len() - TRAILER_LEN;
+    let algorithm = match (&data[split..split + 4] == TRAILER_MAGIC, Algorithm::from_id(data[split + 4])) {
+        (true, Some(algorithm)) => algorithm,
+        _ => return Err(mismatch_error()),
+    };
+    let expected = u64::from_le_bytes(data[split + 5..].try_into().unwrap());
+    data.truncate(split);
+    if algorithm.checksum(&[data.as_slice()]) != expected {
+        return Err(mismatch_error());
+    }
+    Ok(data)
+}
@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::sync::Mutex;
+use lazy_static::lazy_static;
+use log::info;
+use oci_spec::runtime::Spec;
+use serde::Deserialize;
+use crate::utils::oci_utils;
+
+/// Annotation selecting the codec preferred by a sender on the network transport.
+pub const COMPRESSION_ANNOTATION: &str = "transfer.compression";
+
+/// Annotation overriding the payload size below which payloads are sent uncompressed.
+pub const THRESHOLD_ANNOTATION: &str = "transfer.compression.threshold";
+
+pub const DEFAULT_THRESHOLD: usize = 16 * 1024;
+
+/// Prefix of the message a receiver sends after connecting, advertising the codecs it accepts.
+pub const HELLO_MAGIC: &[u8; 4] = b"RRHI";
+
+/// Prefix of a compressed payload: magic, codec (u8), original length (u64, little-endian).
+pub const FRAME_MAGIC: &[u8; 4] = b"RRCZ";
+
+const FRAME_HEADER_LEN: usize = 13;
+
+/// Largest original length of a frame a receiver decompresses. Frames announcing a longer payload
+/// are rejected before it is allocated.
+pub const MAX_DECOMPRESSED_LEN: usize = 1024 * 1024 * 1024;
+
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Codec {
+    Lz4,
+    Zstd,
+}
+
+/// Codecs this shim decompresses.
+pub const SUPPORTED: [Codec; 2] = [Codec::Lz4, Codec::Zstd];
+
+impl Codec {
+    pub fn parse(name: &str) -> Option<Codec> {
+        match name {
+            "lz4" => Some(Codec::Lz4),
+            "zstd" => Some(Codec::Zstd),
+            _ => None,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self {
+            Codec::Lz4 => 1,
+            Codec::Zstd => 2,
+        }
+    }
+
+    fn from_id(id: u8) -> Option<Codec> {
+        SUPPORTED.iter().copied().find(|codec| codec.id() == id)
+    }
+}
+
+/// Returns the hello of a receiver accepting `codecs`: magic followed by a bit mask of the codecs.
+pub fn hello(codecs: &[Codec]) -> [u8; 5] {
+    let mut hello = [0u8; 5];
+    hello[..4].copy_from_slice(HELLO_MAGIC);
+    hello[4] = codecs.iter().fold(0, |mask, codec| mask | codec.id());
+    hello
+}
+
+/// Returns the codecs advertised by a hello, `None` if `bytes` is not one.
+pub fn parse_hello(bytes: &[u8]) -> Option<Vec<Codec>> {
+    if bytes.len() < 5 || &bytes[..4] != HELLO_MAGIC {
+        return None;
+    }
+    Some(SUPPORTED.iter().copied().filter(|codec| bytes[4] & codec.id() != 0).collect())
+}
+
+/// Compression a sender applies on the network transport.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompressionPolicy {
+    /// Preferred codec, `None` disables compression.
+    pub codec: Option<Codec>,
+    /// Payloads smaller than this are sent uncompressed over the splice path.
+    pub threshold: usize,
+}
+
+impl Default for CompressionPolicy {
+    fn default() -> Self {
+        CompressionPolicy { codec: None, threshold: DEFAULT_THRESHOLD }
+    }
+}
+
+impl CompressionPolicy {
+    /// Reads the policy from the `transfer.compression` and `transfer.compression.threshold`
+    /// annotations of the sender.
+    pub fn from_spec(spec: &Spec) -> CompressionPolicy {
+        let threshold = oci_utils::get_wasm_annotations(spec, THRESHOLD_ANNOTATION)
+            .parse()
+            .unwrap_or(DEFAULT_THRESHOLD);
+        CompressionPolicy {
+            codec: Codec::parse(&oci_utils::get_wasm_annotations(spec, COMPRESSION_ANNOTATION)),
+            threshold,
+        }
+    }
+
+    /// Returns the policy for a target preferring `codec`, the sender's preference otherwise.
+    pub fn with_codec(self, codec: Option<Codec>) -> CompressionPolicy {
+        CompressionPolicy { codec: codec.or(self.codec), ..self }
+    }
+
+    /// Whether a payload of `len` bytes is worth compressing.
+    pub fn applies(&self, len: usize) -> bool {
+        self.codec.is_some() && len >= self.threshold
+    }
+
+    /// Negotiates the codec with the codecs `accepted` by the receiver.
+    pub fn negotiate(&self, accepted: &[Codec]) -> Option<Codec> {
+        self.codec.filter(|codec| accepted.contains(codec))
+    }
+}
+
+/// Achieved compression of a codec since the shim started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    pub transfers: u64,
+    pub original_bytes: u64,
+    pub compressed_bytes: u64,
+}
+
+impl CompressionStats {
+    /// Original size divided by compressed size.
+    pub fn ratio(&self) -> f64 {
+        if self.compressed_bytes == 0 {
+            return 1.0;
+        }
+        self.original_bytes as f64 / self.compressed_bytes as f64
+    }
+}
+
+lazy_static! {
+    static ref STATS: Mutex<HashMap<Codec, CompressionStats>> = Mutex::new(HashMap::new());
+}
+
+pub fn stats(codec: Codec) -> CompressionStats {
+    STATS.lock().unwrap().get(&codec).copied().unwrap_or_default()
+}
+
+/// Compresses the concatenation of `segments` into a frame.
+pub fn compress(codec: Codec, segments: &[&[u8]]) -> io::Result<Vec<u8>> {
+    let payload = segments.concat();
+    let compressed = match codec {
+        Codec::Lz4 => lz4_flex::block::compress(&payload),
+        Codec::Zstd => zstd::bulk::compress(&payload, ZSTD_LEVEL)?,
+    };
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + compressed.len());
+    frame.extend_from_slice(FRAME_MAGIC);
+    frame.push(codec.id());
+    frame.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    frame.extend_from_slice(&compressed);
+
+    let mut stats = STATS.lock().unwrap();
+    let codec_stats = stats.entry(codec).or_default();
+    codec_stats.transfers += 1;
+    codec_stats.original_bytes += payload.len() as u64;
+    codec_stats.compressed_bytes += frame.len() as u64;
+    info!(
+        "compressed {} bytes to {} with {:?}, ratio {:.2} over {} transfers",
+        payload.len(), frame.len(), codec, codec_stats.ratio(), codec_stats.transfers
+    );
+    Ok(frame)
+}
+
+/// Decompresses a received frame, whose sender announced it through
+/// [`crate::framing::COMPRESSED`].
+pub fn decompress(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if data.len() < FRAME_HEADER_LEN || &data[..4] != FRAME_MAGIC {
+        return Err(io::Error::new(ErrorKind::InvalidData, "invalid compressed frame"));
+    }
+    let codec = Codec::from_id(data[4])
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("unknown codec {}", data[4])))?;
+    let len = u64::from_le_bytes(data[5..FRAME_HEADER_LEN].try_into().unwrap());
+    if len > MAX_DECOMPRESSED_LEN as u64 {
+        return Err(io::Error::new(ErrorKind::InvalidData, format!("compressed frame of {} bytes exceeds the limit", len)));
+    }
+    let len = len as usize;
+    let compressed = &data[FRAME_HEADER_LEN..];
+    let payload = match codec {
+        Codec::Lz4 => lz4_flex::block::decompress(compressed, len)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, e.to_string()))?,
+        Codec::Zstd => zstd::bulk::decompress(compressed, len)?,
+    };
+    if payload.len() != len {
+        return Err(io::Error::new(ErrorKind::InvalidData, "decompressed payload has the wrong length"));
+    }
+    Ok(payload)
+}
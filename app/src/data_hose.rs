@@ -1,118 +1,930 @@
-use std::path::Path;
 use log::info;
 use oci_spec::runtime::Spec;
 use walkdir::WalkDir;
-use wasmedge_sdk::{host_function, Caller, WasmValue,Vm, Instance, params};
+use wasmedge_sdk::{host_function, Caller, ImportObjectBuilder, Memory, WasmValue,Vm, Instance, params, ValType, WasmEdgeResult};
 use wasmedge_sdk::error::HostFuncError;
-use crate::remote_transfer::{net_transfer_bind};
-use crate::utils::{oci_utils};
-use crate::{runtime};
+use rustls::ServerConfig;
+use crate::remote_transfer::{net_transfer_serve, MAX_IOVECS};
+use crate::utils::{oci_utils, snapshot_utils, socket_utils};
+use crate::workflow::{FunctionSpec, Transport, WorkflowNode};
+use crate::{advertise, checksum, framing, mux, payload_cache, payload_ref, pod, registry, routing, stream, transfers};
+use crate::balancer::{self, Replica, Selection};
+use crate::host_context::HostContext;
+use crate::compression::CompressionPolicy;
+use crate::framing::Flags;
+use crate::payload_cache::Lease;
+use crate::registry::Heartbeat;
+use crate::payload_ref::PayloadRef;
+use crate::error::TransferError;
+use std::net::{SocketAddr, TcpListener};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 extern crate libc;
 
-pub static mut OCI_SPEC:Option<Spec> = None;
-pub static mut BUNDLE_PATH:Option<String> = None;
+pub fn read_memory_host(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    transfer_to_target(context, &caller, &input)
+}
 
-#[host_function]
-pub fn read_memory_host(caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
-    let mut mem = caller.memory(0).unwrap();
+/// Returns the context of the calling module: `context` for the import module of a container
+/// instance, or the context of the calling module of the pod-shared VM, whose import module is
+/// shared by all of its modules and passes `None`.
+/// Killed instances get an error, which traps the guest.
+pub fn caller_context(caller: &Caller, context: Option<&Arc<HostContext>>) -> Result<Arc<HostContext>, TransferError> {
+    let context = match context {
+        Some(context) => context.clone(),
+        None => {
+            let sender = caller.instance().and_then(|instance| instance.name()).unwrap_or_default();
+            pod::get_module(&sender).map(|module| module.context).ok_or(TransferError::ModuleNotFound(sender))?
+        }
+    };
+    if context.shutdown.is_killed() {
+        return Err(TransferError::Killed(context.config.function_name.clone()));
+    }
+    Ok(context)
+}
+
+/// Sends the payload at `(ptr, len)` of the caller's memory to the target function and writes
+/// the response back over the payload. A failed transfer, or a payload outside of the caller's
+/// memory, returns the status of its error.
+pub fn transfer_to_target(context: &HostContext, caller: &Caller, input: &[WasmValue]) -> Result<Vec<WasmValue>, HostFuncError> {
     let arg1_ptr = input[0].to_i32() as u32;
-    let arg1_len = input[1].to_i32() as u32;
+    let arg1_len = input[1].to_i32().max(0) as u32;
+    let result = caller
+        .memory(0)
+        .ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))
+        .and_then(|mut mem| {
+            let payload = mem.read(arg1_ptr, arg1_len)?;
+            let target_function_result = send_payload(context, payload)?;
 
-    let payload = mem.read(arg1_ptr, arg1_len).expect("fail to get string");
-    let mut target_function_result = String::new();
+            // Write response back into Wasm VM
+            let bytes = target_function_result.as_bytes();
+            mem.write(bytes, arg1_ptr)?;
+            Ok(bytes.len() as i32)
+        });
+    Ok(guest_status(result))
+}
 
-    unsafe {
-        let function_metadata = find_function_metadata(BUNDLE_PATH.as_deref().unwrap_or(""));
+/// Sends a payload to the target function found among the bundles, over the Unix socket, or the
+/// network transfer if no socket is reachable.
+pub fn send_payload(context: &HostContext, payload: Vec<u8>) -> Result<String, TransferError> {
+    let response = send_segments(context, &[payload.as_slice()])?;
+    Ok(String::from_utf8_lossy(&response).to_string())
+}
 
-        let (socket_path, function_name, function_address) = match function_metadata {
-            Some((socket, name, address)) => (socket, name, address),
-            None => {
-                log::warn!("No matching function metadata found in annotations.");
-                return Err(HostFuncError::User("Function not found.".to_string().parse().unwrap()));
-            }
-        };
+/// Sends the concatenation of `segments` to the target function, gathered by `writev` on the
+/// Unix socket or by a multi-iovec `vmsplice` on the network transfer.
+pub fn send_segments(context: &HostContext, segments: &[&[u8]]) -> Result<Vec<u8>, TransferError> {
+    // the target may be a module of the same VM, served by its listener
+    let _suspended = pod::suspend();
+    let target = discover_target(context)?;
+    let len = segments.iter().map(|segment| segment.len()).sum();
+    let start = Instant::now();
+    let result = deliver_segments(context, segments, &target.socket_path(), target.address.clone());
+    routing::record(&target.function_name, len, start.elapsed(), result.is_ok());
+    target.selection.report(result.is_ok());
+    result
+}
 
-        log::info!(
-            "Function Metadata - Name: {}, Address: {}, Socket: {}",
-            function_name, function_address, socket_path
-        );
+fn deliver_segments(context: &HostContext, segments: &[&[u8]], socket_path: &str, function_address: String) -> Result<Vec<u8>, TransferError> {
+    let trailer = checksum_trailer(context, segments);
+    let segments = with_trailer(segments, &trailer);
+    let flags = trailer_flags(&trailer);
 
-        // Try using Unix Socket first
-        if let Ok(result) = runtime::connect_unix_socket(payload.clone(), socket_path) {
-            target_function_result = result;
-        } else {
-            // If socket connection fails, fallback to the listener
-            if let Err(err) = net_transfer_bind(payload.clone(),function_address) {
-                log::error!("Listener failed: {:?}", err);
-                return Err(HostFuncError::User("Communication failure.".to_string().parse().unwrap()));
+    // Try using Unix Socket first, over the pooled multiplexed connection
+    match mux::request_flagged(socket_path, flags, &segments) {
+        Ok(result) => return Ok(result),
+        Err(err) if checksum::is_mismatch(&err) => return Err(TransferError::ChecksumMismatch),
+        // the target may have run a request it received, it is not sent a second time
+        Err(err) if !mux::is_unsent(&err) => return Err(TransferError::Communication(err.to_string())),
+        Err(_) => {}
+    }
+    // If the socket cannot be reached, fallback to the listener
+    if let Err(err) = serve_network(context, flags, &segments, function_address, context.config.compression, context.tls_config()?) {
+        log::error!("Listener failed: {:?}", err);
+        if checksum::is_mismatch(&err) {
+            return Err(TransferError::ChecksumMismatch);
+        }
+        return Err(TransferError::Communication(err.to_string()));
+    }
+    Ok(Vec::new())
+}
+
+/// Returns the checksum trailer for a payload if the sender enabled checksums through the
+/// `transfer.checksum` annotation.
+fn checksum_trailer(context: &HostContext, segments: &[&[u8]]) -> Option<Vec<u8>> {
+    Some(checksum::trailer(context.config.checksum?, segments))
+}
+
+/// Returns the flags announcing the checksum trailer, if any, to the receiver.
+fn trailer_flags(trailer: &Option<Vec<u8>>) -> Flags {
+    framing::flag_if(trailer.is_some(), framing::CHECKSUM)
+}
+
+/// Serves a payload over the network transport on `address`. A sender with port `0` or a port
+/// range binds a port of its own and advertises it next to its bundle while the transfer lasts.
+/// The address actually bound is published to the registry for receivers on other nodes.
+fn serve_network(context: &HostContext, flags: Flags, segments: &[&[u8]], address: String, policy: CompressionPolicy, tls: Option<Arc<ServerConfig>>) -> std::io::Result<()> {
+    let range = context.config.port_range;
+    let dynamic = advertise::is_dynamic(&address, range);
+    let listener = match dynamic {
+        true => advertise::bind(&address, range)?,
+        false => TcpListener::bind(&address)?,
+    };
+    let bound = listener.local_addr()?;
+    if dynamic {
+        advertise::advertise(&context.bundle_path, bound)?;
+    }
+    let published = publish_bound(context, bound);
+    let served = net_transfer_serve(listener, flags, segments, policy, tls);
+    drop(published);
+    if dynamic {
+        advertise::withdraw(&context.bundle_path);
+    }
+    served
+}
+
+/// Publishes the address a sender is bound to in the registry, if one is configured, until the
+/// returned heartbeat is dropped.
+fn publish_bound(context: &HostContext, bound: SocketAddr) -> Option<Heartbeat> {
+    let registry = context.registry.as_ref()?;
+    let instance = registry::instance_name(&context.bundle_path);
+    let address = registry::reachable_address(bound);
+    info!("publishing {} of {} as {}", address, context.config.function_name, instance);
+    Some(registry.heartbeat(&context.config.function_name, &instance, &address))
+}
+
+/// Finds the bundle of a container running `module` within the discovery scope of this
+/// container, an empty path if there is none. Transfers to the module pick among its containers
+/// through [`select_container`] instead.
+pub fn find_container_path(context: &HostContext, module: &str) -> String {
+    local_replicas(context, module).into_iter().next().unwrap_or_default()
+}
+
+/// Selects the container running `module` a transfer is sent to by the sender's balancing
+/// strategy, `None` if there is none. The outcome of the transfer is reported to the selection.
+/// The container itself is never selected.
+fn select_container(context: &HostContext, module: &str) -> Option<Selection> {
+    let replicas: Vec<Replica> = local_replicas(context, module)
+        .into_iter()
+        .filter(|container_path| *container_path != context.bundle_path)
+        .map(Replica::Local)
+        .collect();
+    balancer::select(module, &replicas, context.config.balancing)
+}
+
+/// Returns the bundles of all containers running `module` within the discovery scope.
+fn local_replicas(context: &HostContext, module: &str) -> Vec<String> {
+    let roots = context.scope.roots(&context.state_root);
+    snapshot_utils::find_container_paths_scoped(&roots, module, &|spec| context.scope.admits(spec))
+}
+
+/// Returns the replicas of a workflow target its transport can reach, local containers first.
+fn target_replicas(context: &HostContext, target: &FunctionSpec) -> Vec<Replica> {
+    let mut replicas = Vec::new();
+    if target.transport != Transport::Network {
+        replicas.extend(local_replicas(context, &target.module_name()).into_iter().map(Replica::Local));
+    }
+    if target.transport != Transport::Unix {
+        replicas.extend(target.address.iter().chain(target.replicas.iter()).cloned().map(Replica::Remote));
+    }
+    replicas
+}
+
+/// Returns the addresses the instances of `function` published in the registry while serving a
+/// transfer, none if no registry is configured or it cannot be reached.
+pub fn registered_addresses(context: &HostContext, function: &str) -> Vec<String> {
+    let registry = match context.registry.as_ref() {
+        Some(registry) => registry,
+        None => return Vec::new(),
+    };
+    registry.resolve(function).unwrap_or_else(|err| {
+        log::warn!("Could not resolve {} in the registry: {}", function, err);
+        Vec::new()
+    })
+}
+
+/// Checks a transfer against the policies of the node and of this container.
+fn authorize_transfer(context: &HostContext, source: &str, target: &str) -> Result<(), TransferError> {
+    context.config.authorize(source, target)
+}
+
+/// Checks a transfer against the policies of the sender and, if the target module is hosted by
+/// this process, of the target, which receives it without a listener of its own.
+fn authorize_local_transfer(context: &HostContext, source: &str, target: &str, target_module: &str) -> Result<(), TransferError> {
+    authorize_transfer(context, source, target)?;
+    match pod::get_module(target_module) {
+        Some(module) => module.context.config.authorize(source, target),
+        None => Ok(()),
+    }
+}
+
+fn with_trailer<'a>(segments: &[&'a [u8]], trailer: &'a Option<Vec<u8>>) -> Vec<&'a [u8]> {
+    let mut segments = segments.to_vec();
+    if let Some(trailer) = trailer {
+        segments.push(trailer);
+    }
+    segments
+}
+
+/// The target function of a transfer, with the replica selected to receive it.
+struct Target {
+    function_name: String,
+    /// Address the function is served on over the network transport.
+    address: String,
+    /// The outcome of the transfer is reported to the selection.
+    selection: Selection,
+}
+
+impl Target {
+    /// Returns the socket of the selected replica, empty for a replica on another node.
+    fn socket_path(&self) -> String {
+        self.selection.replica().socket_path().unwrap_or_default()
+    }
+}
+
+/// Finds the target function among the bundles, checks that this container may send to it and
+/// selects the replica receiving the transfer by the sender's balancing strategy. The container
+/// itself is never selected.
+fn discover_target(context: &HostContext) -> Result<Target, TransferError> {
+    let roots = context.scope.roots(&context.state_root);
+    let admits = |spec: &Spec| context.scope.admits(spec);
+    let own_socket = format!("{}.sock", context.bundle_path);
+    let mut found = find_all_function_metadata_scoped(&roots, &admits);
+    found.retain(|(socket, _, _)| *socket != own_socket);
+
+    // a target split between versions resolves to one of them per transfer, rolled among the
+    // versions that have live replicas
+    let version = match context.config.routes.as_ref() {
+        Some(routes) => {
+            let live = |version: &str| found.iter().any(|(_, name, _)| name == version);
+            let version = match routes.choose_among(live) {
+                Some(version) => version.to_string(),
+                None => {
+                    let versions: Vec<&str> = routes.routes.iter().map(|route| route.function.as_str()).collect();
+                    log::warn!("No replica of any routed version found.");
+                    return Err(TransferError::TargetNotFound(versions.join(",")));
+                }
+            };
+            found.retain(|(_, name, _)| *name == version);
+            Some(version)
+        }
+        None => None,
+    };
+
+    // containers running the same function as the first one found are its replicas
+    let function_name = match found.first() {
+        Some((_, name, _)) => name.clone(),
+        None => {
+            log::warn!("No matching function metadata found in annotations.");
+            return Err(TransferError::TargetNotFound(version.unwrap_or_default()));
+        }
+    };
+    let candidates: Vec<(Replica, String)> = found
+        .into_iter()
+        .filter(|(_, name, _)| *name == function_name)
+        .map(|(socket, _, address)| (Replica::Local(socket.trim_end_matches(".sock").to_string()), address))
+        .collect();
+    authorize_transfer(context, &context.config.function_name, &function_name)?;
+
+    let replicas: Vec<Replica> = candidates.iter().map(|(replica, _)| replica.clone()).collect();
+    let selection = balancer::select(&function_name, &replicas, context.config.balancing)
+        .ok_or_else(|| TransferError::TargetNotFound(function_name.clone()))?;
+    let address = candidates
+        .into_iter()
+        .find(|(replica, _)| replica == selection.replica())
+        .map(|(_, address)| address)
+        .unwrap_or_default();
+
+    log::info!(
+        "Function Metadata - Name: {}, Address: {}, Replica: {:?}",
+        function_name, address, selection.replica()
+    );
+    Ok(Target { function_name, address, selection })
+}
+
+/// Reads `count` iovecs of two little-endian u32 `(ptr, len)` at `iovs_ptr` of guest memory, at
+/// most [`MAX_IOVECS`].
+fn read_iovecs(mem: &Memory, iovs_ptr: u32, count: i32) -> Result<Vec<(u32, u32)>, TransferError> {
+    let size = u32::try_from(count)
+        .ok()
+        .filter(|count| *count as usize <= MAX_IOVECS)
+        .and_then(|count| count.checked_mul(8))
+        .ok_or_else(|| TransferError::InvalidArgument(format!("{} iovecs", count)))?;
+    let raw = mem.read(iovs_ptr, size)?;
+    Ok(raw
+        .chunks_exact(8)
+        .map(|iov| (
+            u32::from_le_bytes([iov[0], iov[1], iov[2], iov[3]]),
+            u32::from_le_bytes([iov[4], iov[5], iov[6], iov[7]]),
+        ))
+        .collect())
+}
+
+/// Sends the buffers of several iovecs as one payload and scatters the response over several
+/// guest buffers:
+///
+/// `send_vectored(iovs_ptr, iovs_count, recv_iovs_ptr, recv_iovs_count) -> len`
+///
+/// Buffers are referenced in place rather than concatenated. The response fills the receive
+/// buffers in order, bytes beyond their total capacity are dropped; the returned length is the
+/// number of bytes written. Either side takes at most [`MAX_IOVECS`] iovecs.
+pub fn send_vectored(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    Ok(guest_status(scatter_gather(context, &mut mem, &input)))
+}
+
+fn scatter_gather(context: &HostContext, mem: &mut Memory, input: &[WasmValue]) -> Result<i32, TransferError> {
+    let send_iovecs = read_iovecs(mem, input[0].to_i32() as u32, input[1].to_i32())?;
+    let recv_iovecs = read_iovecs(mem, input[2].to_i32() as u32, input[3].to_i32())?;
+
+    let response = {
+        let mut segments: Vec<&[u8]> = Vec::with_capacity(send_iovecs.len());
+        for (ptr, len) in send_iovecs.into_iter().filter(|(_, len)| *len > 0) {
+            let data = mem.data_pointer(ptr, len)?;
+            // the guest is suspended in this call, its memory stays valid and unchanged meanwhile
+            segments.push(unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) });
+        }
+        send_segments(context, &segments)?
+    };
+
+    // Scatter the response into the receive buffers
+    let mut written = 0;
+    for (ptr, len) in recv_iovecs {
+        if written == response.len() {
+            break;
+        }
+        let end = response.len().min(written + len as usize);
+        mem.write(&response[written..end], ptr)?;
+        written = end;
+    }
+    Ok(written as i32)
+}
+
+/// Adds all transfer host functions besides `read_memory_host` to an import module. `context` is
+/// the context of the container instance, the pod-shared VM passes `None` and each call acts on
+/// the context of the calling module.
+pub fn with_transfer_functions(builder: ImportObjectBuilder, context: Option<Arc<HostContext>>) -> WasmEdgeResult<ImportObjectBuilder> {
+    let builder = with_async_transfers(builder, context.clone())?;
+    with_payload_refs(with_streams(builder, context.clone())?, context)
+}
+
+/// Adds the non-blocking and scatter-gather transfer host functions to an import module:
+///
+/// - `send_async(ptr, len) -> handle` copies the payload and sends it on a host worker thread.
+/// - `poll(handle) -> status` returns `-1` while in flight, the response length once completed.
+/// - `wait_any(handles_ptr, count) -> index` blocks until one of the `count` u32 handles at
+///   `handles_ptr` has completed, `-1` if `count` is `0`.
+/// - `read_result(handle, ptr, len) -> status` copies up to `len` bytes of the response to `ptr`
+///   and releases the handle.
+/// - `send_vectored(iovs_ptr, count, recv_iovs_ptr, recv_count) -> len`, see [`send_vectored`].
+///
+/// Failed transfers report `-(code + 1)` with the code of the [`TransferError`]. Payloads sent
+/// asynchronously are always delivered outside the VM, over sockets or the network; a module of
+/// the pod-shared VM releases the VM while it waits for them. Handles
+/// belong to the instance that submitted them, completions not read within
+/// [`transfers::COMPLETION_TTL`] are dropped.
+pub fn with_async_transfers(builder: ImportObjectBuilder, context: Option<Arc<HostContext>>) -> WasmEdgeResult<ImportObjectBuilder> {
+    let (polled, waited, read, vectored) = (context.clone(), context.clone(), context.clone(), context.clone());
+    builder
+        .with_func::<(i32, i32), i32>("send_async", move |frame, input| {
+            send_async(&Caller::new(frame), &input, context.as_ref())
+        })?
+        .with_func::<i32, i32>("poll", move |frame, input| {
+            poll_transfer(&caller_context(&Caller::new(frame), polled.as_ref())?, &input)
+        })?
+        .with_func::<(i32, i32), i32>("wait_any", move |frame, input| {
+            let caller = Caller::new(frame);
+            wait_any(&caller_context(&caller, waited.as_ref())?, caller, input)
+        })?
+        .with_func::<(i32, i32, i32), i32>("read_result", move |frame, input| {
+            let caller = Caller::new(frame);
+            read_result(&caller_context(&caller, read.as_ref())?, caller, input)
+        })?
+        .with_func::<(i32, i32, i32, i32), i32>("send_vectored", move |frame, input| {
+            let caller = Caller::new(frame);
+            send_vectored(&caller_context(&caller, vectored.as_ref())?, caller, input)
+        })
+}
+
+pub fn send_async(caller: &Caller, input: &[WasmValue], context: Option<&Arc<HostContext>>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let address = input[0].to_i32() as u32;
+    let len = input[1].to_i32() as u32;
+    let payload = mem.read(address, len).map_err(TransferError::from)?;
+
+    // modules of the pod-shared VM carry their own wiring
+    let context = caller_context(caller, context)?;
+    let transfers = context.transfers.clone();
+    let handle = transfers.submit(move || match context.node.as_ref().filter(|node| node.fan_out() > 0) {
+        Some(node) => {
+            let targets: Vec<&FunctionSpec> = node.targets.iter().collect();
+            Ok(send_to_workflow_targets(&context, &node.function.name, payload, &targets)?.into_bytes())
+        }
+        None => send_payload(&context, payload).map(String::into_bytes),
+    });
+    Ok(vec![WasmValue::from_i32(handle as i32)])
+}
+
+pub fn poll_transfer(context: &HostContext, input: &[WasmValue]) -> Result<Vec<WasmValue>, HostFuncError> {
+    let status = match context.transfers.poll(input[0].to_i32() as u32) {
+        None => {
+            // a guest polling in a loop lets the target run if it is a module of the same VM
+            let _suspended = pod::suspend();
+            std::thread::yield_now();
+            transfers::PENDING
+        }
+        Some(Ok(response)) => response.len() as i32,
+        Some(Err(code)) => transfers::error_status(code),
+    };
+    Ok(vec![WasmValue::from_i32(status)])
+}
+
+pub fn wait_any(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let handles_ptr = input[0].to_i32() as u32;
+    let count = input[1].to_i32();
+    let result = u32::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(4))
+        .ok_or_else(|| TransferError::InvalidArgument(format!("{} handles", count)))
+        .and_then(|size| Ok(mem.read(handles_ptr, size)?))
+        .map(|raw| {
+            let handles: Vec<u32> = raw
+                .chunks_exact(4)
+                .map(|bytes| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+                .collect();
+            // the transfers may be served by modules of the same VM
+            let _suspended = pod::suspend();
+            context.transfers.wait_any(&handles).map_or(-1, |index| index as i32)
+        });
+    Ok(guest_status(result))
+}
+
+pub fn read_result(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let handle = input[0].to_i32() as u32;
+    let ptr = input[1].to_i32() as u32;
+    let capacity = input[2].to_i32().max(0) as usize;
+    let status = match context.transfers.take(handle) {
+        None => transfers::PENDING,
+        Some(Ok(response)) => {
+            let len = response.len().min(capacity);
+            mem.write(&response[..len], ptr).map_err(TransferError::from)?;
+            len as i32
+        }
+        Some(Err(code)) => transfers::error_status(code),
+    };
+    Ok(vec![WasmValue::from_i32(status)])
+}
+
+/// Adds the stream host functions to an import module, for payloads larger than guest memory:
+///
+/// - `open_stream() -> stream` opens a stream to the target function.
+/// - `write_chunk(stream, ptr, len) -> status` sends a chunk, blocking while the receiver lags
+///   behind.
+/// - `read_chunk(stream, ptr, len) -> read` reads up to `len` bytes of an incoming stream, `0`
+///   once the stream has ended.
+/// - `close(stream) -> result` ends the stream and returns the result of the receiver's
+///   `on_stream` export, which must fit in an i32.
+///
+/// Failures report `-(code + 1)` with the code of the [`TransferError`]. Stream ids belong to the
+/// instance that opened or received the stream.
+pub fn with_streams(builder: ImportObjectBuilder, context: Option<Arc<HostContext>>) -> WasmEdgeResult<ImportObjectBuilder> {
+    let (written, read, closed) = (context.clone(), context.clone(), context.clone());
+    builder
+        .with_func::<(), i32>("open_stream", move |frame, _input| {
+            Ok(guest_status(caller_context(&Caller::new(frame), context.as_ref()).and_then(|context| open_stream(&context))))
+        })?
+        .with_func::<(i32, i32, i32), i32>("write_chunk", move |frame, input| {
+            let caller = Caller::new(frame);
+            write_chunk(&caller_context(&caller, written.as_ref())?, caller, input)
+        })?
+        .with_func::<(i32, i32, i32), i32>("read_chunk", move |frame, input| {
+            let caller = Caller::new(frame);
+            read_chunk(&caller_context(&caller, read.as_ref())?, caller, input)
+        })?
+        .with_func::<i32, i32>("close", move |frame, input| {
+            close_stream(&caller_context(&Caller::new(frame), closed.as_ref())?, &input)
+        })
+}
+
+/// Returns the result of a host function to the guest, failures as `-(code + 1)`.
+fn guest_status(result: Result<i32, TransferError>) -> Vec<WasmValue> {
+    let status = result.unwrap_or_else(|err| {
+        log::error!("Transfer failed: {}", err);
+        transfers::error_status(err.code())
+    });
+    vec![WasmValue::from_i32(status)]
+}
+
+/// Opens a stream to the target function and returns its id.
+pub fn open_stream(context: &HostContext) -> Result<i32, TransferError> {
+    let _suspended = pod::suspend();
+    let target = discover_target(context)?;
+    let opened = context.streams.open(&target.socket_path());
+    target.selection.report(opened.is_ok());
+    Ok(opened? as i32)
+}
+
+pub fn write_chunk(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let id = input[0].to_i32() as u32;
+    let ptr = input[1].to_i32() as u32;
+    let len = input[2].to_i32().max(0) as u32;
+    if len == 0 {
+        return Ok(guest_status(Ok(0)));
+    }
+    let data = mem.data_pointer(ptr, len).map_err(TransferError::from)?;
+    // the chunk is written straight from guest memory, the guest is suspended meanwhile
+    let chunk = unsafe { std::slice::from_raw_parts(data as *const u8, len as usize) };
+    let _suspended = pod::suspend();
+    Ok(guest_status(context.streams.write(id, chunk).map(|_| 0).map_err(TransferError::from)))
+}
+
+pub fn read_chunk(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let id = input[0].to_i32() as u32;
+    let ptr = input[1].to_i32() as u32;
+    let len = input[2].to_i32().max(0) as u32;
+    if len == 0 {
+        return Ok(guest_status(Ok(0)));
+    }
+    let data = mem.data_pointer_mut(ptr, len).map_err(TransferError::from)?;
+    let buffer = unsafe { std::slice::from_raw_parts_mut(data as *mut u8, len as usize) };
+    let _suspended = pod::suspend();
+    Ok(guest_status(context.streams.read(id, buffer).map(|read| read as i32).map_err(TransferError::from)))
+}
+
+pub fn close_stream(context: &HostContext, input: &[WasmValue]) -> Result<Vec<WasmValue>, HostFuncError> {
+    let id = input[0].to_i32() as u32;
+    let _suspended = pod::suspend();
+    Ok(guest_status(context.streams.close(id).map_err(TransferError::from).and_then(|response| {
+        // the receiver answers with the i64 result of its stream entrypoint
+        let mut result = [0u8; 8];
+        let len = response.len().min(result.len());
+        result[..len].copy_from_slice(&response[..len]);
+        i32::try_from(i64::from_le_bytes(result))
+            .map_err(|_| TransferError::InvalidResult(format!("stream {}", id), stream::STREAM_ENTRYPOINT.to_string()))
+    })))
+}
+
+/// Adds the payload reference host functions to an import module, so that receivers fetch only
+/// the parts of a payload they read:
+///
+/// - `publish_payload(ptr, len, ttl_ms, ref_ptr, ref_len) -> len` retains the payload in the shim
+///   for `ttl_ms` (the default TTL if `0`) and writes its reference to `ref_ptr`. The reference is
+///   sent to receivers as a regular payload.
+/// - `payload_len(ref_ptr, ref_len) -> len` returns the length of a referenced payload.
+/// - `fetch_range(ref_ptr, ref_len, offset, ptr, len) -> read` copies up to `len` bytes at
+///   `offset` of a referenced payload to `ptr`.
+///
+/// Failures report `-(code + 1)` with the code of the [`TransferError`].
+pub fn with_payload_refs(builder: ImportObjectBuilder, context: Option<Arc<HostContext>>) -> WasmEdgeResult<ImportObjectBuilder> {
+    builder
+        .with_func::<(i32, i32, i32, i32, i32), i32>("publish_payload", move |frame, input| {
+            let caller = Caller::new(frame);
+            publish_payload(&caller_context(&caller, context.as_ref())?, caller, input)
+        })?
+        .with_func::<(i32, i32), i64>("payload_len", payload_len)?
+        .with_func::<(i32, i32, i64, i32, i32), i32>("fetch_range", fetch_range)
+}
+
+fn read_payload_ref(mem: &Memory, ptr: i32, len: i32) -> Result<PayloadRef, TransferError> {
+    let bytes = mem.read(ptr as u32, len.max(0) as u32)?;
+    PayloadRef::decode(&bytes).ok_or(TransferError::InvalidReference)
+}
+
+pub fn publish_payload(context: &HostContext, caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let payload = mem.read(input[0].to_i32() as u32, input[1].to_i32().max(0) as u32).map_err(TransferError::from)?;
+    let ttl = match input[2].to_i32() {
+        ttl_ms if ttl_ms > 0 => Duration::from_millis(ttl_ms as u64),
+        _ => payload_ref::DEFAULT_TTL,
+    };
+    let ref_ptr = input[3].to_i32() as u32;
+    let ref_capacity = input[4].to_i32().max(0) as usize;
+
+    let result = payload_ref::publish(payload, ttl, context).and_then(|reference| {
+        let encoded = reference.encode();
+        if encoded.len() > ref_capacity {
+            payload_ref::release(reference.id);
+            return Err(TransferError::InvalidReference);
+        }
+        mem.write(&encoded, ref_ptr)?;
+        Ok(encoded.len() as i32)
+    });
+    Ok(guest_status(result))
+}
+
+#[host_function]
+pub fn payload_len(caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let len = match read_payload_ref(&mem, input[0].to_i32(), input[1].to_i32()) {
+        Ok(reference) => reference.len as i64,
+        Err(err) => transfers::error_status(err.code()) as i64,
+    };
+    Ok(vec![WasmValue::from_i64(len)])
+}
+
+#[host_function]
+pub fn fetch_range(caller: Caller, input: Vec<WasmValue>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(String::new(), "memory".to_string()))?;
+    let offset = input[2].to_i64().max(0) as u64;
+    let ptr = input[3].to_i32() as u32;
+    let len = input[4].to_i32().max(0) as u32;
+    let result = read_payload_ref(&mem, input[0].to_i32(), input[1].to_i32())
+        .and_then(|reference| payload_ref::fetch(&reference, offset, len))
+        .and_then(|data| {
+            mem.write(&data, ptr)?;
+            Ok(data.len() as i32)
+        });
+    Ok(guest_status(result))
+}
+
+
+/// Host function of modules hosted in the pod-shared VM. If the target function is co-hosted in
+/// the same VM the payload is moved through linear memory, otherwise the socket path is used.
+pub fn read_memory_pod_shared(caller: Caller, input: Vec<WasmValue>, vm_shared: &Arc<Mutex<Vm>>) -> Result<Vec<WasmValue>, HostFuncError> {
+    let sender = caller.instance().and_then(|instance| instance.name()).unwrap_or_default();
+    let context = caller_context(&caller, None)?;
+    if let Some(node) = context.node.as_ref().filter(|node| node.fan_out() > 0) {
+        return read_memory_workflow(&context, &caller, &input, vm_shared, &sender, node);
+    }
+    if let Some(target) = pod::find_target_module(&sender) {
+        let call = IntraVmCall::new(target.module_name, target.entrypoint);
+        return read_memory_intra_vm(&context, vm_shared, &sender, &call, &input);
+    }
+    transfer_to_target(&context, &caller, &input)
+}
+
+/// Host function body for a transfer to a module of the same VM. The entrypoint's result is
+/// returned to the guest, failures as the status of their error.
+pub fn read_memory_intra_vm(context: &HostContext, vm_shared: &Arc<Mutex<Vm>>, sender: &str, call: &IntraVmCall, input: &[WasmValue]) -> Result<Vec<WasmValue>, HostFuncError> {
+    let address = input[0].to_i32();
+    let len = input[1].to_i32();
+    log::info!("Transfer from `{}` to `{}::{}` within the VM", sender, call.target, call.entrypoint);
+    if let Err(err) = authorize_local_transfer(context, sender, &call.target, &call.target) {
+        return Ok(guest_status(Err(err)));
+    }
+    let result = transfer_data_within_wasm_vm(vm_shared, sender, call, address, len);
+    Ok(guest_status(result.and_then(|result| entrypoint_status(call, result))))
+}
+
+/// Narrows the i64 result of an entrypoint to the i32 returned to the guest. Results that do not
+/// fit are a [`TransferError::InvalidResult`] rather than being truncated.
+fn entrypoint_status(call: &IntraVmCall, result: i64) -> Result<i32, TransferError> {
+    i32::try_from(result).map_err(|_| TransferError::InvalidResult(call.target.clone(), call.entrypoint.clone()))
+}
+
+/// Host function body for a function wired by a workflow manifest. The payload is delivered to
+/// every target of the node, through linear memory for targets hosted in the same VM and over the
+/// Unix socket or the network otherwise. Socket responses are concatenated and written back over
+/// the payload; if all targets were reached in memory the last entrypoint result is returned.
+/// Nothing is delivered unless the function may send to every target.
+pub fn read_memory_workflow(context: &HostContext, caller: &Caller, input: &[WasmValue], vm_shared: &Arc<Mutex<Vm>>, sender: &str, node: &WorkflowNode) -> Result<Vec<WasmValue>, HostFuncError> {
+    let mut mem = caller.memory(0).ok_or_else(|| TransferError::MissingExport(sender.to_string(), "memory".to_string()))?;
+    let address = input[0].to_i32();
+    let len = input[1].to_i32();
+    let payload = mem.read(address as u32, len as u32).map_err(TransferError::from)?;
+
+    // every target is authorized before any of them is delivered to, a denial sends nothing
+    for target in &node.targets {
+        if let Err(err) = authorize_local_transfer(context, &node.function.name, &target.name, &target.module_name()) {
+            return Ok(guest_status(Err(err)));
+        }
+    }
+    let mut remote_targets = Vec::new();
+    let mut in_vm_result = Ok(0);
+    for target in &node.targets {
+        let module_name = target.module_name();
+        let in_vm = match target.transport {
+            Transport::Memory => true,
+            Transport::Auto => vm_shared.lock().unwrap().contains_module(&module_name),
+            Transport::Unix | Transport::Network => false,
+        };
+        log::info!("Workflow transfer from `{}` to `{}` (in VM: {})", node.function.name, target.name, in_vm);
+        if in_vm {
+            let call = IntraVmCall::new(module_name, target.entrypoint.clone().unwrap_or_default());
+            in_vm_result = transfer_data_within_wasm_vm(vm_shared, sender, &call, address, len)
+                .and_then(|result| entrypoint_status(&call, result));
+            if in_vm_result.is_err() {
+                return Ok(guest_status(in_vm_result));
             }
+        } else {
+            remote_targets.push(target);
         }
     }
 
-    // Write response back into Wasm VM
-    let bytes = target_function_result.as_bytes();
-    let len = bytes.len();
-    mem.write(bytes, arg1_ptr).unwrap();
+    if remote_targets.is_empty() {
+        return Ok(guest_status(in_vm_result));
+    }
+    let response = match send_to_workflow_targets(context, &node.function.name, payload, &remote_targets) {
+        Ok(response) => response,
+        Err(err) => return Ok(guest_status(Err(err))),
+    };
+    let bytes = response.as_bytes();
+    mem.write(bytes, address as u32).map_err(TransferError::from)?;
+    Ok(vec![WasmValue::from_i32(bytes.len() as i32)])
+}
 
-    Ok(vec![WasmValue::from_i32(len as i32)])
+/// Selects the next hops incoming streams are forwarded to: the targets of the container's
+/// workflow node reachable over Unix sockets, else the discovered target function. The outcome of
+/// the forwarding is reported to the selections.
+pub fn stream_forward_targets(context: &HostContext) -> Vec<Selection> {
+    match context.node.as_ref().filter(|node| node.fan_out() > 0) {
+        Some(node) => node
+            .targets
+            .iter()
+            .filter(|target| target.transport != Transport::Network)
+            .filter_map(|target| {
+                let selection = select_container(context, &target.module_name());
+                if selection.is_none() {
+                    log::warn!("No socket found to forward streams to {}", target.name);
+                }
+                selection
+            })
+            .collect(),
+        None => discover_target(context).map(|target| vec![target.selection]).unwrap_or_default(),
+    }
 }
 
+/// Sends a payload to the remote targets of a fan-out. Co-located receivers reached over Unix
+/// sockets are handed a reference to a single cached copy of large payloads. Nothing is sent
+/// unless `source` may send to every target.
+fn send_to_workflow_targets(context: &HostContext, source: &str, payload: Vec<u8>, targets: &[&FunctionSpec]) -> Result<String, TransferError> {
+    let _suspended = pod::suspend();
+    for target in targets {
+        authorize_transfer(context, source, &target.name)?;
+    }
+    let lease = if targets.len() > 1 && payload.len() >= payload_cache::MIN_CACHED_SIZE {
+        payload_cache::lease(&payload)
+            .map_err(|e| log::warn!("Failed to cache payload, sending copies: {}", e))
+            .ok()
+    } else {
+        None
+    };
+    let mut response = String::new();
+    for target in targets {
+        response.push_str(&send_to_workflow_target(context, &payload, target, lease.as_ref())?);
+    }
+    Ok(response)
+}
 
-/// Transfers data from the source function within the same Wasm VM.
+/// Sends the payload to a workflow target outside the VM, over its Unix socket if a container
+/// running the target module is found, or over the network if the target has an address.
+fn send_to_workflow_target(context: &HostContext, payload: &[u8], target: &FunctionSpec, cached: Option<&Lease>) -> Result<String, TransferError> {
+    let strategy = target.balancing.unwrap_or(context.config.balancing);
+    let selection = balancer::select(&target.name, &target_replicas(context, target), strategy)
+        .ok_or_else(|| TransferError::TargetNotFound(target.name.clone()))?;
+    let result = match selection.replica() {
+        Replica::Local(container_path) => {
+            let socket_path = format!("{}.sock", container_path);
+            let result = match cached {
+                Some(lease) => {
+                    // the delivery holds the cached payload until the receiver answered
+                    let delivery = lease.clone();
+                    let reference = delivery.reference().encode();
+                    let trailer = checksum_trailer(context, &[reference.as_slice()]);
+                    let flags = framing::CACHE_REF | trailer_flags(&trailer);
+                    mux::request_flagged(&socket_path, flags, &with_trailer(&[reference.as_slice()], &trailer))
+                }
+                None => {
+                    let trailer = checksum_trailer(context, &[payload]);
+                    mux::request_flagged(&socket_path, trailer_flags(&trailer), &with_trailer(&[payload], &trailer))
+                }
+            };
+            result
+                .map(|result| String::from_utf8_lossy(&result).to_string())
+                .map_err(|e| match checksum::is_mismatch(&e) {
+                    true => TransferError::ChecksumMismatch,
+                    false => TransferError::Communication(e.to_string()),
+                })
+        }
+        Replica::Remote(address) => {
+            let trailer = checksum_trailer(context, &[payload]);
+            let policy = context.config.compression.with_codec(target.compression);
+            context.tls_config()
+                .and_then(|tls| Ok(serve_network(context, trailer_flags(&trailer), &with_trailer(&[payload], &trailer), address.clone(), policy, tls)?))
+                .map(|_| String::new())
+        }
+    };
+    match &result {
+        Ok(_) => selection.succeeded(),
+        Err(_) => selection.failed(),
+    }
+    result
+}
+
+/// Entrypoint invoked on the payload when `target.entrypoint` is not annotated.
+pub const DEFAULT_ENTRYPOINT: &str = "process_data";
+
+/// A call into a function registered as a named module of the same WasmEdge VM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IntraVmCall {
+    /// Name of the module instance holding the target function.
+    pub target: String,
+    /// Export invoked as `entrypoint(ptr: i32, len: i32)` on the copied payload.
+    pub entrypoint: String,
+}
+
+impl IntraVmCall {
+    pub fn new(target: String, entrypoint: String) -> IntraVmCall {
+        let entrypoint = if entrypoint.is_empty() { DEFAULT_ENTRYPOINT.to_string() } else { entrypoint };
+        IntraVmCall { target, entrypoint }
+    }
+
+    /// Builds the call from the `target.module` and `target.entrypoint` annotations of the sender,
+    /// used to reach library modules linked into the sender's VM.
+    pub fn from_spec(spec: &Spec) -> Option<IntraVmCall> {
+        let target = oci_utils::get_wasm_annotations(spec, "target.module").replace("/", "");
+        if target.is_empty() {
+            return None;
+        }
+        Some(IntraVmCall::new(target, oci_utils::get_wasm_annotations(spec, "target.entrypoint")))
+    }
+}
+
+/// Transfers data from the sender module to a target module within the same Wasm VM.
 ///
 /// - `vm_shared`: A shared reference to the WasmEdge VM.
-/// - `source_function_name`: Name of the source function module.
-/// - `address`: The address in memory where the data exists.
+/// - `sender`: Name of the module instance holding the payload.
+/// - `call`: Target module and entrypoint to invoke.
+/// - `address`: The address in the sender's memory where the data exists.
 /// - `len`: The length of the data.
+///
+/// The target's `allocate(len)` export provides the destination buffer, whose ownership passes
+/// to the entrypoint. Returns the entrypoint's result, or `0` if it returns nothing.
 pub fn transfer_data_within_wasm_vm(
     vm_shared: &Arc<Mutex<Vm>>,
-    source_function_name: &String,
+    sender: &str,
+    call: &IntraVmCall,
     address: i32,
     len: i32
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut vm_locked = vm_shared.lock().unwrap();
+) -> Result<i64, TransferError> {
+    // The VM handle is cloned out of the mutex so that the target may itself transfer further
+    let mut vm = vm_shared.lock().unwrap().clone();
 
-    // Get the source function module instance (acts as the sender)
-    let source_instance: Instance = vm_locked.named_module(source_function_name.clone()).unwrap();
-    let mut source_memory = source_instance.memory("memory").unwrap();
+    // Read payload from the sender module
+    let sender_instance: Instance = vm.named_module(sender)
+        .map_err(|_| TransferError::ModuleNotFound(sender.to_string()))?;
+    let sender_memory = sender_instance.memory("memory")
+        .ok_or_else(|| TransferError::MissingExport(sender.to_string(), "memory".to_string()))?;
+    let payload = sender_memory.read(address as u32, len as u32)?;
 
-    // Allocate memory in the source function module for the incoming data
-    let allocate = source_instance.func("allocate").unwrap();
-    let alloc_result = allocate.call(&mut *vm_locked, params!()).unwrap();
-    let allocated_mem_addr = alloc_result[0].to_i32();
+    // a target waiting in a host call of its own cannot be run again before it returned
+    if pod::is_suspended(&call.target) {
+        return Err(TransferError::Communication(format!("`{}` is waiting for a transfer", call.target)));
+    }
+    let _nested = pod::nest(&call.target);
 
-    log::info!(
-        "Allocated memory in source function `{}` at address: {}",
-        source_function_name, allocated_mem_addr
-    );
+    // Allocate memory in the target module for the incoming data
+    let target_instance: Instance = vm.named_module(&call.target)
+        .map_err(|_| TransferError::ModuleNotFound(call.target.clone()))?;
+    let mut target_memory = target_instance.memory("memory")
+        .ok_or_else(|| TransferError::MissingExport(call.target.clone(), "memory".to_string()))?;
+    let allocate = target_instance.func("allocate")
+        .ok_or_else(|| TransferError::MissingExport(call.target.clone(), "allocate".to_string()))?;
+    let alloc_result = allocate.call(&mut vm, params!(len))?;
+    let allocated_mem_addr = alloc_result.first().map(|ptr| ptr.to_i32())
+        .ok_or_else(|| TransferError::InvalidResult(call.target.clone(), "allocate".to_string()))?;
 
-    // Read payload from the main module
-    let main_instance: Instance = vm_locked.named_module("main").unwrap();
-    let main_memory = main_instance.memory("memory").unwrap();
-    let payload = main_memory.read(address as u32, len as u32).expect("Failed to read memory from main module");
+    log::info!("Allocated {} bytes in module `{}` at address: {}", len, call.target, allocated_mem_addr);
 
-    log::info!("Payload read successfully from main module.");
+    // Write payload into the target module's memory space
+    target_memory.write(payload, allocated_mem_addr as u32)?;
 
-    // Write payload into the source function module's memory space
-    let _ = source_memory.write(payload, allocated_mem_addr as u32);
+    // Invoke the target's entrypoint on the written payload
+    let entrypoint = target_instance.func(&call.entrypoint)
+        .ok_or_else(|| TransferError::MissingExport(call.target.clone(), call.entrypoint.clone()))?;
+    let result = entrypoint.call(&mut vm, params!(allocated_mem_addr, len))?;
 
-    log::info!("Payload written to source function `{}` memory.", source_function_name);
+    log::info!("Function `{}::{}` executed successfully.", call.target, call.entrypoint);
 
-    // Invoke the source function's processing function (e.g., `process_data`)
-    let process_func = source_instance.func("process_data").unwrap();
-    let _ = process_func.call(&mut *vm_locked, params!()).unwrap();
+    Ok(match result.first() {
+        Some(value) if value.ty() == ValType::I64 => value.to_i64(),
+        Some(value) => value.to_i32() as i64,
+        None => 0,
+    })
+}
 
-    log::info!("Function `{}` executed successfully.", source_function_name);
+pub fn find_function_metadata(root_path: &str) -> Option<(String, String, String)> {
+    find_function_metadata_scoped(&[root_path.to_string()], &|_| true)
+}
 
-    Ok(())
+/// Like [`find_function_metadata`], searching the bundles under any of `roots` that `admits`
+/// accepts.
+pub fn find_function_metadata_scoped(roots: &[String], admits: &dyn Fn(&Spec) -> bool) -> Option<(String, String, String)> {
+    find_all_function_metadata_scoped(roots, admits).into_iter().next()
 }
 
-pub fn find_function_metadata(root_path: &str) -> Option<(String, String, String)> {
-    for file in WalkDir::new(root_path).into_iter().filter_map(|file| file.ok()) {
-        let file_name = file.file_name().to_str().unwrap();
-        if file.metadata().unwrap().is_file() && file_name == "config.json" {
+/// Like [`find_function_metadata_scoped`], returning every function found.
+pub fn find_all_function_metadata_scoped(roots: &[String], admits: &dyn Fn(&Spec) -> bool) -> Vec<(String, String, String)> {
+    let mut found = Vec::new();
+    let files = roots.iter().flat_map(|root| WalkDir::new(root).into_iter().filter_map(|file| file.ok()));
+    for file in files {
+        // bundles may be deleted while they are walked
+        if file.file_name() == "config.json" && file.metadata().map_or(false, |metadata| metadata.is_file()) {
             info!("OCI config spec found: {}", file.path().display());
             let container_path = file.path().display().to_string().replace("/config.json", "");
 
@@ -121,6 +933,9 @@ pub fn find_function_metadata(root_path: &str) -> Option<(String, String, String
                 Ok(spec) => spec,
                 Err(_) => continue,
             };
+            if !admits(&spec) {
+                continue;
+            }
 
             // Retrieve function name and address from annotations
             let function_name = oci_utils::get_wasm_annotations(&spec, "target.function");
@@ -138,12 +953,12 @@ pub fn find_function_metadata(root_path: &str) -> Option<(String, String, String
 
             // Check if the socket file exists
             let socket_path = format!("{}.sock", container_path);
-            if Path::new(&socket_path).exists() {
-                return Some((socket_path, formatted_function_name, function_address));
+            if socket_utils::exists(&socket_path) {
+                found.push((socket_path, formatted_function_name, function_address));
             }
         }
     }
-    None
+    found
 }
 
 
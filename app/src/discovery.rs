@@ -0,0 +1,90 @@
+use std::path::Path;
+use oci_spec::runtime::Spec;
+use crate::utils::oci_utils;
+
+/// Annotation selecting where target functions are discovered: `pod`, `namespace` (default) or
+/// `node`, which searches the bundles of every containerd namespace.
+pub const SCOPE_ANNOTATION: &str = "discovery.scope";
+
+/// Annotation listing further containerd namespaces, comma-separated, searched for targets.
+pub const NAMESPACES_ANNOTATION: &str = "discovery.namespaces";
+
+/// Annotation set by the CRI plugin to the id of the pod sandbox of a container.
+pub const SANDBOX_ID_ANNOTATION: &str = "io.kubernetes.cri.sandbox-id";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Containers of the same pod sandbox.
+    Pod,
+    /// Containers of the same containerd namespace.
+    Namespace,
+    /// All containers of the node.
+    Node,
+}
+
+impl Scope {
+    pub fn parse(name: &str) -> Option<Scope> {
+        match name {
+            "pod" => Some(Scope::Pod),
+            "namespace" => Some(Scope::Namespace),
+            "node" => Some(Scope::Node),
+            _ => None,
+        }
+    }
+}
+
+/// The bundles a container may discover target functions among.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DiscoveryScope {
+    pub scope: Scope,
+    /// containerd namespace of the container, the name of the directory holding its bundle.
+    pub namespace: String,
+    /// Namespaces searched in addition to the container's own.
+    pub namespaces: Vec<String>,
+    /// Pod sandbox of the container, if it was created through CRI.
+    pub sandbox_id: Option<String>,
+}
+
+impl DiscoveryScope {
+    /// Reads the scope of the container whose bundle is at `bundle_path`.
+    pub fn from_spec(spec: &Spec, bundle_path: &str) -> DiscoveryScope {
+        let namespace = Path::new(bundle_path.trim_end_matches('/'))
+            .parent()
+            .and_then(|parent| parent.file_name())
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let namespaces = oci_utils::get_wasm_annotations(spec, NAMESPACES_ANNOTATION)
+            .split(',')
+            .map(|namespace| namespace.trim().to_string())
+            .filter(|namespace| !namespace.is_empty())
+            .collect();
+        let sandbox_id = Some(oci_utils::get_wasm_annotations(spec, SANDBOX_ID_ANNOTATION)).filter(|id| !id.is_empty());
+        DiscoveryScope {
+            scope: Scope::parse(&oci_utils::get_wasm_annotations(spec, SCOPE_ANNOTATION)).unwrap_or(Scope::Namespace),
+            namespace,
+            namespaces,
+            sandbox_id,
+        }
+    }
+
+    /// Returns the directories under the containerd state root `state_root` holding the bundles
+    /// in scope.
+    pub fn roots(&self, state_root: &str) -> Vec<String> {
+        if self.scope == Scope::Node || self.namespace.is_empty() {
+            return vec![state_root.to_string()];
+        }
+        std::iter::once(&self.namespace)
+            .chain(self.namespaces.iter().filter(|namespace| **namespace != self.namespace))
+            .map(|namespace| Path::new(state_root).join(namespace).display().to_string())
+            .collect()
+    }
+
+    /// Whether a container found under the roots is in scope. Containers of other pods are out of
+    /// the `pod` scope; a container created outside CRI has no pod and keeps its namespace scope.
+    pub fn admits(&self, spec: &Spec) -> bool {
+        match (&self.scope, &self.sandbox_id) {
+            (Scope::Pod, Some(sandbox_id)) => oci_utils::get_wasm_annotations(spec, SANDBOX_ID_ANNOTATION) == *sandbox_id,
+            _ => true,
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow;
 use containerd_shim_wasm::sandbox::error;
 use thiserror::Error;
+use wasmedge_sdk::error::HostFuncError;
+use crate::checksum;
 
 #[derive(Debug, Error)]
 pub enum WasmRuntimeError {
@@ -11,3 +13,75 @@ pub enum WasmRuntimeError {
     #[error("{0}")]
     Wasmedge(#[from] Box<wasmedge_sdk::error::WasmEdgeError>),
 }
+
+
+/// Errors of a transfer between functions. They are surfaced to guests as the user code of the
+/// failing host function, see [`TransferError::code`].
+#[derive(Debug, Error)]
+pub enum TransferError {
+    #[error("module `{0}` is not registered in the VM")]
+    ModuleNotFound(String),
+    #[error("module `{0}` does not export `{1}`")]
+    MissingExport(String, String),
+    #[error("`{0}::{1}` returned an unexpected result")]
+    InvalidResult(String, String),
+    #[error("{0}")]
+    Wasmedge(#[from] Box<wasmedge_sdk::error::WasmEdgeError>),
+    #[error("target function `{0}` not found")]
+    TargetNotFound(String),
+    #[error("communication failure: {0}")]
+    Communication(String),
+    #[error("{0}")]
+    Io(std::io::Error),
+    #[error("unknown transfer handle {0}")]
+    UnknownHandle(u32),
+    #[error("payload {0} expired or was never published")]
+    PayloadExpired(u128),
+    #[error("invalid payload reference")]
+    InvalidReference,
+    #[error("payload does not match its checksum")]
+    ChecksumMismatch,
+    #[error("transfer from `{0}` to `{1}` denied by policy")]
+    TransferDenied(String, String),
+    #[error("instance of `{0}` was killed")]
+    Killed(String),
+    #[error("invalid argument: {0}")]
+    InvalidArgument(String),
+}
+
+impl TransferError {
+    pub fn code(&self) -> u32 {
+        match self {
+            TransferError::ModuleNotFound(_) => 1,
+            TransferError::MissingExport(_, _) => 2,
+            TransferError::InvalidResult(_, _) => 3,
+            TransferError::Wasmedge(_) => 4,
+            TransferError::TargetNotFound(_) => 5,
+            TransferError::Communication(_) => 6,
+            TransferError::Io(_) => 7,
+            TransferError::UnknownHandle(_) => 8,
+            TransferError::PayloadExpired(_) => 9,
+            TransferError::InvalidReference => 10,
+            TransferError::ChecksumMismatch => 11,
+            TransferError::TransferDenied(_, _) => 12,
+            TransferError::Killed(_) => 13,
+            TransferError::InvalidArgument(_) => 14,
+        }
+    }
+}
+
+impl From<std::io::Error> for TransferError {
+    fn from(err: std::io::Error) -> Self {
+        if checksum::is_mismatch(&err) {
+            return TransferError::ChecksumMismatch;
+        }
+        TransferError::Io(err)
+    }
+}
+
+impl From<TransferError> for HostFuncError {
+    fn from(err: TransferError) -> Self {
+        log::error!("Transfer failed: {}", err);
+        HostFuncError::User(err.code())
+    }
+}
@@ -0,0 +1,34 @@
+use std::io::{self, ErrorKind};
+
+/// Flags a sender announces ahead of a payload, in the status byte of a multiplexed request or
+/// in the header byte of a network transfer, telling the receiver how the payload is encoded.
+/// Receivers never infer the encoding from the content of a payload, which is chosen by the guest.
+pub type Flags = u8;
+
+/// The payload is a reference to the payload cache of the sending process, see
+/// [`crate::payload_cache::materialize`].
+pub const CACHE_REF: Flags = 1 << 0;
+
+/// The payload ends with a checksum trailer, see [`crate::checksum::verify`].
+pub const CHECKSUM: Flags = 1 << 1;
+
+/// The payload is a compressed frame, see [`crate::compression::decompress`].
+pub const COMPRESSED: Flags = 1 << 2;
+
+pub fn has(flags: Flags, flag: Flags) -> bool {
+    flags & flag != 0
+}
+
+/// Returns `flag` if `set`, no flags otherwise.
+pub fn flag_if(set: bool, flag: Flags) -> Flags {
+    if set { flag } else { 0 }
+}
+
+/// Splits the header byte a network sender writes ahead of its payload off the received data.
+pub fn split_header(mut data: Vec<u8>) -> io::Result<(Flags, Vec<u8>)> {
+    if data.is_empty() {
+        return Err(io::Error::new(ErrorKind::UnexpectedEof, "transfer without header"));
+    }
+    let flags = data.remove(0);
+    Ok((flags, data))
+}
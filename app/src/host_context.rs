@@ -0,0 +1,156 @@
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::sync::Arc;
+use log::error;
+use oci_spec::runtime::Spec;
+use rustls::ServerConfig;
+use crate::advertise::PortRange;
+use crate::balancer::Strategy;
+use crate::checksum::Algorithm;
+use crate::compression::CompressionPolicy;
+use crate::discovery::DiscoveryScope;
+use crate::error::TransferError;
+use crate::registry::Registry;
+use crate::routing::RouteSet;
+use crate::shutdown::Shutdown;
+use crate::stream::Streams;
+use crate::tls;
+use crate::transfer_policy::{self, TransferPolicy};
+use crate::transfers::Transfers;
+use crate::workflow::WorkflowNode;
+
+/// Transfer settings of a container, read once from its annotations.
+#[derive(Debug, Clone)]
+pub struct TransferConfig {
+    /// Name of the function run by the container in transfer policies.
+    pub function_name: String,
+    pub checksum: Option<Algorithm>,
+    pub compression: CompressionPolicy,
+    pub balancing: Strategy,
+    /// Versions the target is split between, if any.
+    pub routes: Option<RouteSet>,
+    /// Transfers the container may send and receive, unrestricted if `None`.
+    pub policy: Option<TransferPolicy>,
+    /// Transfers any container of the node may send and receive, unrestricted if `None`.
+    pub node_policy: Option<TransferPolicy>,
+    pub port_range: Option<PortRange>,
+}
+
+impl TransferConfig {
+    /// Reads the settings from the annotations of `spec` and the policy of the node. Invalid routes
+    /// are ignored, while an invalid policy denies all transfers rather than lifting the restriction.
+    pub fn from_spec(spec: &Spec) -> TransferConfig {
+        TransferConfig::with_node_policy(spec, Path::new(transfer_policy::NODE_POLICY_PATH))
+    }
+
+    /// Like [`TransferConfig::from_spec`], reading the policy of the node from `node_policy`.
+    pub fn with_node_policy(spec: &Spec, node_policy: &Path) -> TransferConfig {
+        let routes = RouteSet::from_spec(spec).unwrap_or_else(|err| {
+            error!("Ignoring invalid routes: {}", err);
+            None
+        });
+        let policy = TransferPolicy::from_spec(spec).unwrap_or_else(|err| {
+            error!("Invalid transfer policy: {}", err);
+            Some(TransferPolicy { edges: Vec::new() })
+        });
+        let node_policy = TransferPolicy::from_file(node_policy).unwrap_or_else(|err| {
+            error!("Invalid node transfer policy: {}", err);
+            Some(TransferPolicy { edges: Vec::new() })
+        });
+        TransferConfig {
+            function_name: transfer_policy::function_name(spec),
+            checksum: Algorithm::from_spec(spec),
+            compression: CompressionPolicy::from_spec(spec),
+            balancing: Strategy::from_spec(spec),
+            routes,
+            policy,
+            node_policy,
+            port_range: PortRange::from_spec(spec),
+        }
+    }
+
+    /// Whether the transfers of the container are restricted by a policy.
+    pub fn restricts(&self) -> bool {
+        self.policy.is_some() || self.node_policy.is_some()
+    }
+
+    /// Checks a transfer against the policy of the node and the policy of the container, both
+    /// must allow it.
+    pub fn authorize(&self, source: &str, target: &str) -> Result<(), TransferError> {
+        transfer_policy::authorize(self.node_policy.as_ref(), source, target)?;
+        transfer_policy::authorize(self.policy.as_ref(), source, target)
+    }
+}
+
+/// State the host functions of a container instance act on. Each instance owns its context and
+/// its import module captures it, so several sandboxes can be hosted by one shim process.
+#[derive(Debug)]
+pub struct HostContext {
+    pub spec: Spec,
+    /// Bundle of the container.
+    pub bundle_path: String,
+    /// containerd state root holding the bundles of all namespaces, with a trailing `/`.
+    pub state_root: String,
+    pub scope: DiscoveryScope,
+    /// Workflow wiring of the function run by the container, resolving its targets.
+    pub node: Option<WorkflowNode>,
+    pub registry: Option<Registry>,
+    pub config: TransferConfig,
+    /// Shutdown request of the instance, stopping it from taking new input.
+    pub shutdown: Shutdown,
+    /// Transfers submitted asynchronously by the guest.
+    pub transfers: Transfers,
+    /// Streams opened by the guest or delivered to it.
+    pub streams: Streams,
+    /// TLS configuration of the sender, loaded from its certificates once; a failure to load
+    /// them fails every network transfer of the instance.
+    tls: Result<Option<Arc<ServerConfig>>, String>,
+}
+
+impl HostContext {
+    pub fn new(spec: Spec, bundle_path: &str, node: Option<WorkflowNode>) -> HostContext {
+        let registry = Registry::from_spec(&spec).unwrap_or_else(|err| {
+            error!("Ignoring registry: {}", err);
+            None
+        });
+        let tls = tls::server_config(&spec).map_err(|err| {
+            error!("Failed to load TLS certificates: {}", err);
+            err.to_string()
+        });
+        HostContext {
+            scope: DiscoveryScope::from_spec(&spec, bundle_path),
+            config: TransferConfig::from_spec(&spec),
+            state_root: state_root(bundle_path),
+            bundle_path: bundle_path.to_string(),
+            spec,
+            node,
+            registry,
+            shutdown: Shutdown::default(),
+            transfers: Transfers::default(),
+            streams: Streams::default(),
+            tls,
+        }
+    }
+
+    /// Shares the shutdown request of the instance with its host functions and listener.
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> HostContext {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// Returns the TLS configuration of the sender if certificates are annotated through
+    /// `tls.cert` and `tls.key`.
+    pub fn tls_config(&self) -> Result<Option<Arc<ServerConfig>>, TransferError> {
+        self.tls.clone().map_err(|err| TransferError::Io(io::Error::new(ErrorKind::InvalidData, err)))
+    }
+}
+
+/// Returns the directory two levels above a bundle, `<root>/<namespace>/<id>`.
+pub fn state_root(bundle_path: &str) -> String {
+    bundle_path
+        .trim_end_matches('/')
+        .rsplitn(3, '/')
+        .nth(2)
+        .map(|root| format!("{}/", root))
+        .unwrap_or_default()
+}
@@ -2,4 +2,25 @@ pub mod error;
 pub mod utils;
 pub mod data_hose;
 pub mod runtime;
-pub mod remote_transfer;
\ No newline at end of file
+pub mod remote_transfer;
+pub mod pod;
+pub mod workflow;
+pub mod mux;
+pub mod framing;
+pub mod transfers;
+pub mod stream;
+pub mod payload_ref;
+pub mod payload_cache;
+pub mod checksum;
+pub mod compression;
+pub mod tls;
+pub mod peer_auth;
+pub mod transfer_policy;
+pub mod discovery;
+pub mod balancer;
+pub mod routing;
+pub mod advertise;
+pub mod registry;
+pub mod resources;
+pub mod host_context;
+pub mod shutdown;
@@ -4,30 +4,37 @@ use containerd_shim_wasm::sandbox::error::Error;
 use containerd_shim_wasm::sandbox::{exec, ShimCli};
 use containerd_shim_wasm::sandbox::oci;
 use containerd_shim_wasm::sandbox::{EngineGetter, Instance, InstanceConfig};
-use libc::{dup, dup2, SIGINT, SIGKILL, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
+use libc::{SIGINT, SIGKILL, SIGTERM, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 use log::{error, info};
 use std::fs::OpenOptions;
 use std::io::ErrorKind;
 use std::os::unix::io::{IntoRawFd, RawFd};
 use std::path::Path;
 use std::sync::{
+    atomic::{AtomicBool, Ordering},
     mpsc::Sender,
     {Arc, Condvar, Mutex},
 };
 use std::thread;
-use wasmedge_sdk::{config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions}, params, ImportObjectBuilder, PluginManager, Vm};
+use std::time::Duration;
+use wasmedge_sdk::{config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions}, Caller, ImportObjectBuilder, PluginManager, Vm};
 use roadrunner::error::WasmRuntimeError;
-use regex::Regex;
-use itertools::Itertools;
-use roadrunner::{data_hose, runtime};
-use roadrunner::data_hose::transfer_data_within_wasm_vm;
-use roadrunner::utils::{oci_utils, snapshot_utils};
-
-static mut STDIN_FD: Option<RawFd> = None;
-static mut STDOUT_FD: Option<RawFd> = None;
-static mut STDERR_FD: Option<RawFd> = None;
+use roadrunner::{data_hose, payload_ref, pod, runtime, workflow};
+use roadrunner::data_hose::IntraVmCall;
+use roadrunner::host_context::HostContext;
+use roadrunner::resources::InstanceResources;
+use roadrunner::shutdown::{self, Shutdown};
+use roadrunner::utils::{module_utils, oci_utils, socket_utils};
 
 type ExitCode = (Mutex<Option<(u32, DateTime<Utc>)>>, Condvar);
+
+/// Waits up to `timeout` for the instance to exit, returns whether it did.
+fn wait_exit(code: &ExitCode, timeout: Duration) -> bool {
+    let (lock, cvar) = code;
+    let exit = lock.lock().unwrap();
+    let (exit, _) = cvar.wait_timeout_while(exit, timeout, |exit| exit.is_none()).unwrap();
+    exit.is_some()
+}
 pub struct Wasi {
     exit_code: Arc<ExitCode>,
     engine: Vm,
@@ -37,24 +44,12 @@ pub struct Wasi {
     stderr: String,
     bundle: String,
     pidfd: Arc<Mutex<Option<exec::PidFD>>>,
+    pod_shared: Arc<AtomicBool>,
+    resources: Arc<Mutex<InstanceResources>>,
+    shutdown: Shutdown,
 }
 
 
-
-pub fn reset_stdio() {
-    unsafe {
-        if STDIN_FD.is_some() {
-            dup2(STDIN_FD.unwrap(), STDIN_FILENO);
-        }
-        if STDOUT_FD.is_some() {
-            dup2(STDOUT_FD.unwrap(), STDOUT_FILENO);
-        }
-        if STDERR_FD.is_some() {
-            dup2(STDERR_FD.unwrap(), STDERR_FILENO);
-        }
-    }
-}
-
 pub fn maybe_open_stdio(path: &str) -> Result<Option<RawFd>, Error> {
     if path.is_empty() {
         return Ok(None);
@@ -69,7 +64,8 @@ pub fn maybe_open_stdio(path: &str) -> Result<Option<RawFd>, Error> {
 }
 
 
-pub fn prepare_module(mut vm: Vm, spec: &oci::Spec, stdin_path: String, stdout_path: String, stderr_path: String ) -> Result<Vm, WasmRuntimeError> {
+pub fn prepare_module(mut vm: Vm, context: &Arc<HostContext>, stdin_path: String, stdout_path: String, stderr_path: String, resources: &mut InstanceResources) -> Result<Vm, WasmRuntimeError> {
+    let spec = &context.spec;
     info!("opening rootfs");
     let rootfs_path = oci::get_root(spec).to_str().unwrap();
     let root = format!("/:{}", rootfs_path);
@@ -85,30 +81,18 @@ pub fn prepare_module(mut vm: Vm, spec: &oci::Spec, stdin_path: String, stdout_p
     info!("envs {:?}", envs);
 
     info!("opening stdin");
-    let stdin = maybe_open_stdio(&stdin_path).context("could not open stdin")?;
-    if stdin.is_some() {
-        unsafe {
-            STDIN_FD = Some(dup(STDIN_FILENO));
-            dup2(stdin.unwrap(), STDIN_FILENO);
-        }
+    if let Some(stdin) = maybe_open_stdio(&stdin_path).context("could not open stdin")? {
+        resources.redirect_stdio(STDIN_FILENO, stdin).context("could not redirect stdin")?;
     }
 
     info!("opening stdout");
-    let stdout = maybe_open_stdio(&stdout_path).context("could not open stdout")?;
-    if stdout.is_some() {
-        unsafe {
-            STDOUT_FD = Some(dup(STDOUT_FILENO));
-            dup2(stdout.unwrap(), STDOUT_FILENO);
-        }
+    if let Some(stdout) = maybe_open_stdio(&stdout_path).context("could not open stdout")? {
+        resources.redirect_stdio(STDOUT_FILENO, stdout).context("could not redirect stdout")?;
     }
 
     info!("opening stderr");
-    let stderr = maybe_open_stdio(&stderr_path).context("could not open stderr")?;
-    if stderr.is_some() {
-        unsafe {
-            STDERR_FD = Some(dup(STDERR_FILENO));
-            dup2(stderr.unwrap(), STDERR_FILENO);
-        }
+    if let Some(stderr) = maybe_open_stdio(&stderr_path).context("could not open stderr")? {
+        resources.redirect_stdio(STDERR_FILENO, stderr).context("could not redirect stderr")?;
     }
 
     let mut cmd = args[0].clone();
@@ -127,42 +111,78 @@ pub fn prepare_module(mut vm: Vm, spec: &oci::Spec, stdin_path: String, stdout_p
         Some(envs.iter().map(|s| s as &str).collect()),
         Some(preopens),
     );
-    let target: String = new_args.get(0).cloned().unwrap_or_default();
+    let intra_vm_call = IntraVmCall::from_spec(spec);
     let vm_shared = Arc::new(Mutex::new(vm.clone()));
 
-    let import = ImportObjectBuilder::new()
+    let host_context = context.clone();
+    let import = data_hose::with_transfer_functions(ImportObjectBuilder::new(), Some(context.clone()))?
         .with_func::<(i32, i32), i32>("read_memory_host", move |caller, input| {
-            // parse the first argument of WasmValue type
-            //println!("[+] external function called: read_memory_host");
-
-            let address = input[0].to_i32();
-            let len = input[1].to_i32();
-            transfer_data_within_wasm_vm(&vm_shared, &target, address, len).unwrap();
-            let result = data_hose::read_memory_host(caller, input)?;
-
-            Ok(result) // Return the result of the function to match the expected type
+            let context = &host_context;
+            if let Some(node) = context.node.as_ref().filter(|node| node.fan_out() > 0) {
+                return data_hose::read_memory_workflow(context, &Caller::new(caller), &input, &vm_shared, "main", node);
+            }
+            // library modules linked into this VM are called directly through linear memory
+            if let Some(call) = &intra_vm_call {
+                if vm_shared.lock().unwrap().contains_module(&call.target) {
+                    return data_hose::read_memory_intra_vm(context, &vm_shared, "main", call, &input);
+                }
+            }
+            data_hose::read_memory_host(context, Caller::new(caller), input)
         })?
         .build("wasi_export")?;
 
-    let vm= vm.register_import_module(import)?.register_module_from_file("main", mod_path)?;
+    let vm = vm.register_import_module(import)?;
+    let vm = register_imported_modules(vm, &mod_path, oci::get_root(spec))?;
+    let vm = vm.register_module_from_file("main", mod_path)?;
     info!("module registered");
     Ok(vm)
 }
 
-pub fn extract_modules_from_wat(path: &Path) -> Vec<String>{
-    let mod_wat = wasmprinter::print_file(path).unwrap();
-    info!("module wat {:?}",mod_wat);
-    let re = Regex::new(r#"\bimport\s+\S+"#).unwrap();
-    let matches = re.find_iter(mod_wat.as_str()).map(|s| s.as_str()).unique().collect_vec();
-    let mut modules: Vec<String> = vec![];
-    for cap in matches {
-        let module = cap.replace("import ","").replace("\"","") + ".wasm";
-        modules.push(module.to_string());
+/// Registers the container's module in the pod-shared VM under its own name (`argv[0]`), so that
+/// co-hosted modules can reach it through linear memory. The `wasi_export` import module is
+/// registered once per shim process and resolves the calling module at call time.
+///
+/// The VM has a single WASI module, which is set up with the args, envs and preopens of a module
+/// each time it enters the VM through [`pod::enter`]; stdio is not redirected since it would
+/// affect every instance in the shim.
+pub fn prepare_shared_module(mut vm: Vm, context: &Arc<HostContext>) -> Result<(Vm, String), WasmRuntimeError> {
+    let spec = &context.spec;
+    if !vm.contains_module("wasi_export") {
+        let vm_shared = Arc::new(Mutex::new(vm.clone()));
+        let import = data_hose::with_transfer_functions(ImportObjectBuilder::new(), None)?
+            .with_func::<(i32, i32), i32>("read_memory_host", move |frame, input| {
+                data_hose::read_memory_pod_shared(Caller::new(frame), input, &vm_shared)
+            })?
+            .build("wasi_export")?;
+        vm = vm.register_import_module(import)?;
+    }
+
+    let module_name = oci_utils::get_module_name(spec);
+    let mod_path = oci::get_root(spec).join(&module_name);
+    if vm.contains_module(&module_name) {
+        // the VM cannot unload modules, a restarted container reuses its previous instance
+        info!("module {} already hosted in pod VM", module_name);
+    } else {
+        vm = register_imported_modules(vm, &mod_path, oci::get_root(spec))?;
+        vm = vm.register_module_from_file(&module_name, mod_path)?;
+        info!("module {} registered in pod VM", module_name);
+    }
+    pod::register_module(&module_name, context.clone());
+    Ok((vm, module_name))
+}
+
+/// Registers the library modules imported by the module at `mod_path` under their import names,
+/// so that they are linked when the module itself is instantiated.
+pub fn register_imported_modules(mut vm: Vm, mod_path: &Path, rootfs: &Path) -> Result<Vm, WasmRuntimeError> {
+    let dependencies = module_utils::resolve_dependencies(mod_path, rootfs)?;
+    for dependency in dependencies {
+        if vm.contains_module(&dependency.import_name) {
+            continue;
+        }
+        info!("registering module {} from {}", dependency.import_name, dependency.path);
+        vm = vm.register_module_from_file(&dependency.import_name, &dependency.path)?;
     }
-    info!("extracted import modules from wat {:#?}", modules);
-    let modules_path: Vec<String> = snapshot_utils::get_existing_image(modules, path.to_str().unwrap().to_string());
-    info!("Modules path: {:#?}",modules_path);
-    return modules_path;
+    Ok(vm)
 }
 
 impl Instance for Wasi {
@@ -178,10 +198,85 @@ impl Instance for Wasi {
             stderr: cfg.get_stderr().unwrap_or_default(),
             bundle: cfg.get_bundle().unwrap_or_default(),
             pidfd: Arc::new(Mutex::new(None)),
+            pod_shared: Arc::new(AtomicBool::new(false)),
+            resources: Arc::new(Mutex::new(InstanceResources::new())),
+            shutdown: Shutdown::default(),
         }
     }
 
     fn start(&self) -> Result<u32, Error> {
+        let started = self.start_instance();
+        if let Err(e) = &started {
+            error!("failed to start {}: {}", self.bundle.as_str(), e);
+            let _ = self.resources.lock().unwrap().release();
+        }
+        started
+    }
+
+    fn kill(&self, signal: u32) -> Result<(), Error> {
+        info!("killcw {}",self.bundle.as_str());
+        if signal as i32 != SIGKILL && signal as i32 != SIGINT && signal as i32 != SIGTERM {
+            println!("{:?}", signal);
+            return Err(Error::InvalidArgument(
+                "only SIGKILL, SIGINT and SIGTERM are supported".to_string(),
+            ));
+        }
+        if signal as i32 == SIGTERM {
+            return self.terminate();
+        }
+
+        if self.pod_shared.load(Ordering::SeqCst) {
+            // pod-shared modules run on a shim thread which cannot be signalled, the instance is
+            // detached and reported as killed; its guest traps at its next host call
+            self.shutdown.kill();
+            let module_name = self.module_name();
+            pod::unregister_module(&module_name, self.bundle.as_str());
+            let _ = self.resources.lock().unwrap().release();
+            let (lock, cvar) = &*self.exit_code;
+            let mut ec = lock.lock().unwrap();
+            if ec.is_none() {
+                *ec = Some((137, Utc::now()));
+            }
+            drop(ec);
+            cvar.notify_all();
+            return Ok(());
+        }
+
+        // resources are released by the waiter once the child exited
+        let lr = self.pidfd.lock().unwrap();
+        let fd = lr
+            .as_ref()
+            .ok_or_else(|| Error::FailedPrecondition("module is not running".to_string()))?;
+        fd.kill(SIGKILL as i32)
+
+    }
+
+    fn delete(&self) -> Result<(), Error> {
+        info!("deletecw {}",self.bundle.as_str());
+        self.resources.lock().unwrap().release()
+    }
+
+    fn wait(&self, channel: Sender<(u32, DateTime<Utc>)>) -> Result<(), Error> {
+        info!("wait");
+        let code = self.exit_code.clone();
+        thread::spawn(move || {
+            let (lock, cvar) = &*code;
+            let mut exit = lock.lock().unwrap();
+            while (*exit).is_none() {
+                exit = cvar.wait(exit).unwrap();
+            }
+            let ec = (*exit).unwrap();
+            channel.send(ec).unwrap();
+        });
+
+        Ok(())
+    }
+}
+
+impl Wasi {
+    /// Starts the container, forked or in the pod-shared VM. The resources acquired on the way are
+    /// released by [`Instance::start`] if it fails.
+    fn start_instance(&self) -> Result<u32, Error> {
 
         info!(">>> shim starts");
         let engine = self.engine.clone();
@@ -193,14 +288,24 @@ impl Instance for Wasi {
         let bundle_path = self.bundle.as_str();
         info!("bundle path {:?}", bundle_path);
         info!("loading specs {:?}", spec);
-        unsafe {
-            data_hose::OCI_SPEC=Some(spec.clone());
-            data_hose::BUNDLE_PATH=Some(bundle_path.rsplitn(3, '/').nth(2).unwrap().to_string()+"/");
+        let node = workflow::load_node(&spec)
+            .map_err(|e| Error::Others(format!("error loading workflow: {}", e)))?;
+        let context = Arc::new(HostContext::new(spec.clone(), bundle_path, node).with_shutdown(self.shutdown.clone()));
+        // sockets of crashed shims would otherwise be found by discovery and refuse connections
+        if let Some(namespace) = Path::new(bundle_path).parent() {
+            socket_utils::sweep_stale(&namespace.display().to_string());
+        }
+        self.resources.lock().unwrap().track_socket(format!("{}.sock", bundle_path));
+        self.resources.lock().unwrap().track_payload_refs(payload_ref::socket_path(bundle_path));
+        self.resources.lock().unwrap().track_advertisement(bundle_path.to_string());
+        if pod::is_pod_shared(&spec) {
+            return self.start_pod_shared(engine, context);
         }
-        let vm = prepare_module(engine, &spec, stdin, stdout, stderr)
+        let vm = prepare_module(engine, &context, stdin, stdout, stderr, &mut self.resources.lock().unwrap())
             .map_err(|e| Error::Others(format!("error setting up module: {}", e)))?;
         info!("vm created");
         let cg = oci::get_cgroup(&spec)?;
+        self.resources.lock().unwrap().track_cgroup(spec.clone());
 
         oci::setup_cgroup(cg.as_ref(), &spec)
             .map_err(|e| Error::Others(format!("error setting up cgroups: {}", e)))?;
@@ -211,16 +316,19 @@ impl Instance for Wasi {
                 *lr = Some(pidfd.clone());
 
                 info!("started wasi instance with tid {} at {}", tid,self.bundle.as_str());
+                // the child holds the container's stdio, the shim gets its own back
+                self.resources.lock().unwrap().restore_stdio();
 
                 let code = self.exit_code.clone();
-                let bundle_path = self.bundle.clone();
+                let resources = self.resources.clone();
                 let _ = thread::spawn(move || {
                     let (lock, cvar) = &*code;
-                    let status = match pidfd.wait() {
+                    let status = pidfd.wait();
+                    let _ = resources.lock().unwrap().release();
+                    let status = match status {
                         Ok(status) => status,
                         Err(e) => {
                             error!("error waiting for pid {}: {}", tid, e);
-                            oci_utils::delete(bundle_path).expect("static delete");
                             cvar.notify_all();
                             return;
                         }
@@ -238,14 +346,18 @@ impl Instance for Wasi {
                 // child process
                 let secondary_function = oci_utils::get_wasm_annotations(&spec, "secondary.function");
                 println!("Secondary function {}",secondary_function);
+                // SIGTERM lets the guest run its shutdown hook instead of terminating the child
+                if let Err(e) = shutdown::install_handler() {
+                    error!("failed to install SIGTERM handler: {}", e);
+                }
                 if secondary_function == "true" {
-                    match runtime::init_listener(bundle_path.to_string(), spec, vm) {
+                    match runtime::serve(context, vm, String::from("main")) {
                          Ok(_) => std::process::exit(0),
                         Err(_) => std::process::exit(137),
                     };
 
                 }else {
-                    match vm.run_func(Some("main"), "_start", params!()) {
+                    match runtime::start(context, vm, String::from("main")) {
                         Ok(_) => std::process::exit(0),
                         Err(_) => std::process::exit(137),
                     };
@@ -254,65 +366,108 @@ impl Instance for Wasi {
         }
     }
 
-    fn kill(&self, signal: u32) -> Result<(), Error> {
-        info!("killcw {}",self.bundle.as_str());
-        let binding = self.bundle.as_str().to_owned() + ".sock";
-        let socket_path = Path::new(&binding);
-        if socket_path.exists() {
-            std::fs::remove_file(&socket_path).unwrap();
-            info!("Socket {:?} deleted",self.bundle.as_str());
-        }
-        if signal as i32 != SIGKILL && signal as i32 != SIGINT {
-            println!("{:?}", signal);
-            return Err(Error::InvalidArgument(
-                "only SIGKILL and SIGINT are supported".to_string(),
-            ));
-        }
-
-        let lr = self.pidfd.lock().unwrap();
-        let fd = lr
-            .as_ref()
-            .ok_or_else(|| Error::FailedPrecondition("module is not running".to_string()))?;
-        fd.kill(SIGKILL as i32)
+    /// Runs the container as a module of the pod-shared VM on a thread of the shim process
+    /// instead of forking, the returned pid is the shim's own.
+    fn start_pod_shared(&self, engine: Vm, context: Arc<HostContext>) -> Result<u32, Error> {
+        let (vm, module_name) = prepare_shared_module(engine, &context)
+            .map_err(|e| Error::Others(format!("error setting up pod-shared module: {}", e)))?;
+        self.pod_shared.store(true, Ordering::SeqCst);
+        info!("started pod-shared module {} at {}", module_name, self.bundle.as_str());
 
-    }
+        let code = self.exit_code.clone();
+        let bundle_path = self.bundle.clone();
+        let resources = self.resources.clone();
+        let _ = thread::spawn(move || {
+            let secondary_function = oci_utils::get_wasm_annotations(&context.spec, "secondary.function");
+            let status = if secondary_function == "true" {
+                match runtime::serve(context.clone(), vm, module_name.clone()) {
+                    Ok(_) => 0,
+                    Err(_) => 137,
+                }
+            } else {
+                // modules of the pod run one at a time, each with its own WASI configuration
+                match runtime::start(context.clone(), vm, module_name.clone()) {
+                    Ok(_) => 0,
+                    Err(e) => {
+                        error!("pod-shared module {} failed: {}", module_name, e);
+                        137
+                    }
+                }
+            };
+            info!("pod-shared module {} exited with status {}", module_name, status);
+            // forked instances lose their streams with the process, the shim keeps running
+            context.streams.clear();
+            let _ = resources.lock().unwrap().release();
+            pod::unregister_module(&module_name, bundle_path.as_str());
 
-    fn delete(&self) -> Result<(), Error> {
-        info!("deletecw {}",self.bundle.as_str());
-        let spec = match oci_utils::load_spec(self.bundle.clone()){
-            Ok(spec) => spec,
-            Err(err) => {
-                error!("Could not load spec, skipping cgroup cleanup: {}", err);
-                return Ok(());
+            let (lock, cvar) = &*code;
+            let mut ec = lock.lock().unwrap();
+            if ec.is_none() {
+                *ec = Some((status, Utc::now()));
             }
-        };
-        let cg = oci::get_cgroup(&spec)?;
-        cg.delete()?;
+            drop(ec);
+            cvar.notify_all();
+        });
+        Ok(std::process::id())
+    }
 
-        let binding = self.bundle.as_str().to_owned() + ".sock";
-        let socket_path = Path::new(&binding);
-        if socket_path.exists() {
-            std::fs::remove_file(&socket_path).unwrap();
-            info!("Socket {:?} deleted",self.bundle.as_str());
+    /// Asks the instance to shut down: it stops taking input, finishes the input in flight and
+    /// runs the guest's `on_shutdown` hook. The instance is killed if it has not exited once the
+    /// grace period is over.
+    fn terminate(&self) -> Result<(), Error> {
+        let grace_period = oci_utils::load_spec(self.bundle.clone())
+            .map(|spec| shutdown::grace_period(&spec))
+            .unwrap_or(shutdown::DEFAULT_GRACE_PERIOD);
+        info!("shutting down {} within {:?}", self.bundle.as_str(), grace_period);
+
+        if self.pod_shared.load(Ordering::SeqCst) {
+            self.shutdown.request();
+        } else {
+            let lr = self.pidfd.lock().unwrap();
+            let fd = lr
+                .as_ref()
+                .ok_or_else(|| Error::FailedPrecondition("module is not running".to_string()))?;
+            fd.kill(SIGTERM as i32)?;
         }
-        Ok(())
-    }
 
-    fn wait(&self, channel: Sender<(u32, DateTime<Utc>)>) -> Result<(), Error> {
-        info!("wait");
         let code = self.exit_code.clone();
-        thread::spawn(move || {
-            let (lock, cvar) = &*code;
-            let mut exit = lock.lock().unwrap();
-            while (*exit).is_none() {
-                exit = cvar.wait(exit).unwrap();
+        let pidfd = self.pidfd.clone();
+        let pod_shared = self.pod_shared.clone();
+        let resources = self.resources.clone();
+        let bundle_path = self.bundle.clone();
+        let module_name = self.module_name();
+        let shutdown = self.shutdown.clone();
+        let _ = thread::spawn(move || {
+            if wait_exit(&code, grace_period) {
+                return;
+            }
+            info!("grace period of {} is over, killing it", bundle_path);
+            if pod_shared.load(Ordering::SeqCst) {
+                // the shim thread cannot be stopped, the instance is detached as on SIGKILL
+                shutdown.kill();
+                pod::unregister_module(&module_name, bundle_path.as_str());
+                let _ = resources.lock().unwrap().release();
+                let (lock, cvar) = &*code;
+                let mut ec = lock.lock().unwrap();
+                if ec.is_none() {
+                    *ec = Some((137, Utc::now()));
+                }
+                drop(ec);
+                cvar.notify_all();
+            } else if let Some(fd) = pidfd.lock().unwrap().as_ref() {
+                if let Err(e) = fd.kill(SIGKILL as i32) {
+                    error!("failed to kill {}: {}", bundle_path, e);
+                }
             }
-            let ec = (*exit).unwrap();
-            channel.send(ec).unwrap();
         });
-
         Ok(())
     }
+
+    fn module_name(&self) -> String {
+        oci_utils::load_spec(self.bundle.clone())
+            .map(|spec| oci_utils::get_module_name(&spec))
+            .unwrap_or_default()
+    }
 }
 
 impl EngineGetter for Wasi {
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader, ErrorKind, IoSlice, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use crate::checksum;
+use crate::framing::Flags;
+use crate::utils::socket_utils;
+
+/// Preamble sent by a client to switch a Unix socket connection to the multiplexed protocol.
+/// Connections without it are served as a single raw request.
+pub const MUX_MAGIC: &[u8; 4] = b"RRMX";
+
+/// Time a request waits for its reply before it fails.
+pub const REPLY_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Largest payload of a frame. Peers announcing a longer frame are rejected before its payload
+/// is allocated; larger payloads are sent as references to the payload cache.
+pub const MAX_FRAME_LEN: usize = 256 * 1024 * 1024;
+
+const STATUS_OK: u8 = 0;
+const STATUS_ERROR: u8 = 1;
+/// The request did not match its checksum, see [`checksum::verify`].
+const STATUS_CHECKSUM: u8 = 2;
+
+/// Writes a frame: correlation id (u64), status (u8), payload length (u32), payload.
+/// All integers are little-endian. The status of a request carries its [`crate::framing`] flags.
+pub fn write_frame<W: Write>(writer: &mut W, id: u64, status: u8, payload: &[u8]) -> io::Result<()> {
+    write_frame_vectored(writer, id, status, &[payload])
+}
+
+/// Writes a frame whose payload is the concatenation of `segments`, header and segments are
+/// handed to the writer together (`writev` on sockets).
+pub fn write_frame_vectored<W: Write>(writer: &mut W, id: u64, status: u8, segments: &[&[u8]]) -> io::Result<()> {
+    let len: usize = segments.iter().map(|segment| segment.len()).sum();
+    check_frame_len(len)?;
+    let mut header = [0u8; 13];
+    header[..8].copy_from_slice(&id.to_le_bytes());
+    header[8] = status;
+    header[9..].copy_from_slice(&(len as u32).to_le_bytes());
+
+    let mut buffers = vec![&header[..]];
+    buffers.extend(segments.iter().copied().filter(|segment| !segment.is_empty()));
+    write_all_vectored(writer, &buffers)?;
+    writer.flush()
+}
+
+/// Writes all `buffers`, continuing after partial vectored writes.
+fn write_all_vectored<W: Write>(writer: &mut W, buffers: &[&[u8]]) -> io::Result<()> {
+    let mut index = 0;
+    let mut offset = 0;
+    while index < buffers.len() {
+        let slices: Vec<IoSlice> = std::iter::once(IoSlice::new(&buffers[index][offset..]))
+            .chain(buffers[index + 1..].iter().map(|buffer| IoSlice::new(buffer)))
+            .collect();
+        let mut written = match writer.write_vectored(&slices) {
+            Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "failed to write frame")),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        while index < buffers.len() && written >= buffers[index].len() - offset {
+            written -= buffers[index].len() - offset;
+            index += 1;
+            offset = 0;
+        }
+        offset += written;
+    }
+    Ok(())
+}
+
+fn check_frame_len(len: usize) -> io::Result<()> {
+    if len > MAX_FRAME_LEN {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("frame of {} bytes exceeds the limit of {} bytes", len, MAX_FRAME_LEN),
+        ));
+    }
+    Ok(())
+}
+
+/// Reads a frame, returns `None` if the peer closed the connection between frames.
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<(u64, u8, Vec<u8>)>> {
+    let mut header = [0u8; 13];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e),
+    }
+    let id = u64::from_le_bytes(header[..8].try_into().unwrap());
+    let len = u32::from_le_bytes(header[9..].try_into().unwrap()) as usize;
+    check_frame_len(len)?;
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some((id, header[8], payload)))
+}
+
+/// Serves multiplexed requests until the client closes the connection. Every request is passed
+/// to `handler` with its flags and answered with a frame carrying the same correlation id.
+pub fn serve<R, W, F>(reader: &mut R, writer: &mut W, mut handler: F) -> io::Result<()>
+where
+    R: Read,
+    W: Write,
+    F: FnMut(Flags, Vec<u8>) -> io::Result<Vec<u8>>,
+{
+    while let Some((id, flags, payload)) = read_frame(reader)? {
+        match handler(flags, payload) {
+            Ok(response) => write_frame(writer, id, STATUS_OK, &response)?,
+            Err(e) if checksum::is_mismatch(&e) => write_frame(writer, id, STATUS_CHECKSUM, e.to_string().as_bytes())?,
+            Err(e) => write_frame(writer, id, STATUS_ERROR, e.to_string().as_bytes())?,
+        }
+    }
+    Ok(())
+}
+
+/// Error of a request that never reached the target: its connection could not be opened or was
+/// closed before the request was written.
+#[derive(Debug)]
+pub struct Unsent(io::Error);
+
+impl fmt::Display for Unsent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "request not sent: {}", self.0)
+    }
+}
+
+impl std::error::Error for Unsent {}
+
+fn unsent(err: io::Error) -> io::Error {
+    io::Error::new(err.kind(), Unsent(err))
+}
+
+/// Whether a request failed before it was sent, the target cannot have run it.
+pub fn is_unsent(err: &io::Error) -> bool {
+    err.get_ref().map_or(false, |inner| inner.is::<Unsent>())
+}
+
+type Pending = Arc<Mutex<HashMap<u64, Sender<io::Result<Vec<u8>>>>>>;
+
+/// A persistent connection to a target's Unix socket shared by concurrent requests.
+/// Replies are routed back to the in-flight request by correlation id.
+pub struct MuxConnection {
+    writer: Mutex<UnixStream>,
+    pending: Pending,
+    next_id: AtomicU64,
+    closed: Arc<AtomicBool>,
+}
+
+impl MuxConnection {
+    /// Opens a connection to `socket_path`, failures are [`Unsent`].
+    pub fn connect(socket_path: &str) -> io::Result<MuxConnection> {
+        let mut stream = socket_utils::connect(socket_path).map_err(unsent)?;
+        stream.write_all(MUX_MAGIC).map_err(unsent)?;
+
+        let pending: Pending = Arc::new(Mutex::new(HashMap::new()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let reader = stream.try_clone().map_err(unsent)?;
+        let reader_pending = pending.clone();
+        let reader_closed = closed.clone();
+        let path = socket_path.to_string();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(reader);
+            loop {
+                match read_frame(&mut reader) {
+                    Ok(Some((id, status, payload))) => {
+                        let result = match status {
+                            STATUS_OK => Ok(payload),
+                            STATUS_CHECKSUM => Err(checksum::mismatch_error()),
+                            _ => Err(io::Error::new(ErrorKind::Other, String::from_utf8_lossy(&payload).to_string())),
+                        };
+                        match reader_pending.lock().unwrap().remove(&id) {
+                            Some(sender) => { let _ = sender.send(result); }
+                            None => warn!("dropping reply for unknown request {} on {}", id, path),
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        warn!("multiplexed connection to {} failed: {}", path, e);
+                        break;
+                    }
+                }
+            }
+            reader_closed.store(true, Ordering::SeqCst);
+            for (_, sender) in reader_pending.lock().unwrap().drain() {
+                let _ = sender.send(Err(io::Error::new(ErrorKind::BrokenPipe, "connection closed")));
+            }
+        });
+
+        Ok(MuxConnection {
+            writer: Mutex::new(stream),
+            pending,
+            next_id: AtomicU64::new(1),
+            closed,
+        })
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    /// Sends a request and blocks until its reply arrives.
+    pub fn request(&self, payload: &[u8]) -> io::Result<Vec<u8>> {
+        self.request_vectored(&[payload])
+    }
+
+    /// Sends a request whose payload is the concatenation of `segments`.
+    pub fn request_vectored(&self, segments: &[&[u8]]) -> io::Result<Vec<u8>> {
+        let sent = self.send(0, segments)?;
+        self.wait_reply(sent)
+    }
+
+    /// Writes a request and returns the receiver of its reply. An error means the request did not
+    /// reach the target, a closed connection is [`Unsent`]. A failed write may have left a partial
+    /// frame, which the target drops, the connection is closed.
+    fn send(&self, flags: Flags, segments: &[&[u8]]) -> io::Result<(u64, Receiver<io::Result<Vec<u8>>>)> {
+        check_frame_len(segments.iter().map(|segment| segment.len()).sum())?;
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let (sender, receiver) = channel();
+        self.pending.lock().unwrap().insert(id, sender);
+        if self.is_closed() {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(unsent(io::Error::new(ErrorKind::BrokenPipe, "connection closed")));
+        }
+
+        let written = {
+            let mut writer = self.writer.lock().unwrap();
+            let written = write_frame_vectored(&mut *writer, id, flags, segments);
+            if written.is_err() {
+                self.closed.store(true, Ordering::SeqCst);
+                let _ = writer.shutdown(std::net::Shutdown::Both);
+            }
+            written
+        };
+        if let Err(e) = written {
+            self.pending.lock().unwrap().remove(&id);
+            return Err(unsent(e));
+        }
+        Ok((id, receiver))
+    }
+
+    /// Waits up to [`REPLY_TIMEOUT`] for the reply of a sent request. A reply arriving later is
+    /// dropped.
+    fn wait_reply(&self, (id, receiver): (u64, Receiver<io::Result<Vec<u8>>>)) -> io::Result<Vec<u8>> {
+        match receiver.recv_timeout(REPLY_TIMEOUT) {
+            Ok(reply) => reply,
+            Err(RecvTimeoutError::Timeout) => {
+                self.pending.lock().unwrap().remove(&id);
+                Err(io::Error::new(ErrorKind::TimedOut, format!("no reply to request {} within {:?}", id, REPLY_TIMEOUT)))
+            }
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::new(ErrorKind::BrokenPipe, "connection closed")),
+        }
+    }
+}
+
+lazy_static! {
+    static ref POOL: Mutex<HashMap<String, Arc<MuxConnection>>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the pooled connection to `socket_path`, opening a new one if there is none or the
+/// previous one was closed.
+pub fn pooled_connection(socket_path: &str) -> io::Result<Arc<MuxConnection>> {
+    let mut pool = POOL.lock().unwrap();
+    if let Some(connection) = pool.get(socket_path) {
+        if !connection.is_closed() {
+            return Ok(connection.clone());
+        }
+    }
+    info!("opening multiplexed connection to {}", socket_path);
+    let connection = Arc::new(MuxConnection::connect(socket_path)?);
+    pool.insert(socket_path.to_string(), connection.clone());
+    Ok(connection)
+}
+
+/// Sends a request over the pooled connection to `socket_path`. A request that could not be
+/// written because the pooled connection broke is retried once on a fresh connection. Requests
+/// whose connection broke once they were sent are not retried, the target may have run them;
+/// [`is_unsent`] tells the failures of requests that were never sent apart.
+pub fn request(socket_path: &str, payload: &[u8]) -> io::Result<Vec<u8>> {
+    request_vectored(socket_path, &[payload])
+}
+
+/// Like [`request`], with the payload gathered from `segments`.
+pub fn request_vectored(socket_path: &str, segments: &[&[u8]]) -> io::Result<Vec<u8>> {
+    request_flagged(socket_path, 0, segments)
+}
+
+/// Like [`request_vectored`], announcing the encoding of the payload through `flags`.
+pub fn request_flagged(socket_path: &str, flags: Flags, segments: &[&[u8]]) -> io::Result<Vec<u8>> {
+    let connection = pooled_connection(socket_path)?;
+    match connection.send(flags, segments) {
+        Err(_) if connection.is_closed() => {
+            let connection = pooled_connection(socket_path)?;
+            let sent = connection.send(flags, segments)?;
+            connection.wait_reply(sent)
+        }
+        sent => connection.wait_reply(sent?),
+    }
+}
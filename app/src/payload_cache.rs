@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::FileExt;
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::sync::Mutex;
+use std::time::Instant;
+use lazy_static::lazy_static;
+use log::info;
+use sha2::{Digest, Sha256};
+
+/// Prefix of an encoded cache reference.
+pub const CACHE_MAGIC: &[u8; 4] = b"RRCH";
+
+/// Payloads below this size are sent as they are, a reference would not save a transfer.
+pub const MIN_CACHED_SIZE: usize = 64 * 1024;
+
+/// Bytes kept in the cache before unreferenced payloads are evicted, least recently used first.
+pub const DEFAULT_CAPACITY: u64 = 1 << 30;
+
+/// Largest payload a received reference is resolved for.
+pub const MAX_REFERENCED_LEN: u64 = DEFAULT_CAPACITY;
+
+/// Name of the memfds holding cached payloads, as shown by `/proc/<pid>/fd`.
+const MEMFD_NAME: &str = "rr-payload";
+
+const REF_LEN: usize = 4 + 32 + 4 + 4 + 8;
+
+/// A payload stored once in a memfd of the caching shim. Co-located receivers read it through
+/// `/proc/<pid>/fd/<fd>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheRef {
+    /// SHA-256 of the payload, the cache key.
+    pub digest: [u8; 32],
+    pub pid: u32,
+    pub fd: i32,
+    pub len: u64,
+}
+
+impl CacheRef {
+    /// Encodes the reference: magic, digest, pid (u32), fd (i32), payload length (u64).
+    /// All integers are little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(REF_LEN);
+        bytes.extend_from_slice(CACHE_MAGIC);
+        bytes.extend_from_slice(&self.digest);
+        bytes.extend_from_slice(&self.pid.to_le_bytes());
+        bytes.extend_from_slice(&self.fd.to_le_bytes());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<CacheRef> {
+        if bytes.len() != REF_LEN || &bytes[..4] != CACHE_MAGIC {
+            return None;
+        }
+        Some(CacheRef {
+            digest: bytes[4..36].try_into().ok()?,
+            pid: u32::from_le_bytes(bytes[36..40].try_into().ok()?),
+            fd: i32::from_le_bytes(bytes[40..44].try_into().ok()?),
+            len: u64::from_le_bytes(bytes[44..52].try_into().ok()?),
+        })
+    }
+}
+
+struct Entry {
+    file: File,
+    len: u64,
+    refs: usize,
+    last_used: Instant,
+}
+
+struct Cache {
+    entries: HashMap<[u8; 32], Entry>,
+    size: u64,
+    capacity: u64,
+}
+
+impl Cache {
+    /// Evicts unreferenced entries, least recently used first, until the cache fits its capacity.
+    fn evict(&mut self) {
+        while self.size > self.capacity {
+            let lru = self
+                .entries
+                .iter()
+                .filter(|(_, entry)| entry.refs == 0)
+                .min_by_key(|(_, entry)| entry.last_used)
+                .map(|(digest, _)| *digest);
+            match lru.and_then(|digest| self.entries.remove(&digest)) {
+                Some(entry) => self.size -= entry.len,
+                None => break,
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref CACHE: Mutex<Cache> = Mutex::new(Cache {
+        entries: HashMap::new(),
+        size: 0,
+        capacity: DEFAULT_CAPACITY,
+    });
+}
+
+/// Sets the number of bytes kept in the cache.
+pub fn set_capacity(capacity: u64) {
+    let mut cache = CACHE.lock().unwrap();
+    cache.capacity = capacity;
+    cache.evict();
+}
+
+/// Returns the number of cached payloads and their total size.
+pub fn usage() -> (usize, u64) {
+    let cache = CACHE.lock().unwrap();
+    (cache.entries.len(), cache.size)
+}
+
+/// A reference held on a cached payload, which is not evicted while leases on it exist.
+/// Every delivery of the reference to a receiver holds its own lease until the receiver answered.
+pub struct Lease {
+    reference: CacheRef,
+}
+
+impl Lease {
+    pub fn reference(&self) -> &CacheRef {
+        &self.reference
+    }
+}
+
+impl Clone for Lease {
+    fn clone(&self) -> Lease {
+        if let Some(entry) = CACHE.lock().unwrap().entries.get_mut(&self.reference.digest) {
+            entry.refs += 1;
+        }
+        Lease { reference: self.reference.clone() }
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut cache = CACHE.lock().unwrap();
+        if let Some(entry) = cache.entries.get_mut(&self.reference.digest) {
+            entry.refs -= 1;
+            entry.last_used = Instant::now();
+        }
+        cache.evict();
+    }
+}
+
+fn create_memfd(payload: &[u8]) -> io::Result<File> {
+    let name = CString::new(MEMFD_NAME).unwrap();
+    let fd = unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { File::from_raw_fd(fd) };
+    file.write_all(payload)?;
+    // receivers may rely on the content matching the digest
+    let seals = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE | libc::F_SEAL_SEAL;
+    if unsafe { libc::fcntl(file.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(file)
+}
+
+/// Stores `payload` in the cache, unless a payload with the same content is cached already, and
+/// returns a lease on it.
+pub fn lease(payload: &[u8]) -> io::Result<Lease> {
+    let digest: [u8; 32] = Sha256::digest(payload).into();
+    let mut cache = CACHE.lock().unwrap();
+    if let Some(entry) = cache.entries.get_mut(&digest) {
+        entry.refs += 1;
+        entry.last_used = Instant::now();
+        let reference = CacheRef { digest, pid: std::process::id(), fd: entry.file.as_raw_fd(), len: entry.len };
+        return Ok(Lease { reference });
+    }
+
+    let file = create_memfd(payload)?;
+    let len = payload.len() as u64;
+    let reference = CacheRef { digest, pid: std::process::id(), fd: file.as_raw_fd(), len };
+    info!("cached payload of {} bytes in memfd {}", len, reference.fd);
+    cache.entries.insert(digest, Entry { file, len, refs: 1, last_used: Instant::now() });
+    cache.size += len;
+    cache.evict();
+    Ok(Lease { reference })
+}
+
+/// Reads a cached payload, from the memfd of this process or through `/proc` from the shim that
+/// cached it. Only payload memfds are read, and only if their size is the referenced length. The
+/// content is checked against the digest since file descriptors are reused once an entry was
+/// evicted.
+pub fn read(reference: &CacheRef) -> io::Result<Vec<u8>> {
+    let payload = if reference.pid == std::process::id() {
+        let cache = CACHE.lock().unwrap();
+        let entry = cache
+            .entries
+            .get(&reference.digest)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, "cached payload was evicted"))?;
+        read_file(&entry.file, reference.len)?
+    } else {
+        let path = format!("/proc/{}/fd/{}", reference.pid, reference.fd);
+        let target = fs::read_link(&path)?;
+        if !target.to_string_lossy().starts_with(&format!("/memfd:{} ", MEMFD_NAME)) {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, format!("{} is not a cached payload", path)));
+        }
+        read_file(&File::open(path)?, reference.len)?
+    };
+    let digest: [u8; 32] = Sha256::digest(&payload).into();
+    if digest != reference.digest {
+        return Err(io::Error::new(ErrorKind::InvalidData, "cached payload does not match its digest"));
+    }
+    Ok(payload)
+}
+
+fn read_file(file: &File, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_REFERENCED_LEN || file.metadata()?.len() != len {
+        return Err(io::Error::new(ErrorKind::InvalidData, "cached payload does not have the referenced length"));
+    }
+    let mut payload = vec![0u8; len as usize];
+    file.read_exact_at(&mut payload, 0)?;
+    Ok(payload)
+}
+
+/// Replaces a cache reference received from the process `peer` by the payload it references.
+/// Callers only pass inputs announced as references by the sender, see
+/// [`crate::framing::CACHE_REF`]. A process can only reference payloads it cached itself.
+pub fn materialize(input: &[u8], peer: u32) -> io::Result<Vec<u8>> {
+    let reference = CacheRef::decode(input)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "malformed cache reference"))?;
+    if reference.pid != peer {
+        return Err(io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("process {} sent a reference to the cache of process {}", peer, reference.pid),
+        ));
+    }
+    read(&reference)
+}
@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::io::{self, BufReader, ErrorKind, Read};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use crate::error::TransferError;
+use crate::host_context::{HostContext, TransferConfig};
+use crate::mux::{self, MUX_MAGIC};
+use crate::peer_auth::PeerPolicy;
+use crate::transfer_policy;
+use crate::utils::socket_utils;
+
+/// Prefix of an encoded payload reference.
+pub const REF_MAGIC: &[u8; 4] = b"RRRF";
+
+/// Time a published payload is retained when the guest does not pass a TTL.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(60);
+
+/// Interval at which a range server checks whether it was closed while no receiver connects.
+const CLOSE_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A payload retained by the instance that published it, fetched by receivers on demand.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PayloadRef {
+    /// Socket of the publishing instance serving range requests.
+    pub socket_path: String,
+    /// Random id of the payload, so that receivers cannot guess the payloads of others.
+    pub id: u128,
+    pub len: u64,
+}
+
+impl PayloadRef {
+    /// Encodes the reference: magic, id (u128), payload length (u64), socket path length (u16),
+    /// socket path. All integers are little-endian.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = REF_MAGIC.to_vec();
+        bytes.extend_from_slice(&self.id.to_le_bytes());
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes.extend_from_slice(&(self.socket_path.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(self.socket_path.as_bytes());
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Option<PayloadRef> {
+        if bytes.len() < 30 || &bytes[..4] != REF_MAGIC {
+            return None;
+        }
+        let id = u128::from_le_bytes(bytes[4..20].try_into().ok()?);
+        let len = u64::from_le_bytes(bytes[20..28].try_into().ok()?);
+        let path_len = u16::from_le_bytes(bytes[28..30].try_into().ok()?) as usize;
+        let socket_path = String::from_utf8(bytes.get(30..30 + path_len)?.to_vec()).ok()?;
+        Some(PayloadRef { socket_path, id, len })
+    }
+}
+
+/// Socket serving range requests for the payloads published by the instance of `bundle_path`.
+pub fn socket_path(bundle_path: &str) -> String {
+    format!("{}.refs.sock", bundle_path)
+}
+
+struct Published {
+    data: Arc<Vec<u8>>,
+    expires: Instant,
+    /// Socket of the range server of the publishing instance.
+    server: String,
+}
+
+/// Who may fetch the payloads of an instance: the same peers and sources it accepts input from.
+struct Access {
+    peers: PeerPolicy,
+    config: TransferConfig,
+    state_root: String,
+}
+
+impl Access {
+    fn authorize(&self, socket: &UnixStream) -> io::Result<()> {
+        let peer = self.peers.authorize(socket)?;
+        if self.config.restricts() {
+            let receiver = transfer_policy::peer_function(&self.state_root, peer.pid)
+                .unwrap_or_else(|| transfer_policy::UNKNOWN_SOURCE.to_string());
+            self.config
+                .authorize(&self.config.function_name, &receiver)
+                .map_err(|e| io::Error::new(ErrorKind::PermissionDenied, e.to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+lazy_static! {
+    static ref PUBLISHED: Mutex<HashMap<u128, Published>> = Mutex::new(HashMap::new());
+    /// Range servers running in this shim by socket path, with the flag stopping them.
+    static ref SERVERS: Mutex<HashMap<String, Arc<AtomicBool>>> = Mutex::new(HashMap::new());
+}
+
+/// Returns the socket serving range requests for payloads of the instance, starting it next to
+/// its bundle on first use.
+fn server_path(context: &HostContext) -> io::Result<String> {
+    let path = socket_path(&context.bundle_path);
+    let mut servers = SERVERS.lock().unwrap();
+    if servers.contains_key(&path) {
+        return Ok(path);
+    }
+    let listener = socket_utils::bind(&path, socket_utils::is_abstract(&context.spec))?;
+    // Accepts without blocking so that closing the server stops the wait for receivers
+    listener.set_nonblocking(true)?;
+    info!("serving payload references on {}", path);
+    let access = Arc::new(Access {
+        peers: PeerPolicy::from_spec(&context.spec),
+        config: context.config.clone(),
+        state_root: context.state_root.clone(),
+    });
+    let closed = Arc::new(AtomicBool::new(false));
+    servers.insert(path.clone(), closed.clone());
+    let server = path.clone();
+    thread::spawn(move || serve(listener, server, access, closed));
+    Ok(path)
+}
+
+fn serve(listener: UnixListener, server: String, access: Arc<Access>, closed: Arc<AtomicBool>) {
+    while !closed.load(Ordering::SeqCst) {
+        let mut socket = match listener.accept() {
+            Ok((socket, _)) => socket,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                thread::sleep(CLOSE_POLL_INTERVAL);
+                continue;
+            }
+            Err(e) => {
+                warn!("payload reference connection failed: {}", e);
+                continue;
+            }
+        };
+        // Only receivers allowed to send to the instance may read what it published
+        if let Err(e) = socket.set_nonblocking(false).and_then(|_| access.authorize(&socket)) {
+            warn!("Unauthorized payload reference connection: {}", e);
+            continue;
+        }
+        let server = server.clone();
+        thread::spawn(move || {
+            let mut reader = BufReader::new(socket.try_clone()?);
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic)?;
+            if &magic != MUX_MAGIC {
+                return Err(io::Error::new(ErrorKind::InvalidData, "expected multiplexed connection"));
+            }
+            mux::serve(&mut reader, &mut socket, |request| serve_range(&server, request))
+        });
+    }
+}
+
+/// Handles a range request for a payload published behind `server`: id (u128), offset (u64),
+/// maximum length (u32).
+fn serve_range(server: &str, request: Vec<u8>) -> io::Result<Vec<u8>> {
+    if request.len() != 28 {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "malformed range request"));
+    }
+    let id = u128::from_le_bytes(request[..16].try_into().unwrap());
+    let offset = u64::from_le_bytes(request[16..24].try_into().unwrap());
+    let len = u32::from_le_bytes(request[24..].try_into().unwrap());
+    read_published(Some(server), id, offset, len).map_err(|e| io::Error::new(ErrorKind::NotFound, e.to_string()))
+}
+
+fn purge_expired(published: &mut HashMap<u128, Published>) {
+    let now = Instant::now();
+    published.retain(|_, payload| payload.expires > now);
+}
+
+/// Retains `data` for `ttl` and returns the reference receivers fetch it with. The range server
+/// of the instance is started next to its bundle.
+pub fn publish(data: Vec<u8>, ttl: Duration, context: &HostContext) -> Result<PayloadRef, TransferError> {
+    let socket_path = server_path(context)?;
+    let id = rand::random::<u128>();
+    let len = data.len() as u64;
+    let mut published = PUBLISHED.lock().unwrap();
+    purge_expired(&mut published);
+    published.insert(id, Published { data: Arc::new(data), expires: Instant::now() + ttl, server: socket_path.clone() });
+    Ok(PayloadRef { socket_path, id, len })
+}
+
+/// Releases a published payload before its TTL expires.
+pub fn release(id: u128) {
+    PUBLISHED.lock().unwrap().remove(&id);
+}
+
+/// Stops the range server at `socket_path`, drops the payloads published behind it and removes
+/// its socket. The socket is removed even if the server ran in another process.
+pub fn close(socket_path: &str) {
+    if let Some(closed) = SERVERS.lock().unwrap().remove(socket_path) {
+        closed.store(true, Ordering::SeqCst);
+    }
+    PUBLISHED.lock().unwrap().retain(|_, payload| payload.server != socket_path);
+    socket_utils::remove(socket_path);
+}
+
+/// Reads up to `len` bytes at `offset` of a payload published by this shim.
+pub fn read_range(id: u128, offset: u64, len: u32) -> Result<Vec<u8>, TransferError> {
+    read_published(None, id, offset, len)
+}
+
+/// Reads a range of a published payload, only if it was published behind `server` when given.
+fn read_published(server: Option<&str>, id: u128, offset: u64, len: u32) -> Result<Vec<u8>, TransferError> {
+    let data = {
+        let mut published = PUBLISHED.lock().unwrap();
+        purge_expired(&mut published);
+        published
+            .get(&id)
+            .filter(|payload| server.map_or(true, |server| payload.server == server))
+            .map(|payload| payload.data.clone())
+            .ok_or(TransferError::PayloadExpired(id))?
+    };
+    let start = (offset as usize).min(data.len());
+    let end = start.saturating_add(len as usize).min(data.len());
+    Ok(data[start..end].to_vec())
+}
+
+/// Fetches up to `len` bytes at `offset` of a referenced payload, from the memory of this shim if
+/// one of its instances published the payload, otherwise from the publishing instance.
+pub fn fetch(reference: &PayloadRef, offset: u64, len: u32) -> Result<Vec<u8>, TransferError> {
+    if SERVERS.lock().unwrap().contains_key(&reference.socket_path) {
+        return read_published(Some(&reference.socket_path), reference.id, offset, len);
+    }
+    let mut request = Vec::with_capacity(28);
+    request.extend_from_slice(&reference.id.to_le_bytes());
+    request.extend_from_slice(&offset.to_le_bytes());
+    request.extend_from_slice(&len.to_le_bytes());
+    mux::request(&reference.socket_path, &request).map_err(|e| TransferError::Communication(e.to_string()))
+}
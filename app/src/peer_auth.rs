@@ -0,0 +1,140 @@
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use log::warn;
+use oci_spec::runtime::Spec;
+use crate::utils::oci_utils;
+
+/// Annotation listing the cgroups, comma-separated, whose processes may connect to the socket of
+/// a function. Paths starting with `/` match the cgroup and its descendants, other entries match a
+/// path component such as a systemd slice. Defaults to the sandbox cgroup of the container.
+pub const PEER_CGROUPS_ANNOTATION: &str = "peer.cgroups";
+
+/// Credentials of the process at the other end of a Unix socket, as of its `connect`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCredentials {
+    pub pid: u32,
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Reads the credentials of the peer with `SO_PEERCRED`.
+pub fn peer_credentials(socket: &UnixStream) -> io::Result<PeerCredentials> {
+    let mut cred = libc::ucred { pid: 0, uid: 0, gid: 0 };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            socket.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(PeerCredentials { pid: cred.pid as u32, uid: cred.uid, gid: cred.gid })
+}
+
+/// Returns the cgroup paths of a process, one per hierarchy, from `/proc/<pid>/cgroup`.
+pub fn process_cgroups(pid: u32) -> io::Result<Vec<String>> {
+    let content = fs::read_to_string(format!("/proc/{}/cgroup", pid))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.splitn(3, ':').nth(2))
+        .map(str::to_string)
+        .collect())
+}
+
+/// Whether the cgroup `path` of a peer is matched by an allowed entry.
+pub fn cgroup_matches(path: &str, allowed: &str) -> bool {
+    if allowed.starts_with('/') {
+        let allowed = allowed.trim_end_matches('/');
+        return allowed.is_empty() || path == allowed || path.starts_with(&format!("{}/", allowed));
+    }
+    path.split('/').any(|component| component == allowed)
+}
+
+/// Returns the sandbox cgroup of a container: the parent of its cgroup for a cgroupfs path, the
+/// slice for a systemd `slice:prefix:name` path.
+pub fn sandbox_cgroup(cgroups_path: &str) -> Option<String> {
+    if cgroups_path.contains(':') {
+        return cgroups_path.split(':').next().filter(|slice| !slice.is_empty()).map(str::to_string);
+    }
+    Path::new(cgroups_path)
+        .parent()
+        .map(|parent| parent.display().to_string())
+        .filter(|parent| parent.len() > 1)
+}
+
+/// Decides which local processes may send input to a function over its Unix socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PeerPolicy {
+    /// Users peers may run as.
+    pub uids: Vec<u32>,
+    /// Cgroups peers must belong to, only this process may connect when empty.
+    pub cgroups: Vec<String>,
+}
+
+impl PeerPolicy {
+    /// Reads the allowed cgroups from the `peer.cgroups` annotation, falling back to the sandbox
+    /// cgroup of the container. Peers must run as root or as the user of this shim. Without either,
+    /// no other process is allowed.
+    pub fn from_spec(spec: &Spec) -> PeerPolicy {
+        let annotated = oci_utils::get_wasm_annotations(spec, PEER_CGROUPS_ANNOTATION);
+        let cgroups: Vec<String> = if annotated.is_empty() {
+            spec.linux()
+                .as_ref()
+                .and_then(|linux| linux.cgroups_path().as_ref())
+                .and_then(|path| sandbox_cgroup(&path.display().to_string()))
+                .into_iter()
+                .collect()
+        } else {
+            annotated.split(',').map(|entry| entry.trim().to_string()).filter(|entry| !entry.is_empty()).collect()
+        };
+        if cgroups.is_empty() {
+            warn!("no sandbox cgroup derived and none annotated through {}, denying all peers", PEER_CGROUPS_ANNOTATION);
+        }
+        let mut uids = vec![0];
+        let uid = unsafe { libc::geteuid() };
+        if uid != 0 {
+            uids.push(uid);
+        }
+        PeerPolicy { uids, cgroups }
+    }
+
+    /// Checks the credentials of the peer of `socket`. Connections from this process are always
+    /// accepted.
+    pub fn authorize(&self, socket: &UnixStream) -> io::Result<PeerCredentials> {
+        let peer = peer_credentials(socket)?;
+        if peer.pid == std::process::id() {
+            return Ok(peer);
+        }
+        if !self.uids.contains(&peer.uid) {
+            return Err(rejected(&peer, "user not allowed"));
+        }
+        if self.cgroups.is_empty() {
+            return Err(rejected(&peer, "no cgroup allowed"));
+        }
+        let cgroups = process_cgroups(peer.pid)?;
+        if !self.admits_cgroups(&cgroups) {
+            return Err(rejected(&peer, &format!("cgroups {:?} not allowed", cgroups)));
+        }
+        Ok(peer)
+    }
+
+    /// Whether a process in `cgroups` belongs to an allowed cgroup.
+    pub fn admits_cgroups(&self, cgroups: &[String]) -> bool {
+        cgroups
+            .iter()
+            .any(|path| self.cgroups.iter().any(|allowed| cgroup_matches(path, allowed)))
+    }
+}
+
+fn rejected(peer: &PeerCredentials, reason: &str) -> io::Error {
+    warn!("rejected connection from pid {} uid {}: {}", peer.pid, peer.uid, reason);
+    io::Error::new(ErrorKind::PermissionDenied, format!("peer {} rejected: {}", peer.pid, reason))
+}
@@ -0,0 +1,326 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use containerd_shim_wasm::sandbox::oci;
+use lazy_static::lazy_static;
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use wasmedge_sdk::Vm;
+use crate::error::TransferError;
+use crate::host_context::HostContext;
+use crate::utils::oci_utils;
+use crate::workflow::WorkflowNode;
+
+/// Annotation enabling the pod-shared mode. All Roadrunner containers of a pod sandbox that set
+/// it are hosted by the shim process itself, each one as a named module instance of the shared `Vm`.
+pub const POD_SHARED_ANNOTATION: &str = "pod.shared";
+
+/// WASI arguments, environment and preopened directories of a container. The pod-shared `Vm` has
+/// a single WASI module, which is set up with the configuration of a module whenever it enters
+/// the VM.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct WasiConfig {
+    pub args: Vec<String>,
+    pub envs: Vec<String>,
+    /// Directories as `guest:host`, the rootfs of the container is preopened as `/`.
+    pub preopens: Vec<String>,
+}
+
+impl WasiConfig {
+    pub fn from_spec(spec: &Spec) -> WasiConfig {
+        let mut preopens = vec![format!("/:{}", oci::get_root(spec).display())];
+        preopens.extend(oci_utils::get_wasm_mounts(spec).into_iter().map(str::to_string));
+        WasiConfig {
+            args: oci_utils::arg_to_wasi(spec),
+            envs: oci_utils::env_to_wasi(spec),
+            preopens,
+        }
+    }
+
+    /// Sets up the WASI module of `vm` with this configuration.
+    pub fn apply(&self, vm: &mut Vm) -> Result<(), TransferError> {
+        vm.wasi_module()?.initialize(
+            Some(self.args.iter().map(String::as_str).collect()),
+            Some(self.envs.iter().map(String::as_str).collect()),
+            Some(self.preopens.iter().map(String::as_str).collect()),
+        );
+        Ok(())
+    }
+}
+
+/// A module instance hosted in the pod-shared `Vm`.
+#[derive(Clone, Debug)]
+pub struct PodModule {
+    pub module_name: String,
+    pub bundle_path: String,
+    /// Function name advertised through the `target.function` annotation, if any.
+    pub function_name: String,
+    /// Export invoked on payloads sent to this module, from the `target.entrypoint` annotation.
+    pub entrypoint: String,
+    /// Wiring of the module if it is part of a workflow.
+    pub workflow: Option<WorkflowNode>,
+    /// Context the host functions act on when called by this module.
+    pub context: Arc<HostContext>,
+    pub wasi: WasiConfig,
+}
+
+/// Entry into the `Vm` of the process, see [`enter`].
+#[derive(Debug, Default)]
+struct EntryState {
+    /// Whether a thread runs guest code in the `Vm`, one module or connection at a time.
+    entered: bool,
+    /// Modules whose guest code released the `Vm` while waiting in a host call, see [`suspend`].
+    suspended: HashSet<String>,
+    /// Modules whose shutdown hook runs alongside their guest code, see [`interrupt`].
+    interrupted: HashSet<String>,
+}
+
+lazy_static! {
+    static ref POD_MODULES: Mutex<HashMap<String, PodModule>> = Mutex::new(HashMap::new());
+    static ref VM_ENTRY: (Mutex<EntryState>, Condvar) = (Mutex::new(EntryState::default()), Condvar::new());
+}
+
+/// Modules of the pod-shared `Vm` running guest code on a thread: the module that entered, then
+/// the modules it called within the VM.
+struct Entered {
+    vm: Vm,
+    modules: Vec<String>,
+    suspended: bool,
+}
+
+thread_local! {
+    static ENTERED: RefCell<Option<Entered>> = RefCell::new(None);
+}
+
+pub fn is_pod_shared(spec: &Spec) -> bool {
+    oci_utils::get_wasm_annotations(spec, POD_SHARED_ANNOTATION) == "true"
+}
+
+pub fn register_module(module_name: &str, context: Arc<HostContext>) {
+    let spec = &context.spec;
+    let module = PodModule {
+        module_name: module_name.to_string(),
+        bundle_path: context.bundle_path.clone(),
+        function_name: oci_utils::get_wasm_annotations(spec, "target.function").replace("/", ""),
+        entrypoint: oci_utils::get_wasm_annotations(spec, "target.entrypoint"),
+        workflow: context.node.clone(),
+        wasi: WasiConfig::from_spec(spec),
+        context: context.clone(),
+    };
+    info!("pod module registered {:?}", module);
+    POD_MODULES.lock().unwrap().insert(module_name.to_string(), module);
+}
+
+/// Removes the module unless it has been registered again by another bundle in the meantime.
+pub fn unregister_module(module_name: &str, bundle_path: &str) {
+    let mut modules = POD_MODULES.lock().unwrap();
+    if modules.get(module_name).map_or(false, |module| module.bundle_path == bundle_path) {
+        modules.remove(module_name);
+    }
+}
+
+pub fn is_registered(module_name: &str) -> bool {
+    POD_MODULES.lock().unwrap().contains_key(module_name)
+}
+
+pub fn get_module(module_name: &str) -> Option<PodModule> {
+    POD_MODULES.lock().unwrap().get(module_name).cloned()
+}
+
+/// Returns the co-hosted module that advertises a target function, mirroring the discovery done
+/// by `find_function_metadata` for bundles. The sender itself is never returned; among several
+/// candidates the module whose name sorts first is picked, so every call resolves the same one.
+pub fn find_target_module(sender: &str) -> Option<PodModule> {
+    POD_MODULES
+        .lock()
+        .unwrap()
+        .values()
+        .filter(|module| module.module_name != sender && !module.function_name.is_empty())
+        .min_by(|a, b| a.module_name.cmp(&b.module_name))
+        .cloned()
+}
+
+/// Enters the pod-shared `Vm` to run guest code of `module_name`: waits until no other module
+/// runs and the module is not suspended, then sets up the WASI module with the configuration of
+/// `module_name`. The module runs while the returned entry is held; host functions called by it
+/// are already inside and must not enter again. Fails once the module was unregistered, e.g. by a
+/// kill.
+pub fn enter(vm: &mut Vm, module_name: &str) -> Result<VmEntry, TransferError> {
+    let entry = acquire(Some(module_name));
+    let module = get_module(module_name).ok_or_else(|| TransferError::ModuleNotFound(module_name.to_string()))?;
+    module.wasi.apply(vm)?;
+    ENTERED.with(|entered| {
+        *entered.borrow_mut() = Some(Entered { vm: vm.clone(), modules: vec![module_name.to_string()], suspended: false });
+    });
+    Ok(entry)
+}
+
+/// Waits until no other thread runs guest code in the `Vm` of this process. Forked instances
+/// take it to serve their connections one at a time, pod-shared modules through [`enter`].
+pub fn lock_vm() -> VmEntry {
+    acquire(None)
+}
+
+/// Held while a thread runs guest code in the `Vm` of the process.
+#[derive(Debug)]
+pub struct VmEntry(());
+
+impl Drop for VmEntry {
+    fn drop(&mut self) {
+        ENTERED.with(|entered| entered.borrow_mut().take());
+        entry_state().entered = false;
+        VM_ENTRY.1.notify_all();
+    }
+}
+
+fn acquire(module_name: Option<&str>) -> VmEntry {
+    let mut state = entry_state();
+    while state.entered || module_name.map_or(false, |name| state.suspended.contains(name)) {
+        state = wait_entry(state);
+    }
+    state.entered = true;
+    VmEntry(())
+}
+
+fn entry_state() -> MutexGuard<'static, EntryState> {
+    // a guest trapping while inside does not leave the VM unusable for the others
+    VM_ENTRY.0.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn wait_entry(state: MutexGuard<'static, EntryState>) -> MutexGuard<'static, EntryState> {
+    VM_ENTRY.1.wait(state).unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Releases the pod-shared `Vm` while the guest code of this thread waits in a blocking host call,
+/// so that the modules it waits for, e.g. a target hosted in the same VM and served through its
+/// socket, can run meanwhile. The modules of this thread stay out of the VM until the returned
+/// guard entered it again. Does nothing outside of a pod-shared module or if already suspended.
+pub fn suspend() -> Option<Suspension> {
+    let modules = ENTERED.with(|entered| {
+        let mut entered = entered.borrow_mut();
+        let entered = entered.as_mut().filter(|entered| !entered.suspended)?;
+        entered.suspended = true;
+        Some(entered.modules.clone())
+    })?;
+    let mut state = entry_state();
+    // a shutdown hook interrupting the modules keeps the VM until it returned
+    while modules.iter().any(|module| state.interrupted.contains(module)) {
+        state = wait_entry(state);
+    }
+    state.entered = false;
+    state.suspended.extend(modules.iter().cloned());
+    drop(state);
+    VM_ENTRY.1.notify_all();
+    Some(Suspension { modules })
+}
+
+/// Enters the pod-shared `Vm` again once dropped, see [`suspend`].
+#[derive(Debug)]
+pub struct Suspension {
+    modules: Vec<String>,
+}
+
+impl Drop for Suspension {
+    fn drop(&mut self) {
+        let mut state = entry_state();
+        while state.entered {
+            state = wait_entry(state);
+        }
+        state.entered = true;
+        for module in &self.modules {
+            state.suspended.remove(module);
+        }
+        drop(state);
+        ENTERED.with(|entered| {
+            if let Some(entered) = entered.borrow_mut().as_mut() {
+                entered.suspended = false;
+                // the modules that ran meanwhile set up the WASI module with their configuration
+                if let Some(module) = get_module(&entered.modules[0]) {
+                    if let Err(e) = module.wasi.apply(&mut entered.vm) {
+                        warn!("failed to set up WASI of {} again: {}", module.module_name, e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Whether the guest code of `module_name` waits in a host call with the `Vm` released. Calls
+/// into the module within the VM would run it again before it returned.
+pub fn is_suspended(module_name: &str) -> bool {
+    entry_state().suspended.contains(module_name)
+}
+
+/// Marks `module_name` as running guest code on this thread while the returned guard is held,
+/// for a call into it within the VM, so that it is suspended along with the calling module.
+pub fn nest(module_name: &str) -> Nested {
+    let nested = ENTERED.with(|entered| match entered.borrow_mut().as_mut() {
+        Some(entered) => {
+            entered.modules.push(module_name.to_string());
+            true
+        }
+        None => false,
+    });
+    Nested(nested)
+}
+
+#[derive(Debug)]
+pub struct Nested(bool);
+
+impl Drop for Nested {
+    fn drop(&mut self) {
+        if !self.0 {
+            return;
+        }
+        ENTERED.with(|entered| {
+            if let Some(entered) = entered.borrow_mut().as_mut() {
+                entered.modules.pop();
+            }
+        });
+    }
+}
+
+/// Lets the shutdown hook of `module_name` run alongside the guest code of the module: a running
+/// module does not suspend until the returned guard is dropped, a suspended module has the hook
+/// enter the `Vm` in its place.
+pub fn interrupt(vm: &mut Vm, module_name: &str) -> Interruption {
+    let mut state = entry_state();
+    loop {
+        if !state.suspended.contains(module_name) {
+            state.interrupted.insert(module_name.to_string());
+            return Interruption { module_name: module_name.to_string(), entered: false };
+        }
+        if !state.entered {
+            state.entered = true;
+            drop(state);
+            if let Some(module) = get_module(module_name) {
+                if let Err(e) = module.wasi.apply(vm) {
+                    warn!("failed to set up WASI of {}: {}", module_name, e);
+                }
+            }
+            return Interruption { module_name: module_name.to_string(), entered: true };
+        }
+        state = wait_entry(state);
+    }
+}
+
+/// Ends the interruption of a module once dropped, see [`interrupt`].
+#[derive(Debug)]
+pub struct Interruption {
+    module_name: String,
+    entered: bool,
+}
+
+impl Drop for Interruption {
+    fn drop(&mut self) {
+        let mut state = entry_state();
+        match self.entered {
+            true => state.entered = false,
+            false => {
+                state.interrupted.remove(&self.module_name);
+            }
+        }
+        drop(state);
+        VM_ENTRY.1.notify_all();
+    }
+}
@@ -0,0 +1,159 @@
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Error};
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use redis::{Client, Commands, Connection, RedisResult};
+use crate::utils::oci_utils;
+
+/// Annotation pointing the shim at the Redis registry shared between nodes, e.g.
+/// `redis://registry:6379/0`. Without it inter-node addresses come from annotations only.
+pub const REGISTRY_ANNOTATION: &str = "registry.url";
+
+/// Annotation setting the time in seconds a published address outlives its last heartbeat.
+pub const REGISTRY_TTL_ANNOTATION: &str = "registry.ttl";
+
+pub const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// Prefix of the keys functions are published under, as `<prefix>:<function>:<instance>`.
+pub const KEY_PREFIX: &str = "roadrunner:function";
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Registry of function addresses shared by the shims of all nodes. Every instance of a function
+/// serving a transfer publishes the address it is bound to under a key of its own, which expires
+/// unless refreshed by a heartbeat, so the addresses of crashed shims and lost nodes drop out on
+/// their own. Receivers on other nodes resolve the addresses of their sources to connect to them.
+#[derive(Debug, Clone)]
+pub struct Registry {
+    client: Client,
+    ttl: Duration,
+}
+
+impl Registry {
+    pub fn new(url: &str, ttl: Duration) -> Result<Registry, Error> {
+        let client = Client::open(url).map_err(|e| anyhow!("invalid registry url `{}`: {}", url, e))?;
+        Ok(Registry { client, ttl: ttl.max(Duration::from_secs(1)) })
+    }
+
+    /// Reads the registry from the `registry.url` and `registry.ttl` annotations, `Ok(None)` if
+    /// no registry is configured.
+    pub fn from_spec(spec: &Spec) -> Result<Option<Registry>, Error> {
+        let url = oci_utils::get_wasm_annotations(spec, REGISTRY_ANNOTATION);
+        if url.is_empty() {
+            return Ok(None);
+        }
+        let ttl = match oci_utils::get_wasm_annotations(spec, REGISTRY_TTL_ANNOTATION).as_str() {
+            "" => DEFAULT_TTL,
+            ttl => Duration::from_secs(ttl.parse().map_err(|_| anyhow!("invalid registry ttl `{}`", ttl))?),
+        };
+        Registry::new(&url, ttl).map(Some)
+    }
+
+    pub fn ttl(&self) -> Duration {
+        self.ttl
+    }
+
+    fn connection(&self) -> RedisResult<Connection> {
+        self.client.get_connection_with_timeout(CONNECT_TIMEOUT)
+    }
+
+    /// Publishes the address of an instance of `function` for the TTL of the registry.
+    pub fn publish(&self, function: &str, instance: &str, address: &str) -> RedisResult<()> {
+        self.connection()?.set_ex(key(function, instance), address, self.ttl.as_secs())
+    }
+
+    /// Removes the address of an instance of `function` before its TTL runs out.
+    pub fn withdraw(&self, function: &str, instance: &str) -> RedisResult<()> {
+        self.connection()?.del(key(function, instance))
+    }
+
+    /// Returns the addresses of all live instances of `function`, sorted.
+    pub fn resolve(&self, function: &str) -> RedisResult<Vec<String>> {
+        let mut connection = self.connection()?;
+        let keys: Vec<String> = connection.scan_match::<_, String>(key(function, "*"))?.collect();
+        let mut addresses = Vec::with_capacity(keys.len());
+        for key in keys {
+            // keys expiring between the scan and the read are skipped
+            if let Some(address) = connection.get::<_, Option<String>>(&key)? {
+                addresses.push(address);
+            }
+        }
+        addresses.sort();
+        addresses.dedup();
+        Ok(addresses)
+    }
+
+    /// Publishes the address of an instance of `function` and keeps it alive until the returned
+    /// heartbeat is dropped. The address is refreshed three times per TTL so that a single missed
+    /// beat does not expire it.
+    pub fn heartbeat(&self, function: &str, instance: &str, address: &str) -> Heartbeat {
+        let stop = Arc::new(AtomicBool::new(false));
+        let registry = self.clone();
+        let (function, instance, address) = (function.to_string(), instance.to_string(), address.to_string());
+        let beating = stop.clone();
+        let handle = thread::spawn(move || {
+            let interval = registry.ttl / 3;
+            while !beating.load(Ordering::SeqCst) {
+                if let Err(e) = registry.publish(&function, &instance, &address) {
+                    warn!("failed to publish {} of {} to the registry: {}", address, function, e);
+                }
+                let next = Instant::now() + interval;
+                while !beating.load(Ordering::SeqCst) && Instant::now() < next {
+                    thread::park_timeout(Duration::from_millis(100).min(interval));
+                }
+            }
+            if let Err(e) = registry.withdraw(&function, &instance) {
+                warn!("failed to withdraw {} of {} from the registry: {}", address, function, e);
+            }
+        });
+        Heartbeat { stop, handle: Some(handle) }
+    }
+}
+
+fn key(function: &str, instance: &str) -> String {
+    format!("{}:{}:{}", KEY_PREFIX, function, instance)
+}
+
+/// Name an instance publishes its address under: the host and the container id, the name of its
+/// bundle directory.
+pub fn instance_name(bundle_path: &str) -> String {
+    let host = hostname::get().map(|host| host.to_string_lossy().to_string()).unwrap_or_default();
+    let container = Path::new(bundle_path.trim_end_matches('/'))
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    format!("{}:{}", host, container)
+}
+
+/// Returns the address receivers on other nodes reach a listener bound to `bound` at: the host
+/// name of this node if the listener is bound to all interfaces.
+pub fn reachable_address(bound: SocketAddr) -> String {
+    if !bound.ip().is_unspecified() {
+        return bound.to_string();
+    }
+    let host = hostname::get().map(|host| host.to_string_lossy().to_string()).unwrap_or_default();
+    format!("{}:{}", host, bound.port())
+}
+
+/// Keeps the address of an instance published. Dropping it stops the heartbeat and withdraws the
+/// address.
+pub struct Heartbeat {
+    stop: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Drop for Heartbeat {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(handle) = self.handle.take() {
+            handle.thread().unpark();
+            let _ = handle.join();
+        }
+        info!("registry heartbeat stopped");
+    }
+}
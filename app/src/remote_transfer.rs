@@ -1,32 +1,181 @@
 use std::{io, ptr};
+use std::io::{ErrorKind, Read, Write};
+use std::time::{Duration, Instant};
 use std::net::{TcpListener, TcpStream};
 use std::os::fd::AsRawFd;
+use std::sync::Arc;
 use libc::{iovec, size_t, splice, vmsplice, SPLICE_F_MOVE};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use crate::checksum;
+use crate::compression::{self, CompressionPolicy};
+use crate::framing::{self, Flags};
+use crate::tls::{self, SecureStream};
+
+/// Time a sender waits for the receiver to advertise its codecs before sending uncompressed.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Time a sender waits for its receiver to connect before the transfer fails.
+pub const ACCEPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Upper bound of iovecs passed to a single `vmsplice` call (`UIO_MAXIOV`).
+pub const MAX_IOVECS: usize = 1024;
 
 pub fn net_transfer_bind(payload: Vec<u8>,address:String) -> io::Result<()> {
+    net_transfer_bind_vectored(&[&payload], address)
+}
+
+/// Serves the concatenation of `segments` to the first client connecting to `address`, without
+/// joining them into one buffer first.
+pub fn net_transfer_bind_vectored(segments: &[&[u8]], address: String) -> io::Result<()> {
+    net_transfer_bind_compressed(segments, address, CompressionPolicy::default())
+}
+
+/// Like [`net_transfer_bind_vectored`], compressing payloads above the threshold of `policy` when
+/// the receiver accepts its codec. Smaller payloads keep the splice path.
+pub fn net_transfer_bind_compressed(segments: &[&[u8]], address: String, policy: CompressionPolicy) -> io::Result<()> {
+    net_transfer_bind_secure(0, segments, address, policy, None)
+}
+
+/// Like [`net_transfer_bind_compressed`], encrypting the transfer when `tls` is set. The session
+/// is handed to the kernel after the handshake where kTLS is available, so the payload is still
+/// spliced; otherwise it is encrypted by rustls. `flags` announce the encoding of the payload.
+pub fn net_transfer_bind_secure(flags: Flags, segments: &[&[u8]], address: String, policy: CompressionPolicy, tls: Option<Arc<ServerConfig>>) -> io::Result<()> {
 
     // Start the TCP listener
     let listener = TcpListener::bind(address)?;
+    net_transfer_serve(listener, flags, segments, policy, tls)
+}
 
-    // Handle each incoming client
-    for stream in listener.incoming().next() {
-        match stream {
-            Ok(stream) => {
-                if let Err(e) = handle_client(stream, &payload) {
-                    eprintln!("Error handling client: {}", e);
-                }
-            }
-            Err(e) => {
-                eprintln!("Error accepting connection: {}", e);
-            }
+/// Serves the payload to the first client of a listener bound by the caller, e.g. on an
+/// ephemeral port it advertised. The payload is preceded by a header byte holding `flags`. Fails
+/// if no client connected within [`ACCEPT_TIMEOUT`].
+pub fn net_transfer_serve(listener: TcpListener, flags: Flags, segments: &[&[u8]], policy: CompressionPolicy, tls: Option<Arc<ServerConfig>>) -> io::Result<()> {
+    let stream = accept_within(&listener, ACCEPT_TIMEOUT)?;
+    let served = match &tls {
+        Some(config) => match tls::accept(stream, config.clone()) {
+            Ok(SecureStream::Kernel(stream)) => serve_client(stream, flags, segments, &policy),
+            Ok(SecureStream::User(stream)) => serve_client_tls(stream, flags, segments, &policy),
+            Err(e) => Err(e),
+        },
+        None => serve_client(stream, flags, segments, &policy),
+    };
+    if let Err(e) = served {
+        eprintln!("Error handling client: {}", e);
+        if checksum::is_mismatch(&e) {
+            return Err(e);
         }
     }
     println!("Server closed");
     Ok(())
 }
 
+/// Accepts the first client of `listener`, waiting for it at most `timeout`.
+fn accept_within(listener: &TcpListener, timeout: Duration) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    let mut fd = libc::pollfd { fd: listener.as_raw_fd(), events: libc::POLLIN, revents: 0 };
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let ready = unsafe { libc::poll(&mut fd, 1, remaining.as_millis().min(i32::MAX as u128) as i32) };
+        match ready {
+            -1 if io::Error::last_os_error().kind() == ErrorKind::Interrupted => continue,
+            -1 => return Err(io::Error::last_os_error()),
+            0 => return Err(io::Error::new(ErrorKind::TimedOut, format!("no receiver connected within {:?}", timeout))),
+            _ => return listener.accept().map(|(stream, _)| stream),
+        }
+    }
+}
+
+fn serve_client(stream: TcpStream, flags: Flags, segments: &[&[u8]], policy: &CompressionPolicy) -> io::Result<()> {
+    let data_len: usize = segments.iter().map(|segment| segment.len()).sum();
+    let header = [flags];
+    if !policy.applies(data_len) {
+        return handle_client_vectored(stream, &with_header(&header, segments));
+    }
+
+    // Receivers advertise the codecs they accept right after connecting
+    stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+    let mut hello = [0u8; 5];
+    let accepted = match (&stream).read_exact(&mut hello) {
+        Ok(()) => compression::parse_hello(&hello).unwrap_or_default(),
+        Err(_) => Vec::new(),
+    };
+    stream.set_read_timeout(None)?;
+
+    match policy.negotiate(&accepted) {
+        Some(codec) => {
+            let frame = compression::compress(codec, segments)?;
+            (&stream).write_all(&[flags | framing::COMPRESSED])?;
+            (&stream).write_all(&frame)?;
+            stream.shutdown(std::net::Shutdown::Write)?;
+            read_ack(&mut &stream)
+        }
+        None => handle_client_vectored(stream, &with_header(&header, segments)),
+    }
+}
+
+fn with_header<'a>(header: &'a [u8], segments: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    let mut framed = Vec::with_capacity(segments.len() + 1);
+    framed.push(header);
+    framed.extend_from_slice(segments);
+    framed
+}
+
+/// Sends the payload through a userspace TLS session, used when the kernel has no kTLS.
+fn serve_client_tls(mut stream: StreamOwned<ServerConnection, TcpStream>, flags: Flags, segments: &[&[u8]], policy: &CompressionPolicy) -> io::Result<()> {
+    let data_len: usize = segments.iter().map(|segment| segment.len()).sum();
+    let mut accepted = Vec::new();
+    if policy.applies(data_len) {
+        stream.sock.set_read_timeout(Some(HELLO_TIMEOUT))?;
+        let mut hello = [0u8; 5];
+        if stream.read_exact(&mut hello).is_ok() {
+            accepted = compression::parse_hello(&hello).unwrap_or_default();
+        }
+        stream.sock.set_read_timeout(None)?;
+    }
+
+    match policy.negotiate(&accepted) {
+        Some(codec) => {
+            let frame = compression::compress(codec, segments)?;
+            stream.write_all(&[flags | framing::COMPRESSED])?;
+            stream.write_all(&frame)?;
+        }
+        None => {
+            stream.write_all(&[flags])?;
+            for segment in segments {
+                stream.write_all(segment)?;
+            }
+        }
+    }
+    tls::shutdown_write(&mut stream)?;
+    read_ack(&mut stream)
+}
+
+/// Waits for the receiver to close the connection. Receivers verifying a checksum acknowledge the
+/// transfer first, others just close it.
+fn read_ack<R: Read>(reader: &mut R) -> io::Result<()> {
+    let mut reply = Vec::new();
+    if tls::read_to_end(reader, &mut reply).is_err() {
+        return Ok(());
+    }
+    // a hello not consumed by a sender without compression precedes the acknowledgement
+    let ack = match compression::parse_hello(&reply) {
+        Some(_) => reply.get(5),
+        None => reply.first(),
+    };
+    match ack {
+        Some(&checksum::ACK_MISMATCH) => Err(checksum::mismatch_error()),
+        _ => Ok(()),
+    }
+}
+
 pub fn handle_client(stream: TcpStream, payload: &[u8]) -> io::Result<()> {
-    let data_len = payload.len();
+    handle_client_vectored(stream, &[payload])
+}
+
+/// Sends the concatenation of `segments` to the client, mapping several segments onto the pipe
+/// with a single multi-iovec `vmsplice` per round.
+pub fn handle_client_vectored(stream: TcpStream, segments: &[&[u8]]) -> io::Result<()> {
+    let data_len: usize = segments.iter().map(|segment| segment.len()).sum();
 
     // Create a pipe
     let mut pipefd: [libc::c_int; 2] = [0; 2];
@@ -39,21 +188,32 @@ pub fn handle_client(stream: TcpStream, payload: &[u8]) -> io::Result<()> {
     let socket_fd = stream.as_raw_fd();
     let mut total_sent = 0;
     let chunk_size = 65536; // Use 64 KB chunks for transfer
+    // Position of the next byte to send: segment index and offset within the segment
+    let mut segment = 0;
+    let mut offset = 0;
 
-    //let start = chrono::offset::Utc::now();
-    //println!("start transfer at {:?}", start);
     // Transfer the in-memory data using vmsplice and splice
     while total_sent < data_len {
-        let bytes_left = data_len - total_sent;
-        let write_size = std::cmp::min(chunk_size, bytes_left);
-        // Create the iovec structure to reference the in-memory data
-        let iovec = iovec {
-            iov_base: unsafe { payload.as_ptr().add(total_sent) as *mut libc::c_void },
-            iov_len: write_size as size_t,
-        };
+        // Create the iovec structures referencing up to one chunk of the remaining segments
+        let mut iovecs: Vec<iovec> = Vec::new();
+        let mut write_size = 0;
+        let mut index = segment;
+        let mut start = offset;
+        while index < segments.len() && write_size < chunk_size && iovecs.len() < MAX_IOVECS {
+            let len = std::cmp::min(segments[index].len() - start, chunk_size - write_size);
+            if len > 0 {
+                iovecs.push(iovec {
+                    iov_base: unsafe { segments[index].as_ptr().add(start) as *mut libc::c_void },
+                    iov_len: len as size_t,
+                });
+                write_size += len;
+            }
+            index += 1;
+            start = 0;
+        }
         // Use vmsplice to map the in-memory data to the pipe
         let n_written = unsafe {
-            vmsplice(pipefd[1], &iovec as *const iovec, 1, SPLICE_F_MOVE)
+            vmsplice(pipefd[1], iovecs.as_ptr(), iovecs.len(), SPLICE_F_MOVE)
         };
         if n_written == -1 {
             return Err(io::Error::last_os_error());
@@ -81,6 +241,20 @@ pub fn handle_client(stream: TcpStream, payload: &[u8]) -> io::Result<()> {
         }
 
         total_sent += n_written as usize;
+
+        // vmsplice may map less than requested, continue after the last byte actually sent
+        let mut advance = n_written as usize;
+        while advance > 0 && segment < segments.len() {
+            let remaining = segments[segment].len() - offset;
+            if advance < remaining {
+                offset += advance;
+                advance = 0;
+            } else {
+                advance -= remaining;
+                segment += 1;
+                offset = 0;
+            }
+        }
     }
 
     // Close the pipe after transferring the data
@@ -89,11 +263,8 @@ pub fn handle_client(stream: TcpStream, payload: &[u8]) -> io::Result<()> {
         libc::close(pipefd[1]);
     }
 
-    //et end_time = chrono::offset::Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
-    //println!("Overall sent {} bytes at {:?}", total_sent, end_time);
-
     // Close the stream to signal the client that the transmission is complete
     stream.shutdown(std::net::Shutdown::Write)?;
 
-    Ok(())
-}
\ No newline at end of file
+    read_ack(&mut &stream)
+}
@@ -0,0 +1,132 @@
+use std::io;
+use std::os::unix::io::RawFd;
+use containerd_shim_wasm::sandbox::{oci, Error};
+use libc::{close, dup, dup2};
+use log::{error, info};
+use oci_spec::runtime::Spec;
+use crate::{advertise, payload_ref};
+use crate::utils::socket_utils;
+
+/// A standard stream of the shim redirected to a file of the container.
+struct Redirect {
+    /// Descriptor of the shim that was redirected, e.g. `STDOUT_FILENO`.
+    target: RawFd,
+    /// Duplicate of the original descriptor, restored on release.
+    saved: RawFd,
+    /// Descriptor of the container's file.
+    opened: RawFd,
+}
+
+/// The resources a container instance holds outside of its VM: its socket, the socket serving the
+/// payloads it published, the address it advertises for network transfers, the redirected stdio of the shim and its cgroup. They are torn down exactly once,
+/// on the first call to [`InstanceResources::release`] or when the guard is dropped, whichever
+/// exit path the instance takes.
+#[derive(Default)]
+pub struct InstanceResources {
+    socket_path: Option<String>,
+    payload_refs: Option<String>,
+    advertised: Option<String>,
+    stdio: Vec<Redirect>,
+    cgroup: Option<Spec>,
+    released: bool,
+}
+
+impl InstanceResources {
+    pub fn new() -> InstanceResources {
+        InstanceResources::default()
+    }
+
+    /// Tracks the socket the function listens on, removed on release.
+    pub fn track_socket(&mut self, socket_path: String) {
+        self.socket_path = Some(socket_path);
+    }
+
+    /// Tracks the socket serving the payloads the instance publishes, closed and removed on release
+    /// whether the instance ran in the shim or in a forked child.
+    pub fn track_payload_refs(&mut self, socket_path: String) {
+        self.payload_refs = Some(socket_path);
+    }
+
+    /// Tracks the address file the instance may advertise next to its bundle, withdrawn on release
+    /// even if the instance exited while serving a transfer.
+    pub fn track_advertisement(&mut self, bundle_path: String) {
+        self.advertised = Some(bundle_path);
+    }
+
+    /// Tracks the cgroup created from the spec of the container, deleted on release.
+    pub fn track_cgroup(&mut self, spec: Spec) {
+        self.cgroup = Some(spec);
+    }
+
+    /// Redirects the descriptor `target` of the shim to `opened`, taking ownership of `opened`.
+    /// The original descriptor is restored by [`InstanceResources::restore_stdio`].
+    pub fn redirect_stdio(&mut self, target: RawFd, opened: RawFd) -> io::Result<()> {
+        let saved = unsafe { dup(target) };
+        if saved < 0 {
+            let err = io::Error::last_os_error();
+            unsafe { close(opened) };
+            return Err(err);
+        }
+        if unsafe { dup2(opened, target) } < 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                close(saved);
+                close(opened);
+            }
+            return Err(err);
+        }
+        self.stdio.push(Redirect { target, saved, opened });
+        Ok(())
+    }
+
+    /// Points the redirected descriptors of the shim back at their originals. Once a child has
+    /// been forked with the redirected stdio, the shim no longer needs it.
+    pub fn restore_stdio(&mut self) {
+        // restored in reverse so that a descriptor redirected twice ends up at its original
+        while let Some(redirect) = self.stdio.pop() {
+            unsafe {
+                dup2(redirect.saved, redirect.target);
+                close(redirect.saved);
+                close(redirect.opened);
+            }
+        }
+    }
+
+    pub fn is_released(&self) -> bool {
+        self.released
+    }
+
+    /// Tears down every resource of the instance. Later calls do nothing. Failures are logged and
+    /// do not stop the rest of the teardown; the first one is returned.
+    pub fn release(&mut self) -> Result<(), Error> {
+        if self.released {
+            return Ok(());
+        }
+        self.released = true;
+        self.restore_stdio();
+        if let Some(socket_path) = self.socket_path.take() {
+            socket_utils::remove(&socket_path);
+        }
+        if let Some(socket_path) = self.payload_refs.take() {
+            payload_ref::close(&socket_path);
+        }
+        if let Some(bundle_path) = self.advertised.take() {
+            advertise::withdraw(&bundle_path);
+        }
+        let mut result = Ok(());
+        if let Some(spec) = self.cgroup.take() {
+            if let Err(e) = oci::get_cgroup(&spec).and_then(|cgroup| cgroup.delete()) {
+                error!("failed to delete cgroup: {}", e);
+                result = Err(e);
+            }
+        }
+        info!("instance resources released");
+        result
+    }
+}
+
+impl Drop for InstanceResources {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
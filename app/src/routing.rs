@@ -0,0 +1,152 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use anyhow::{anyhow, Error};
+use lazy_static::lazy_static;
+use log::info;
+use oci_spec::runtime::Spec;
+use rand::Rng;
+use crate::utils::oci_utils;
+
+/// Annotation resolving the target of a sender to a weighted set of function versions, as
+/// comma-separated `function=weight` entries, e.g. `resize:v1=90,resize:v2=10`. Versions are the
+/// names containers advertise through `target.function`.
+pub const ROUTES_ANNOTATION: &str = "target.routes";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WeightedRoute {
+    pub function: String,
+    pub weight: u32,
+}
+
+/// Versions of a target function transfers are split between.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RouteSet {
+    pub routes: Vec<WeightedRoute>,
+}
+
+impl RouteSet {
+    pub fn parse(spec: &str) -> Result<RouteSet, Error> {
+        let routes: Vec<WeightedRoute> = spec
+            .split(',')
+            .map(str::trim)
+            .filter(|route| !route.is_empty())
+            .map(|route| {
+                let (function, weight) = route
+                    .rsplit_once('=')
+                    .ok_or_else(|| anyhow!("route `{}` has no weight", route))?;
+                let weight = weight.trim().parse().map_err(|_| anyhow!("route `{}` has an invalid weight", route))?;
+                Ok(WeightedRoute { function: function.trim().to_string(), weight })
+            })
+            .collect::<Result<_, Error>>()?;
+        // summed as u64, weights of up to u32::MAX each cannot overflow
+        if routes.iter().map(|route| route.weight as u64).sum::<u64>() == 0 {
+            return Err(anyhow!("routes `{}` have no positive weight", spec));
+        }
+        Ok(RouteSet { routes })
+    }
+
+    /// Reads the routes from the `target.routes` annotation of the sender, `Ok(None)` if the
+    /// target is not split.
+    pub fn from_spec(spec: &Spec) -> Result<Option<RouteSet>, Error> {
+        let routes = oci_utils::get_wasm_annotations(spec, ROUTES_ANNOTATION);
+        if routes.is_empty() {
+            return Ok(None);
+        }
+        RouteSet::parse(&routes).map(Some)
+    }
+
+    /// Picks the version of the next transfer with a probability proportional to its weight.
+    pub fn choose(&self) -> &str {
+        self.choose_among(|_| true).unwrap_or_default()
+    }
+
+    /// Picks the version of the next transfer among those `available` accepts, with a probability
+    /// proportional to its weight among them. `None` if none of them has a positive weight.
+    pub fn choose_among<F: Fn(&str) -> bool>(&self, available: F) -> Option<&str> {
+        let total: u64 = self
+            .routes
+            .iter()
+            .filter(|route| available(&route.function))
+            .map(|route| route.weight as u64)
+            .sum();
+        if total == 0 {
+            return None;
+        }
+        let roll = rand::thread_rng().gen_range(0..total);
+        Some(roll_over(self.routes.iter().filter(|route| available(&route.function)), roll))
+    }
+
+    /// Returns the version owning the `roll`-th unit of the total weight.
+    pub fn choose_at(&self, roll: u64) -> &str {
+        roll_over(self.routes.iter(), roll)
+    }
+}
+
+/// Returns the route of `routes` owning the `roll`-th unit of their total weight, the last one if
+/// `roll` exceeds it.
+fn roll_over<'a, I: Iterator<Item = &'a WeightedRoute>>(routes: I, roll: u64) -> &'a str {
+    let mut remaining = roll;
+    let mut last = "";
+    for route in routes {
+        if remaining < route.weight as u64 {
+            return &route.function;
+        }
+        remaining -= route.weight as u64;
+        last = &route.function;
+    }
+    last
+}
+
+/// Transfers sent to a function version since the shim started.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RouteStats {
+    pub transfers: u64,
+    pub failures: u64,
+    pub bytes: u64,
+    pub latency: Duration,
+}
+
+impl RouteStats {
+    pub fn mean_latency(&self) -> Duration {
+        match self.transfers {
+            0 => Duration::ZERO,
+            // divided exactly while the count fits the integer division of `Duration`
+            transfers => match u32::try_from(transfers) {
+                Ok(transfers) => self.latency / transfers,
+                Err(_) => self.latency.div_f64(transfers as f64),
+            },
+        }
+    }
+
+    pub fn error_rate(&self) -> f64 {
+        match self.transfers {
+            0 => 0.0,
+            transfers => self.failures as f64 / transfers as f64,
+        }
+    }
+}
+
+lazy_static! {
+    static ref STATS: Mutex<HashMap<String, RouteStats>> = Mutex::new(HashMap::new());
+}
+
+/// Records a transfer of `bytes` to a function version.
+pub fn record(function: &str, bytes: usize, elapsed: Duration, succeeded: bool) {
+    let mut stats = STATS.lock().unwrap();
+    let version = stats.entry(function.to_string()).or_default();
+    version.transfers += 1;
+    version.bytes += bytes as u64;
+    version.latency += elapsed;
+    if !succeeded {
+        version.failures += 1;
+    }
+    info!(
+        "transfers to {}: {} sent, error rate {:.3}, mean latency {:?}",
+        function, version.transfers, version.error_rate(), version.mean_latency()
+    );
+}
+
+pub fn stats(function: &str) -> RouteStats {
+    STATS.lock().unwrap().get(function).copied().unwrap_or_default()
+}
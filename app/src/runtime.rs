@@ -1,34 +1,69 @@
 extern crate libc;
-use crate::data_hose::BUNDLE_PATH;
-use crate::utils::{oci_utils, snapshot_utils};
+use crate::data_hose;
+use crate::host_context::HostContext;
+use crate::utils::{oci_utils, socket_utils};
+use crate::mux::{self, MUX_MAGIC};
+use crate::peer_auth::PeerPolicy;
+use crate::transfer_policy;
+use crate::{advertise, checksum, compression, framing, payload_cache, pod, shutdown, tls};
+use crate::error::TransferError;
+use crate::stream::{self, ForwardMode, STREAM_ENTRYPOINT, STREAM_MAGIC};
+use crate::workflow::FunctionSpec;
 use anyhow::Error;
 use chrono;
 use chrono::{SecondsFormat, Utc};
 use oci_spec::runtime::Spec;
-use std::io::{BufReader, Read, Write};
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use std::io::{BufReader, ErrorKind, Read, Write};
 use std::net::TcpStream;
-use std::os::unix::net::{UnixListener, UnixStream};
-use std::path::Path;
+use std::os::unix::net::UnixStream;
 use std::result::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
 use wasmedge_sdk::{params, Instance, Vm, WasmVal};
 
+/// Export called on the receiving module when an incoming transfer failed.
+pub const TRANSFER_ERROR_EXPORT: &str = "on_transfer_error";
+
+/// Interval at which listeners waiting for input check for a shutdown request.
+const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Interval at which receivers look up the addresses their sources published to the registry.
+const REGISTRY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
 #[derive(Clone)]
 pub struct Runtime {
     pub bundle_path: String,
     pub oci_spec: Spec,
-    pub vm: Option<Vm>
+    pub vm: Option<Vm>,
+    /// Name of the module instance holding the function, `main` unless hosted in the pod-shared VM.
+    pub module_name: String,
+    pub context: Arc<HostContext>,
 }
 
 impl Runtime {
-    pub fn new(bundle_path: String, oci_spec: Spec, wasm_vm: Vm) -> Runtime {
+    pub fn new(context: Arc<HostContext>, wasm_vm: Vm) -> Runtime {
         Runtime {
-            bundle_path,
-            oci_spec,
-            vm :Some(wasm_vm)
+            bundle_path: context.bundle_path.clone(),
+            oci_spec: context.spec.clone(),
+            vm :Some(wasm_vm),
+            module_name: String::from("main"),
+            context,
         }
     }
 
-    unsafe fn handle_connection(&mut self, mut socket: UnixStream) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn with_module(mut self, module_name: String) -> Runtime {
+        self.module_name = module_name;
+        self
+    }
+
+    /// Serves a connection to the Unix socket from the process `peer`. `multiplexed` is set once
+    /// the client switched to the multiplexed protocol, whose connections stay open for further
+    /// requests.
+    unsafe fn handle_connection(&mut self, mut socket: UnixStream, peer: u32, multiplexed: &AtomicBool) -> Result<(), Box<dyn std::error::Error>> {
 
         let mut chunk = [0u8; 4];
 
@@ -37,6 +72,74 @@ impl Runtime {
         let mut buffer = Vec::new(); // Buffer to accumulate the entire input
 
         let mut reader = BufReader::new(socket.try_clone()?);
+
+        // Read the first bytes to detect clients using the multiplexed or streaming protocol
+        while buffer.len() < MUX_MAGIC.len() {
+            let bytes_read = reader.read(&mut chunk[..MUX_MAGIC.len() - buffer.len()])?;
+            if bytes_read == 0 {
+                break;
+            }
+            buffer.extend_from_slice(&chunk[..bytes_read]);
+        }
+        if buffer.as_slice() == MUX_MAGIC {
+            multiplexed.store(true, Ordering::SeqCst);
+            return Ok(mux::serve(&mut reader, &mut socket, |flags, input| {
+                let input = match framing::has(flags, framing::CHECKSUM) {
+                    true => checksum::verify(input).map_err(|e| {
+                        self.notify_transfer_error(TransferError::ChecksumMismatch.code());
+                        e
+                    })?,
+                    false => input,
+                };
+                // Fan-outs deliver large payloads as references to the node-local cache
+                let input = match framing::has(flags, framing::CACHE_REF) {
+                    true => payload_cache::materialize(&input, peer)?,
+                    false => input,
+                };
+                self.call_vm_with_input(input)
+                    .map(|result| result.to_le_bytes().to_vec())
+                    .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e.to_string()))
+            })?);
+        }
+        if buffer.as_slice() == STREAM_MAGIC {
+            let forward_mode = ForwardMode::from_spec(&self.oci_spec);
+            let forwards = match forward_mode {
+                ForwardMode::Off => Vec::new(),
+                _ => data_hose::stream_forward_targets(&self.context),
+            };
+            let forward_paths: Vec<String> = forwards.iter().filter_map(|selection| selection.replica().socket_path()).collect();
+            // The next hops are healthy if the stream reached them
+            let report = |forwarded: bool| forwards.into_iter().for_each(|selection| selection.report(forwarded));
+            if forward_mode == ForwardMode::Relay {
+                // Pass-through, the response of the next hop is returned to the source
+                let response = stream::relay(reader, &forward_paths);
+                report(response.is_ok());
+                socket.write_all(&response?)?;
+                socket.flush()?;
+                return Ok(());
+            }
+            // The guest pulls the chunks itself, the socket is consumed at the pace of the guest
+            // and every chunk is forwarded to the next hop as soon as it is read
+            let stream_id = match self.context.streams.accept_forwarding(reader, &forward_paths) {
+                Ok(stream_id) => stream_id,
+                Err(e) => {
+                    report(false);
+                    return Err(e.into());
+                }
+            };
+            let result = self.call_vm_with_stream(stream_id);
+            // a guest may end the stream by closing it itself
+            let closed = match self.context.streams.close(stream_id) {
+                Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+                closed => closed,
+            };
+            report(closed.is_ok());
+            closed?;
+            socket.write_all(&result?.to_le_bytes())?;
+            socket.flush()?;
+            return Ok(());
+        }
+
         loop {
             let bytes_read = reader.read(&mut chunk)?;
             if bytes_read == 0 {
@@ -56,42 +159,105 @@ impl Runtime {
     }
 
 
+    /// Listens on the Unix socket of the function until a shutdown is requested. Every connection
+    /// is served on a thread of its own, so that multiplexed connections kept open by pooling
+    /// clients do not hold back other clients. On shutdown no further connections are accepted,
+    /// open multiplexed connections stop taking requests and the requests in flight are answered.
     pub fn create_server_socket(&mut self) -> Result<(), Box<dyn std::error::Error>> {
 
-        let binding = self.bundle_path.to_owned() + ".sock";
-        let socket_path = Path::new(&binding);
-        if socket_path.exists() {
-            std::fs::remove_file(&socket_path).unwrap();
+        let socket_path = self.bundle_path.to_owned() + ".sock";
+        let abstract_namespace = socket_utils::is_abstract(&self.oci_spec);
+        let listener = socket_utils::bind(&socket_path, abstract_namespace)?;
+        match abstract_namespace {
+            true => println!("Socket created successfully at @{} {}", socket_utils::abstract_name(&socket_path), Utc::now()),
+            false => println!("Socket created successfully at {:?} {}", &socket_path, Utc::now()),
         }
-
-        let listener = UnixListener::bind(&socket_path)?;
-        println!("Socket created successfully at {:?} {}", &socket_path, Utc::now());
-        for stream in listener.incoming().next() {
-            match stream {
-                Ok(socket) => unsafe {
-                    // Handle the connection (consider using threads or async for concurrency)
-                    self.handle_connection(socket).unwrap_or_else(|e| eprintln!("Error: {}", e));
+        let peers = PeerPolicy::from_spec(&self.oci_spec);
+        let mut connections: Vec<Connection> = Vec::new();
+        // Accepts without blocking so that a shutdown request stops the wait for input
+        listener.set_nonblocking(true)?;
+        loop {
+            if self.context.shutdown.is_requested() {
+                println!("Shutdown requested, no longer accepting connections {}", Utc::now());
+                break;
+            }
+            connections.retain(|connection| !connection.handle.is_finished());
+            match listener.accept() {
+                Ok((socket, _)) => {
+                    socket.set_nonblocking(false)?;
+                    // Only processes of the sandbox may send input, others are dropped unread
+                    let peer = match peers.authorize(&socket) {
+                        Ok(peer) => peer,
+                        Err(e) => {
+                            eprintln!("Unauthorized connection: {}", e);
+                            continue;
+                        }
+                    };
+                    if self.context.config.restricts() {
+                        let source = transfer_policy::peer_function(&self.context.state_root, peer.pid)
+                            .unwrap_or_else(|| transfer_policy::UNKNOWN_SOURCE.to_string());
+                        if let Err(e) = self.context.config.authorize(&source, &self.context.config.function_name) {
+                            eprintln!("Rejected connection: {}", e);
+                            continue;
+                        }
+                    }
+                    connections.push(self.spawn_connection(socket, peer.pid)?);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(SHUTDOWN_POLL_INTERVAL),
+                Err(e) => {
+                    eprintln!("Connection failed: {}", e);
+                    break;
                 }
-                Err(e) => eprintln!("Connection failed: {}", e),
             }
         }
+        for connection in connections {
+            connection.drain();
+        }
         Ok(())
     }
 
+    fn spawn_connection(&self, socket: UnixStream, peer: u32) -> std::io::Result<Connection> {
+        let multiplexed = Arc::new(AtomicBool::new(false));
+        let mut runtime = self.clone();
+        let served = socket.try_clone()?;
+        let connection_multiplexed = multiplexed.clone();
+        let handle = thread::spawn(move || unsafe {
+            runtime
+                .handle_connection(served, peer, &connection_multiplexed)
+                .unwrap_or_else(|e| eprintln!("Error: {}", e));
+        });
+        Ok(Connection { socket, multiplexed, handle })
+    }
+
+    /// Enters the VM before guest code of the module runs, connections served concurrently run
+    /// the guest one at a time. Modules of the pod-shared VM are set up with their own WASI
+    /// configuration, see [`pod::enter`].
+    fn enter_vm(&mut self) -> Result<pod::VmEntry, TransferError> {
+        if !pod::is_pod_shared(&self.oci_spec) {
+            return Ok(pod::lock_vm());
+        }
+        let vm = self.vm.as_mut().ok_or_else(|| TransferError::ModuleNotFound(self.module_name.clone()))?;
+        pod::enter(vm, &self.module_name)
+    }
+
     fn call_vm_with_input(&mut self, input: Vec<u8>) -> Result<i64, Box<dyn std::error::Error>>{
         //println!("Value from func a {}",input);
-        // Set new arguments on the wasi instance
+        let _entered = self.enter_vm()?;
+        let pod_shared = pod::is_pod_shared(&self.oci_spec);
         let vm = self.vm.as_mut().unwrap();
-        let mut wasi_instance = vm.wasi_module()?;
-        wasi_instance.initialize(
-            Some(vec![]),
-            Some(vec![]),
-            Some(vec![]),
-        );
+        if !pod_shared {
+            // Set new arguments on the wasi instance
+            let mut wasi_instance = vm.wasi_module()?;
+            wasi_instance.initialize(
+                Some(vec![]),
+                Some(vec![]),
+                Some(vec![]),
+            );
+        }
         let start= Utc::now();
         println!("Run wasm func at {:?}",Utc::now());
         // wasm module main function: https://github.com/containerd/runwasi/blob/f3bc0c436077bdca3ed105b12ffe8eff1517ecad/crates/containerd-shim-wasmedge/src/instance.rs#L52
-        let main_instance = vm.named_module("main").unwrap();
+        let main_instance = vm.named_module(&self.module_name).unwrap();
         //Allocate memory
         let allocate = main_instance.func("allocate_memory").unwrap();
         let len = input.len() as i32;
@@ -112,6 +278,87 @@ impl Runtime {
         let result = res[0].to_i64();
         Ok(result)
     }
+    /// Runs the stream entrypoint of the module with the id of an incoming stream, which the guest
+    /// drains with `read_chunk` into buffers of its own size.
+    fn call_vm_with_stream(&mut self, stream_id: u32) -> Result<i64, Box<dyn std::error::Error>> {
+        let _entered = self.enter_vm()?;
+        let vm = self.vm.as_mut().unwrap();
+        let start = Utc::now();
+        println!("Run wasm stream func at {:?}", start);
+        let main_instance = vm.named_module(&self.module_name)?;
+        let stream_func = main_instance
+            .func(STREAM_ENTRYPOINT)
+            .ok_or_else(|| format!("module {} does not export {}", self.module_name, STREAM_ENTRYPOINT))?;
+        let res = stream_func.call(vm, params!(stream_id as i32))?;
+
+        let end = Utc::now();
+        println!("Run stream func finished at {:?} Duration {}", end, end - start);
+        Ok(res.first().map_or(0, |value| value.to_i64()))
+    }
+
+    /// Reports a failed incoming transfer to the guest through its optional `on_transfer_error`
+    /// export, called with the code of the [`TransferError`].
+    pub fn notify_transfer_error(&mut self, code: u32) {
+        let _entered = match self.enter_vm() {
+            Ok(entered) => entered,
+            Err(_) => return,
+        };
+        let vm = match self.vm.as_mut() {
+            Some(vm) => vm,
+            None => return,
+        };
+        let instance = match vm.named_module(&self.module_name) {
+            Ok(instance) => instance,
+            Err(_) => return,
+        };
+        if let Some(func) = instance.func(TRANSFER_ERROR_EXPORT) {
+            if let Err(e) = func.call(vm, params!(code as i32)) {
+                eprintln!("Failed to report transfer error to guest: {}", e);
+            }
+        }
+    }
+
+    /// Lets the guest clean up through its optional `on_shutdown` export once it no longer
+    /// receives input after a shutdown request.
+    pub fn notify_shutdown(&mut self) {
+        let _entered = match self.enter_vm() {
+            Ok(entered) => entered,
+            Err(_) => return,
+        };
+        self.call_shutdown_hook();
+    }
+
+    /// Calls the `on_shutdown` export without entering the VM, for guests still running `_start`.
+    fn call_shutdown_hook(&mut self) {
+        let vm = match self.vm.as_mut() {
+            Some(vm) => vm,
+            None => return,
+        };
+        let instance = match vm.named_module(&self.module_name) {
+            Ok(instance) => instance,
+            Err(_) => return,
+        };
+        if let Some(func) = instance.func(shutdown::SHUTDOWN_EXPORT) {
+            if let Err(e) = func.call(vm, params!()) {
+                eprintln!("Failed to run shutdown hook of guest: {}", e);
+            }
+        }
+    }
+
+    /// Runs the guest's `_start` export, marking `running` as cleared before leaving the VM.
+    fn run_start(&mut self, running: &Mutex<bool>) -> Result<(), Box<dyn std::error::Error>> {
+        // forked instances have the VM to themselves while `_start` runs
+        let _entered = match pod::is_pod_shared(&self.oci_spec) {
+            true => Some(self.enter_vm()?),
+            false => None,
+        };
+        let vm = self.vm.as_mut().ok_or_else(|| TransferError::ModuleNotFound(self.module_name.clone()))?;
+        let result = vm.run_func(Some(self.module_name.as_str()), "_start", params!());
+        *running.lock().unwrap_or_else(PoisonError::into_inner) = false;
+        result?;
+        Ok(())
+    }
+
     // Write to WasmVM
     fn write_memory_host(main_instance: &Instance, address:i32, data:Vec<u8>) {
         let mut memory = main_instance.memory("memory").unwrap();
@@ -120,19 +367,36 @@ impl Runtime {
 
 
     pub fn stop_socket (&self) -> Result<(), Box<dyn std::error::Error>>{
-        let binding = self.bundle_path.as_str().to_owned() + ".sock";
-        connect_unix_socket(String::from("exit").into_bytes(),self.bundle_path.as_str().to_owned())?;
-        let socket_path = Path::new(&binding);
-        if socket_path.exists() {
-            std::fs::remove_file(&socket_path).unwrap();
-            println!("Socket {:?} deleted",self.bundle_path.as_str());
-        }
+        connect_unix_socket(&self.context, String::from("exit").into_bytes(),self.bundle_path.as_str().to_owned())?;
+        socket_utils::remove(&(self.bundle_path.to_owned() + ".sock"));
         Ok(())
     }
 }
 
+/// A connection served by the listener of a [`Runtime`].
+struct Connection {
+    socket: UnixStream,
+    multiplexed: Arc<AtomicBool>,
+    handle: JoinHandle<()>,
+}
+
+impl Connection {
+    /// Waits for the connection to be served. Multiplexed connections are kept open by their
+    /// clients, no further requests are read from them; the request in flight is still answered.
+    fn drain(self) {
+        // the protocol is only known once the client sent its first bytes
+        while !self.handle.is_finished() {
+            if self.multiplexed.load(Ordering::SeqCst) {
+                let _ = self.socket.shutdown(std::net::Shutdown::Read);
+                break;
+            }
+            thread::sleep(SHUTDOWN_POLL_INTERVAL);
+        }
+        let _ = self.handle.join();
+    }
+}
 
-pub fn connect_unix_socket(input_fn_a:Vec<u8>, mut socket_path: String) -> Result<String, Error> {
+pub fn connect_unix_socket(context: &HostContext, input_fn_a:Vec<u8>, mut socket_path: String) -> Result<String, Error> {
 
     const MAX_RETRIES: u32 = 1000; // Maximum value for u32 (4,294,967,295)
 
@@ -140,17 +404,17 @@ pub fn connect_unix_socket(input_fn_a:Vec<u8>, mut socket_path: String) -> Resul
     let mut stream: UnixStream;
 
     loop {
-        match UnixStream::connect(socket_path.clone() + ".sock") {
+        match socket_utils::connect(&(socket_path.clone() + ".sock")) {
             Ok(s) => {
                 stream = s;
                 break;
             },
-            Err(_err) => {
+            Err(err) => {
                 retries += 1;
                 if retries >= MAX_RETRIES {
-                    panic!("Exceeded maximum retries, failed to connect to socket.");
+                    return Err(Error::new(err).context("Exceeded maximum retries, failed to connect to socket."));
                 }
-                socket_path = unsafe{snapshot_utils::find_container_path_parallel(BUNDLE_PATH.as_deref().unwrap_or(""), "alice-lib.wasm")};
+                socket_path = data_hose::find_container_path(context, "alice-lib.wasm");
             }
         }
     }
@@ -158,7 +422,7 @@ pub fn connect_unix_socket(input_fn_a:Vec<u8>, mut socket_path: String) -> Resul
     if let Err(e) = stream.write_all(input_fn_a.as_slice()) {
         eprintln!("Failed to write data: {:?}", e);
     }
-    stream.shutdown(std::net::Shutdown::Write).expect("shutdown failed");
+    stream.shutdown(std::net::Shutdown::Write)?;
     let mut response = String::new();
     stream.read_to_string(&mut response)?;
     Ok(response)
@@ -166,30 +430,184 @@ pub fn connect_unix_socket(input_fn_a:Vec<u8>, mut socket_path: String) -> Resul
 
 
 
+/// Serves a secondary function until a shutdown is requested: its Unix socket takes input from
+/// senders on the node for as long as the instance runs, while the sources of its network address
+/// are waited for alongside. The guest's `on_shutdown` hook runs once all input in flight was
+/// handled. A failing network receive shuts the instance down.
+pub fn serve(context: Arc<HostContext>, vm: Vm, module_name: String) -> Result<(), Box<dyn std::error::Error>> {
+    let mut listener = Runtime::new(context.clone(), vm.clone()).with_module(module_name.clone());
+    let unix_listener = thread::spawn(move || listener.create_server_socket().map_err(|e| e.to_string()));
+
+    let received = init_listener(context.clone(), vm.clone(), module_name.clone()).map_err(|e| e.to_string());
+    if let Err(e) = &received {
+        eprintln!("Receiving from network sources failed: {}", e);
+        context.shutdown.request();
+    }
+    let served = unix_listener.join().unwrap_or_else(|_| Err("Unix listener panicked".to_string()));
+    if context.shutdown.is_requested() {
+        Runtime::new(context, vm).with_module(module_name).notify_shutdown();
+    }
+    Ok(received.and(served)?)
+}
+
+/// Runs a function through its `_start` export until it returns. A shutdown requested meanwhile
+/// calls the guest's `on_shutdown` hook alongside `_start`, the way a signal handler interrupts a
+/// program, so that the guest can wind down before its grace period is over.
+pub fn start(context: Arc<HostContext>, vm: Vm, module_name: String) -> Result<(), Box<dyn std::error::Error>> {
+    let running = Arc::new(Mutex::new(true));
+    let mut hook = Runtime::new(context.clone(), vm.clone()).with_module(module_name.clone());
+    let watched = running.clone();
+    let watcher = thread::spawn(move || loop {
+        {
+            // `_start` holds the VM until it cleared the flag, the hook runs inside its entry, or in
+            // its place while it waits in a host call
+            let running = watched.lock().unwrap_or_else(PoisonError::into_inner);
+            if !*running {
+                return false;
+            }
+            if hook.context.shutdown.is_requested() {
+                let _interrupted = pod::interrupt(hook.vm.as_mut().unwrap(), &hook.module_name);
+                hook.call_shutdown_hook();
+                return true;
+            }
+        }
+        thread::sleep(SHUTDOWN_POLL_INTERVAL);
+    });
+
+    let mut runtime = Runtime::new(context.clone(), vm).with_module(module_name);
+    let started = runtime.run_start(&running);
+    // also stops the watcher of a `_start` that never ran
+    *running.lock().unwrap_or_else(PoisonError::into_inner) = false;
+    let notified = watcher.join().unwrap_or(false);
+    if !notified && context.shutdown.is_requested() {
+        runtime.notify_shutdown();
+    }
+    started
+}
+
+/// Receives the input of the sources of the function's network address, if it has one, and runs
+/// the function on it.
 #[tokio::main(flavor = "current_thread")]
-pub async fn init_listener(bundle_path: String, oci_spec: Spec, vm: Vm) -> Result<(), Box<dyn std::error::Error>>{
+pub async fn init_listener(context: Arc<HostContext>, vm: Vm, module_name: String) -> Result<(), Box<dyn std::error::Error>>{
     println!("before init");
-    let address = oci_utils::arg_to_wasi(&oci_spec).first().unwrap().to_string();
-    let mut listener = Runtime::new(bundle_path.clone(), oci_spec.clone(), vm.clone());
-    let input = connect_to_source(address)?;
-    listener.call_vm_with_input(input).expect("TODO: panic message");
+    let mut address = match oci_utils::arg_to_wasi(&context.spec).first() {
+        Some(address) => address.to_string(),
+        None => String::new(),
+    };
+    let mut sources = 1;
+    let mut source_specs = Vec::new();
+    // A workflow node fans in the payloads of all its sources, joined in arrival order
+    if let Some(node) = &context.node {
+        if let Some(node_address) = node.function.address.clone() {
+            address = node_address;
+            sources = node.fan_in().max(1);
+            source_specs = node.sources.clone();
+        }
+    }
+    if address.is_empty() {
+        return Ok(());
+    }
+    let client_tls = tls::client_config(&context.spec)?;
+    let mut listener = Runtime::new(context.clone(), vm.clone()).with_module(module_name);
+    // Sources are only connected to if the policies allow their transfers, sources the workflow
+    // does not name are unknown
+    for index in 0..sources {
+        let source = source_specs.get(index).map_or(transfer_policy::UNKNOWN_SOURCE, |source: &FunctionSpec| source.name.as_str());
+        if let Err(e) = context.config.authorize(source, &context.config.function_name) {
+            listener.notify_transfer_error(e.code());
+            return Err(e.into());
+        }
+    }
+    let mut input = Vec::new();
+    for index in 0..sources {
+        match connect_to_source(&context, address.clone(), source_specs.get(index), client_tls.as_ref()) {
+            Ok(payload) => input.extend(payload),
+            Err(e) if e.kind() == ErrorKind::Interrupted && context.shutdown.is_requested() => {
+                // The input is incomplete, the function is not run on it
+                println!("Shutdown requested, no longer waiting for sources {}", Utc::now());
+                return Ok(());
+            }
+            Err(e) => {
+                if checksum::is_mismatch(&e) {
+                    listener.notify_transfer_error(TransferError::ChecksumMismatch.code());
+                }
+                return Err(e.into());
+            }
+        }
+    }
+    listener.call_vm_with_input(input)?;
     Ok(())
 }
 
-fn connect_to_source(address: String) -> Result<Vec<u8>, Box<dyn std::error::Error>>{
-    let mut buffer = Vec::new();
+/// Connects to a sender and receives its payload. A sender running on this node as `source` may
+/// serve on a port of its own, advertised next to its bundle, a sender on another node is reached
+/// at the address it published to the registry. Other senders are reached at `address`.
+fn connect_to_source(context: &HostContext, address: String, source: Option<&FunctionSpec>, client_tls: Option<&(Arc<ClientConfig>, ServerName<'static>)>) -> std::io::Result<Vec<u8>>{
+    let mut source_bundle = None;
+    let mut published: Vec<String> = Vec::new();
+    let mut resolved_at: Option<Instant> = None;
+    let mut attempts = 0;
     loop {
-        match TcpStream::connect(address.clone()) {
-            Ok(mut stream) => {
-                // Read all the data from the server until the connection is closed
-                let bytes_read = stream.read_to_end(&mut buffer)?;
-
-                let end_time = chrono::offset::Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
-                println!("Received {} bytes at {:?}", bytes_read, end_time);
-                return Ok(buffer);
+        if context.shutdown.is_requested() {
+            return Err(std::io::Error::new(ErrorKind::Interrupted, "shutdown requested"));
+        }
+        if let Some(source) = source.filter(|_| source_bundle.is_none()) {
+            source_bundle = Some(data_hose::find_container_path(context, &source.module_name())).filter(|path| !path.is_empty());
+        }
+        let mut target = source_bundle.as_deref().and_then(advertise::resolve);
+        if let Some(source) = source.filter(|_| target.is_none() && context.registry.is_some()) {
+            if resolved_at.map_or(true, |at| at.elapsed() >= REGISTRY_POLL_INTERVAL) {
+                published = data_hose::registered_addresses(context, &source.name);
+                resolved_at = Some(Instant::now());
+            }
+            // instances of the source serving a transfer are tried in turn
+            attempts += 1;
+            target = (!published.is_empty()).then(|| published[attempts % published.len()].clone());
+        }
+        let target = target.unwrap_or_else(|| address.clone());
+        match TcpStream::connect(target) {
+            Ok(stream) => {
+                return match client_tls {
+                    Some((config, server_name)) => receive(&mut tls::connect(stream, config.clone(), server_name.clone())?),
+                    None => receive(&mut &stream),
+                };
             }
             _ => {}
         }
     }
 
-}
\ No newline at end of file
+}
+
+/// Receives a payload from a sender, over a plain connection or a TLS session.
+fn receive<S: Read + Write>(stream: &mut S) -> std::io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    // Advertise the codecs the sender may compress the payload with
+    stream.write_all(&compression::hello(&compression::SUPPORTED))?;
+    stream.flush()?;
+
+    // Read all the data from the server until the connection is closed
+    let bytes_read = tls::read_to_end(stream, &mut buffer)?;
+
+    let end_time = chrono::offset::Utc::now().to_rfc3339_opts(SecondsFormat::Nanos, true);
+    println!("Received {} bytes at {:?}", bytes_read, end_time);
+
+    // Acknowledge the transfer so that the sender learns about checksum mismatches
+    let verified = framing::split_header(buffer).and_then(|(flags, payload)| {
+        let payload = match framing::has(flags, framing::COMPRESSED) {
+            true => compression::decompress(payload)?,
+            false => payload,
+        };
+        match framing::has(flags, framing::CHECKSUM) {
+            true => checksum::verify(payload),
+            false => Ok(payload),
+        }
+    });
+    let ack = match &verified {
+        Err(e) if checksum::is_mismatch(e) => checksum::ACK_MISMATCH,
+        _ => checksum::ACK_OK,
+    };
+    if let Err(e) = stream.write_all(&[ack]).and_then(|_| stream.flush()) {
+        eprintln!("Failed to acknowledge transfer: {}", e);
+    }
+    verified
+}
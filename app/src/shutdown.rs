@@ -0,0 +1,79 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use libc::{c_int, sigaction, sigemptyset, SA_RESTART, SIGTERM};
+use oci_spec::runtime::Spec;
+use crate::utils::oci_utils;
+
+/// Annotation setting the seconds an instance is given to shut down after SIGTERM before it is
+/// killed.
+pub const GRACE_PERIOD_ANNOTATION: &str = "shutdown.grace_period";
+
+pub const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Export called on the guest once it stopped receiving input after SIGTERM, or alongside `_start`
+/// for functions running through it.
+pub const SHUTDOWN_EXPORT: &str = "on_shutdown";
+
+/// Set by the SIGTERM handler of a forked instance.
+static TERMINATED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_sigterm(_signal: c_int) {
+    TERMINATED.store(true, Ordering::SeqCst);
+}
+
+/// Lets SIGTERM request the shutdown of the instance run by this process instead of terminating
+/// it. Only forked instances install it, the shim itself keeps the default disposition.
+pub fn install_handler() -> io::Result<()> {
+    unsafe {
+        let mut action: sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_sigterm as extern "C" fn(c_int) as usize;
+        action.sa_flags = SA_RESTART;
+        sigemptyset(&mut action.sa_mask);
+        if sigaction(SIGTERM, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Reads the grace period from the `shutdown.grace_period` annotation.
+pub fn grace_period(spec: &Spec) -> Duration {
+    oci_utils::get_wasm_annotations(spec, GRACE_PERIOD_ANNOTATION)
+        .parse()
+        .map(Duration::from_secs)
+        .unwrap_or(DEFAULT_GRACE_PERIOD)
+}
+
+/// Shutdown request of an instance, shared between the shim and the thread or process serving
+/// it. Once requested the instance stops receiving new input, finishes the input in flight and
+/// calls the guest's `on_shutdown` export.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<AtomicBool>,
+    killed: Arc<AtomicBool>,
+}
+
+impl Shutdown {
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    /// Marks the instance as killed. Instances running on a shim thread cannot be stopped from
+    /// outside, their host functions fail from then on so that the guest traps at its next call.
+    pub fn kill(&self) {
+        self.request();
+        self.killed.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::SeqCst)
+    }
+
+    /// Whether the shutdown was requested, through [`Shutdown::request`] or a SIGTERM received by
+    /// the process.
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst) || TERMINATED.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, BufReader, ErrorKind, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use log::info;
+use oci_spec::runtime::Spec;
+use crate::utils::{oci_utils, socket_utils};
+
+/// Preamble sent by a client to open a chunked stream on a Unix socket connection.
+pub const STREAM_MAGIC: &[u8; 4] = b"RRST";
+
+/// Export called on the receiving module with the id of an incoming stream.
+pub const STREAM_ENTRYPOINT: &str = "on_stream";
+
+/// Annotation enabling cut-through forwarding of incoming streams to the next hop of a chain.
+pub const FORWARD_ANNOTATION: &str = "stream.forward";
+
+/// Size of the buffer used to drain the part of a forwarded stream the guest did not read.
+const FORWARD_BUFFER_SIZE: usize = 64 * 1024;
+
+/// How an incoming stream is passed on to the next hop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForwardMode {
+    /// The stream ends at this function.
+    Off,
+    /// Chunks are forwarded as soon as they are received while the guest processes them.
+    Tee,
+    /// The shim forwards the stream without running the guest, it is a pure relay.
+    Relay,
+}
+
+impl ForwardMode {
+    /// Reads the mode from the `stream.forward` annotation (`tee` or `relay`), `Off` otherwise.
+    pub fn from_spec(spec: &Spec) -> ForwardMode {
+        match oci_utils::get_wasm_annotations(spec, FORWARD_ANNOTATION).as_str() {
+            "tee" => ForwardMode::Tee,
+            "relay" => ForwardMode::Relay,
+            _ => ForwardMode::Off,
+        }
+    }
+}
+
+/// Writes a chunk: payload length (u32, little-endian) followed by the payload. An empty chunk
+/// marks the end of the stream.
+pub fn write_chunk_frame<W: Write>(writer: &mut W, data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_le_bytes())?;
+    writer.write_all(data)
+}
+
+/// Reads the payload of a chunked stream into buffers of any size, chunks larger than the buffer
+/// are handed out over several reads.
+pub struct ChunkReader<R: Read> {
+    reader: R,
+    remaining: usize,
+    finished: bool,
+}
+
+impl<R: Read> ChunkReader<R> {
+    pub fn new(reader: R) -> ChunkReader<R> {
+        ChunkReader { reader, remaining: 0, finished: false }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+}
+
+impl<R: Read> Read for ChunkReader<R> {
+    /// Returns 0 once the end of the stream was received.
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        while self.remaining == 0 {
+            if self.finished {
+                return Ok(0);
+            }
+            let mut header = [0u8; 4];
+            self.reader.read_exact(&mut header)?;
+            self.remaining = u32::from_le_bytes(header) as usize;
+            self.finished = self.remaining == 0;
+        }
+        let len = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..len])?;
+        if read == 0 {
+            return Err(io::Error::new(ErrorKind::UnexpectedEof, "stream closed before its end"));
+        }
+        self.remaining -= read;
+        Ok(read)
+    }
+}
+
+/// A stream accepted from a source, chunks are read from the socket when the guest asks for them
+/// and passed on to the next hops right away.
+struct Incoming {
+    reader: ChunkReader<BufReader<UnixStream>>,
+    forwards: Vec<UnixStream>,
+}
+
+impl Incoming {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        if read > 0 {
+            for forward in &mut self.forwards {
+                write_chunk_frame(forward, &buf[..read])?;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Forwards what the guest left unread, ends the forwarded streams and returns the responses
+    /// of the next hops in order.
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut response = Vec::new();
+        if self.forwards.is_empty() {
+            return Ok(response);
+        }
+        let mut buffer = vec![0u8; FORWARD_BUFFER_SIZE];
+        while self.read(&mut buffer)? > 0 {}
+        for forward in &mut self.forwards {
+            write_chunk_frame(forward, &[])?;
+            forward.shutdown(std::net::Shutdown::Write)?;
+            forward.read_to_end(&mut response)?;
+        }
+        Ok(response)
+    }
+}
+
+enum Stream {
+    /// Opened by a guest towards a target, chunks are written to the socket.
+    Outgoing(UnixStream),
+    Incoming(Incoming),
+}
+
+/// The streams of an instance. Ids are only valid for the instance that opened or accepted the
+/// stream, and the streams go with it.
+#[derive(Default)]
+pub struct Streams {
+    next_id: AtomicU32,
+    streams: Mutex<HashMap<u32, Arc<Mutex<Stream>>>>,
+}
+
+impl fmt::Debug for Streams {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Streams").field("open", &self.streams.lock().unwrap().len()).finish()
+    }
+}
+
+impl Streams {
+    fn insert(&self, stream: Stream) -> u32 {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst) + 1;
+        self.streams.lock().unwrap().insert(id, Arc::new(Mutex::new(stream)));
+        id
+    }
+
+    fn get(&self, id: u32) -> io::Result<Arc<Mutex<Stream>>> {
+        self.streams
+            .lock()
+            .unwrap()
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("unknown stream {}", id)))
+    }
+
+    /// Opens a stream to the function listening on `socket_path` and returns its id.
+    pub fn open(&self, socket_path: &str) -> io::Result<u32> {
+        let mut socket = socket_utils::connect(socket_path)?;
+        socket.write_all(STREAM_MAGIC)?;
+        let id = self.insert(Stream::Outgoing(socket));
+        info!("opened stream {} to {}", id, socket_path);
+        Ok(id)
+    }
+
+    /// Registers a stream accepted on a connection whose preamble was already consumed.
+    pub fn accept(&self, reader: BufReader<UnixStream>) -> u32 {
+        self.insert(Stream::Incoming(Incoming { reader: ChunkReader::new(reader), forwards: Vec::new() }))
+    }
+
+    /// Registers an accepted stream whose chunks are forwarded to the functions listening on
+    /// `forward_paths` as they are received.
+    pub fn accept_forwarding(&self, reader: BufReader<UnixStream>, forward_paths: &[String]) -> io::Result<u32> {
+        Ok(self.insert(Stream::Incoming(forwarding(reader, forward_paths)?)))
+    }
+
+    /// Writes a chunk to an outgoing stream. Blocks while the socket buffers are full, i.e. until
+    /// the receiver has consumed earlier chunks.
+    pub fn write(&self, id: u32, data: &[u8]) -> io::Result<()> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        match &mut *self.get(id)?.lock().unwrap() {
+            Stream::Outgoing(socket) => write_chunk_frame(socket, data),
+            Stream::Incoming(_) => Err(io::Error::new(ErrorKind::Unsupported, format!("stream {} is read-only", id))),
+        }
+    }
+
+    /// Reads the next bytes of an incoming stream into `buf`, returns 0 at the end of the stream.
+    pub fn read(&self, id: u32, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut *self.get(id)?.lock().unwrap() {
+            Stream::Incoming(incoming) => incoming.read(buf),
+            Stream::Outgoing(_) => Err(io::Error::new(ErrorKind::Unsupported, format!("stream {} is write-only", id))),
+        }
+    }
+
+    /// Closes a stream. For an outgoing stream the end is signalled to the receiver and its
+    /// response is returned once it has consumed the stream. For a forwarded incoming stream the
+    /// rest of the stream is forwarded first and the responses of the next hops are returned.
+    pub fn close(&self, id: u32) -> io::Result<Vec<u8>> {
+        let stream = self
+            .streams
+            .lock()
+            .unwrap()
+            .remove(&id)
+            .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("unknown stream {}", id)))?;
+        let mut response = Vec::new();
+        match &mut *stream.lock().unwrap() {
+            Stream::Outgoing(socket) => {
+                write_chunk_frame(socket, &[])?;
+                socket.shutdown(std::net::Shutdown::Write)?;
+                socket.read_to_end(&mut response)?;
+            }
+            Stream::Incoming(incoming) => response = incoming.finish()?,
+        }
+        Ok(response)
+    }
+
+    /// Drops the streams the guest left open once it exited, their peers see the connection
+    /// close before the end of the stream.
+    pub fn clear(&self) {
+        let streams: Vec<_> = self.streams.lock().unwrap().drain().collect();
+        if !streams.is_empty() {
+            info!("dropping {} streams left open", streams.len());
+        }
+    }
+}
+
+fn forwarding(reader: BufReader<UnixStream>, forward_paths: &[String]) -> io::Result<Incoming> {
+    let mut forwards = Vec::with_capacity(forward_paths.len());
+    for path in forward_paths {
+        let mut socket = socket_utils::connect(path)?;
+        socket.write_all(STREAM_MAGIC)?;
+        info!("forwarding stream to {}", path);
+        forwards.push(socket);
+    }
+    Ok(Incoming { reader: ChunkReader::new(reader), forwards })
+}
+
+/// Passes an accepted stream through to the functions listening on `forward_paths` without
+/// buffering it, and returns their responses.
+pub fn relay(reader: BufReader<UnixStream>, forward_paths: &[String]) -> io::Result<Vec<u8>> {
+    forwarding(reader, forward_paths)?.finish()
+}
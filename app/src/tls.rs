@@ -0,0 +1,276 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use lazy_static::lazy_static;
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{CipherSuite, ClientConfig, ClientConnection, ConnectionTrafficSecrets, ProtocolVersion, RootCertStore};
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+use crate::utils::oci_utils;
+
+/// Annotation of the PEM certificate chain a sender presents.
+pub const CERT_ANNOTATION: &str = "tls.cert";
+/// Annotation of the PEM private key of the sender's certificate.
+pub const KEY_ANNOTATION: &str = "tls.key";
+/// Annotation of the PEM certificates a receiver trusts, self-signed sender certificates included.
+pub const CA_ANNOTATION: &str = "tls.ca";
+/// Annotation of the name the receiver expects in the sender's certificate, `localhost` by default.
+pub const SERVER_NAME_ANNOTATION: &str = "tls.server_name";
+
+const DEFAULT_SERVER_NAME: &str = "localhost";
+
+// linux/tls.h
+const TCP_ULP: libc::c_int = 31;
+const SOL_TLS: libc::c_int = 282;
+const TLS_TX: libc::c_int = 1;
+const TLS_RX: libc::c_int = 2;
+const TLS_1_2_VERSION: u16 = 0x0303;
+const TLS_1_3_VERSION: u16 = 0x0304;
+const TLS_CIPHER_AES_GCM_128: u16 = 51;
+const TLS_CIPHER_AES_GCM_256: u16 = 52;
+const TLS_CIPHER_CHACHA20_POLY1305: u16 = 54;
+
+lazy_static! {
+    /// Whether the kernel takes over sessions in both directions, by protocol version and cipher.
+    static ref KTLS_PROBES: Mutex<HashMap<(u16, u16), bool>> = Mutex::new(HashMap::new());
+}
+
+/// Resolves a certificate file mounted into the container, or a path on the host.
+fn resolve_file(spec: &Spec, annotation: &str) -> Option<PathBuf> {
+    let path = oci_utils::get_wasm_annotations(spec, annotation);
+    if path.is_empty() {
+        return None;
+    }
+    let mounted = oci_utils::resolve_in_rootfs(spec, &path);
+    Some(if mounted.exists() { mounted } else { PathBuf::from(path) })
+}
+
+fn invalid_data<E: std::fmt::Display>(err: E) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, err.to_string())
+}
+
+/// Builds the configuration of a sender from the `tls.cert` and `tls.key` annotations, `None` if
+/// TLS is not enabled for the container.
+pub fn server_config(spec: &Spec) -> io::Result<Option<Arc<ServerConfig>>> {
+    let (cert, key) = match (resolve_file(spec, CERT_ANNOTATION), resolve_file(spec, KEY_ANNOTATION)) {
+        (Some(cert), Some(key)) => (cert, key),
+        _ => return Ok(None),
+    };
+    let certs = CertificateDer::pem_file_iter(&cert)
+        .map_err(invalid_data)?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(invalid_data)?;
+    let key = PrivateKeyDer::from_pem_file(&key).map_err(invalid_data)?;
+    let mut config = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(invalid_data)?;
+    // secrets are handed to the kernel after the handshake, tickets would be sent by rustls later
+    config.enable_secret_extraction = true;
+    config.send_tls13_tickets = 0;
+    Ok(Some(Arc::new(config)))
+}
+
+/// Builds the configuration of a receiver from the `tls.ca` annotation, `None` if TLS is not
+/// enabled for the container.
+pub fn client_config(spec: &Spec) -> io::Result<Option<(Arc<ClientConfig>, ServerName<'static>)>> {
+    let ca = match resolve_file(spec, CA_ANNOTATION) {
+        Some(ca) => ca,
+        None => return Ok(None),
+    };
+    let mut roots = RootCertStore::empty();
+    for cert in CertificateDer::pem_file_iter(&ca).map_err(invalid_data)? {
+        roots.add(cert.map_err(invalid_data)?).map_err(invalid_data)?;
+    }
+    let config = ClientConfig::builder().with_root_certificates(roots).with_no_client_auth();
+
+    let mut server_name = oci_utils::get_wasm_annotations(spec, SERVER_NAME_ANNOTATION);
+    if server_name.is_empty() {
+        server_name = DEFAULT_SERVER_NAME.to_string();
+    }
+    let server_name = ServerName::try_from(server_name).map_err(invalid_data)?;
+    Ok(Some((Arc::new(config), server_name)))
+}
+
+/// A sender's connection after the handshake.
+pub enum SecureStream {
+    /// Records are encrypted by the kernel, the socket is used as a plain one so that `splice`
+    /// keeps working.
+    Kernel(TcpStream),
+    /// The kernel does not support kTLS, records are encrypted by rustls.
+    User(StreamOwned<ServerConnection, TcpStream>),
+}
+
+/// Performs the handshake of a sender and hands the session to the kernel if it supports kTLS.
+pub fn accept(mut stream: TcpStream, config: Arc<ServerConfig>) -> io::Result<SecureStream> {
+    let mut connection = ServerConnection::new(config).map_err(invalid_data)?;
+    while connection.is_handshaking() {
+        connection.complete_io(&mut stream)?;
+    }
+    while connection.wants_write() {
+        connection.write_tls(&mut stream)?;
+    }
+    // records received along with the end of the handshake were decrypted by rustls already
+    let buffered = connection.process_new_packets().map_err(invalid_data)?.plaintext_bytes_to_read();
+    let version = match connection.protocol_version() {
+        Some(ProtocolVersion::TLSv1_2) => TLS_1_2_VERSION,
+        _ => TLS_1_3_VERSION,
+    };
+    // the secrets cannot be given back to rustls once extracted, so the kernel must accept the
+    // session in both directions before they are
+    let cipher_type = connection.negotiated_cipher_suite().and_then(|suite| kernel_cipher(suite.suite()));
+    let accepted = cipher_type.map_or(false, |cipher_type| ktls_accepts(version, cipher_type));
+    if buffered > 0 || !accepted || !ktls_supported(&stream) {
+        warn!("kTLS unavailable, encrypting in userspace");
+        return Ok(SecureStream::User(StreamOwned::new(connection, stream)));
+    }
+
+    let secrets = connection.dangerous_extract_secrets().map_err(invalid_data)?;
+    set_crypto_info(&stream, TLS_TX, version, secrets.tx)?;
+    set_crypto_info(&stream, TLS_RX, version, secrets.rx)?;
+    info!("kTLS enabled on {:?}", stream.peer_addr());
+    Ok(SecureStream::Kernel(stream))
+}
+
+/// Performs the handshake of a receiver. Receivers decrypt in userspace.
+pub fn connect(stream: TcpStream, config: Arc<ClientConfig>, server_name: ServerName<'static>) -> io::Result<StreamOwned<ClientConnection, TcpStream>> {
+    let connection = ClientConnection::new(config, server_name).map_err(invalid_data)?;
+    let mut stream = StreamOwned::new(connection, stream);
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    Ok(stream)
+}
+
+/// Reads a TLS stream until the peer closes it. A kTLS sender closes the socket without a
+/// `close_notify` alert, which is not treated as an error; payloads are framed by the connection.
+pub fn read_to_end<R: Read>(reader: &mut R, buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let start = buffer.len();
+    match reader.read_to_end(buffer) {
+        Ok(read) => Ok(read),
+        Err(e) if e.kind() == ErrorKind::UnexpectedEof => Ok(buffer.len() - start),
+        Err(e) => Err(e),
+    }
+}
+
+/// Ends a userspace TLS stream in the write direction.
+pub fn shutdown_write(stream: &mut StreamOwned<ServerConnection, TcpStream>) -> io::Result<()> {
+    stream.conn.send_close_notify();
+    stream.flush()?;
+    stream.sock.shutdown(std::net::Shutdown::Write)
+}
+
+/// Attaches the TLS upper layer protocol, which fails on kernels without kTLS.
+fn ktls_supported(stream: &TcpStream) -> bool {
+    let ulp = b"tls";
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_TCP,
+            TCP_ULP,
+            ulp.as_ptr() as *const libc::c_void,
+            ulp.len() as libc::socklen_t,
+        )
+    };
+    result == 0
+}
+
+/// kTLS cipher of a negotiated cipher suite, `None` if the kernel does not implement it.
+fn kernel_cipher(suite: CipherSuite) -> Option<u16> {
+    match suite {
+        CipherSuite::TLS13_AES_128_GCM_SHA256
+        | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+        | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 => Some(TLS_CIPHER_AES_GCM_128),
+        CipherSuite::TLS13_AES_256_GCM_SHA384
+        | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+        | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 => Some(TLS_CIPHER_AES_GCM_256),
+        CipherSuite::TLS13_CHACHA20_POLY1305_SHA256
+        | CipherSuite::TLS_ECDHE_ECDSA_WITH_CHACHA20_POLY1305_SHA256
+        | CipherSuite::TLS_ECDHE_RSA_WITH_CHACHA20_POLY1305_SHA256 => Some(TLS_CIPHER_CHACHA20_POLY1305),
+        _ => None,
+    }
+}
+
+/// Whether the kernel accepts sessions of `version` and `cipher_type` for sending and receiving.
+/// Kernels may lack a cipher or kTLS receive support; this is probed once, with a zero key on a
+/// loopback connection, as the secrets of a real session can only be extracted once.
+fn ktls_accepts(version: u16, cipher_type: u16) -> bool {
+    let mut probes = KTLS_PROBES.lock().unwrap();
+    *probes.entry((version, cipher_type)).or_insert_with(|| match probe_ktls(version, cipher_type) {
+        Ok(()) => true,
+        Err(e) => {
+            warn!("kTLS rejects cipher {} of TLS version {:#06x}: {}", cipher_type, version, e);
+            false
+        }
+    })
+}
+
+fn probe_ktls(version: u16, cipher_type: u16) -> io::Result<()> {
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    let _client = TcpStream::connect(listener.local_addr()?)?;
+    let (stream, _) = listener.accept()?;
+    if !ktls_supported(&stream) {
+        return Err(io::Error::last_os_error());
+    }
+    let key_len = match cipher_type {
+        TLS_CIPHER_AES_GCM_128 => 16,
+        _ => 32,
+    };
+    let info = crypto_info(version, cipher_type, &vec![0u8; key_len], &[0u8; 12], 0);
+    set_socket_option(&stream, TLS_TX, &info)?;
+    set_socket_option(&stream, TLS_RX, &info)
+}
+
+fn set_crypto_info(stream: &TcpStream, direction: libc::c_int, version: u16, secrets: (u64, ConnectionTrafficSecrets)) -> io::Result<()> {
+    let (sequence, secrets) = secrets;
+    let (cipher_type, key, iv) = match secrets {
+        ConnectionTrafficSecrets::Aes128Gcm { key, iv } => (TLS_CIPHER_AES_GCM_128, key, iv),
+        ConnectionTrafficSecrets::Aes256Gcm { key, iv } => (TLS_CIPHER_AES_GCM_256, key, iv),
+        ConnectionTrafficSecrets::Chacha20Poly1305 { key, iv } => (TLS_CIPHER_CHACHA20_POLY1305, key, iv),
+        _ => return Err(io::Error::new(ErrorKind::Unsupported, "cipher not supported by kTLS")),
+    };
+    let info = crypto_info(version, cipher_type, key.as_ref(), iv.as_ref(), sequence);
+    set_socket_option(stream, direction, &info)
+}
+
+/// Lays out the `tls12_crypto_info_*` structure of a cipher.
+fn crypto_info(version: u16, cipher_type: u16, key: &[u8], iv: &[u8], sequence: u64) -> Vec<u8> {
+    let mut info = Vec::new();
+    info.extend_from_slice(&version.to_ne_bytes());
+    info.extend_from_slice(&cipher_type.to_ne_bytes());
+    // the kernel takes the implicit part of the nonce as salt and the rest as iv
+    match cipher_type {
+        TLS_CIPHER_CHACHA20_POLY1305 => {
+            info.extend_from_slice(iv);
+            info.extend_from_slice(key);
+        }
+        _ => {
+            info.extend_from_slice(&iv[4..]);
+            info.extend_from_slice(key);
+            info.extend_from_slice(&iv[..4]);
+        }
+    }
+    info.extend_from_slice(&sequence.to_be_bytes());
+    info
+}
+
+fn set_socket_option(stream: &TcpStream, direction: libc::c_int, info: &[u8]) -> io::Result<()> {
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            SOL_TLS,
+            direction,
+            info.as_ptr() as *const libc::c_void,
+            info.len() as libc::socklen_t,
+        )
+    };
+    if result != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+use anyhow::{anyhow, Error};
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use walkdir::WalkDir;
+use crate::error::TransferError;
+use crate::peer_auth;
+use crate::utils::oci_utils;
+use crate::workflow::{self, WORKFLOW_FUNCTION_ANNOTATION};
+
+/// Annotation listing the transfers a function takes part in, as comma-separated
+/// `source->target` edges. `*` matches any function.
+pub const POLICY_ANNOTATION: &str = "transfer.policy";
+
+/// File holding the transfer policy of the node, edges separated by commas or newlines. It
+/// restricts every function of the node in addition to their own policies, which cannot lift it.
+pub const NODE_POLICY_PATH: &str = "/etc/roadrunner/transfer.policy";
+
+/// Target of the audit log entries of transfer decisions.
+pub const AUDIT_TARGET: &str = "audit";
+
+/// Function name of an unidentified sender.
+pub const UNKNOWN_SOURCE: &str = "unknown";
+
+/// The source→target edges transfers are allowed on. Functions are named by their workflow
+/// function, the name they advertise through `target.function`, or their module name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransferPolicy {
+    pub edges: Vec<(String, String)>,
+}
+
+impl TransferPolicy {
+    /// Parses `source->target` edges separated by commas or newlines.
+    pub fn parse(spec: &str) -> Result<TransferPolicy, Error> {
+        let edges = spec
+            .split(|c| c == ',' || c == '\n')
+            .map(str::trim)
+            .filter(|edge| !edge.is_empty())
+            .map(|edge| match edge.split_once("->") {
+                Some((source, target)) if !source.trim().is_empty() && !target.trim().is_empty() => {
+                    Ok((source.trim().to_string(), target.trim().to_string()))
+                }
+                _ => Err(anyhow!("malformed transfer policy edge `{}`", edge)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(TransferPolicy { edges })
+    }
+
+    /// Reads the policy of a container from the `transfer.policy` annotation, or the `policy`
+    /// edges of its workflow manifest. Returns `Ok(None)` if transfers are not restricted.
+    pub fn from_spec(spec: &Spec) -> Result<Option<TransferPolicy>, Error> {
+        let annotated = oci_utils::get_wasm_annotations(spec, POLICY_ANNOTATION);
+        if !annotated.is_empty() {
+            return Ok(Some(TransferPolicy::parse(&annotated)?));
+        }
+        Ok(workflow::load_workflow(spec)?
+            .filter(|workflow| !workflow.policy.is_empty())
+            .map(|workflow| TransferPolicy {
+                edges: workflow
+                    .policy
+                    .iter()
+                    .flat_map(|edge| edge.to.iter().map(move |to| (edge.from.clone(), to.clone())))
+                    .collect(),
+            }))
+    }
+
+    /// Reads the policy of the node from `path`. Returns `Ok(None)` if the node has none.
+    pub fn from_file(path: &Path) -> Result<Option<TransferPolicy>, Error> {
+        match fs::read_to_string(path) {
+            Ok(content) => Ok(Some(TransferPolicy::parse(&content)?)),
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(anyhow!("cannot read {}: {}", path.display(), err)),
+        }
+    }
+
+    pub fn allows(&self, source: &str, target: &str) -> bool {
+        self.edges.iter().any(|(from, to)| {
+            (from == "*" || from == source) && (to == "*" || to == target)
+        })
+    }
+}
+
+/// Checks a transfer against `policy` and records the decision in the audit log. Transfers are
+/// allowed when there is no policy.
+pub fn authorize(policy: Option<&TransferPolicy>, source: &str, target: &str) -> Result<(), TransferError> {
+    let policy = match policy {
+        Some(policy) => policy,
+        None => return Ok(()),
+    };
+    if policy.allows(source, target) {
+        info!(target: AUDIT_TARGET, "transfer allowed: {} -> {}", source, target);
+        return Ok(());
+    }
+    warn!(target: AUDIT_TARGET, "transfer denied: {} -> {}", source, target);
+    Err(TransferError::TransferDenied(source.to_string(), target.to_string()))
+}
+
+/// Returns the name identifying the function run by a container in transfer policies.
+pub fn function_name(spec: &Spec) -> String {
+    for annotation in [WORKFLOW_FUNCTION_ANNOTATION, "target.function"] {
+        let name = oci_utils::get_wasm_annotations(spec, annotation).replace("/", "");
+        if !name.is_empty() {
+            return name;
+        }
+    }
+    oci_utils::get_module_name(spec)
+}
+
+/// Identifies the function run by a local process, by matching its cgroups against the ids of
+/// the containers whose bundles are found under `bundle_root`.
+pub fn peer_function(bundle_root: &str, pid: u32) -> Option<String> {
+    let cgroups = peer_auth::process_cgroups(pid).ok()?;
+    WalkDir::new(bundle_root)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_dir() && entry.path().join("config.json").exists())
+        .find(|entry| {
+            let id = entry.file_name().to_string_lossy().to_string();
+            cgroups.iter().any(|cgroup| cgroup.contains(&id))
+        })
+        .and_then(|entry| oci_utils::load_spec(entry.path().display().to_string()).ok())
+        .map(|spec| function_name(&spec))
+}
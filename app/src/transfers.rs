@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+use lazy_static::lazy_static;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use crate::error::TransferError;
+
+/// Number of host threads performing transfers submitted by guests.
+pub const WORKER_THREADS: usize = 16;
+
+/// Status returned to guests for a transfer that has not completed yet.
+pub const PENDING: i32 = -1;
+
+/// Converts an error code of [`TransferError::code`] into the negative status returned to guests,
+/// which is kept apart from [`PENDING`].
+pub fn error_status(code: u32) -> i32 {
+    -(code as i32) - 1
+}
+
+type Completion = Option<Result<Vec<u8>, u32>>;
+
+/// A completion with the time its transfer completed.
+type Entry = (Completion, Option<Instant>);
+
+/// How long the completion of a transfer is kept once it completed, for guests that never read
+/// its result.
+pub const COMPLETION_TTL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Registry {
+    next_handle: AtomicU32,
+    completions: Mutex<HashMap<u32, Entry>>,
+    completed: Condvar,
+}
+
+lazy_static! {
+    static ref WORKERS: ThreadPool = ThreadPoolBuilder::new()
+        .num_threads(WORKER_THREADS)
+        .thread_name(|i| format!("rr-transfer-{}", i))
+        .build()
+        .expect("failed to build transfer worker pool");
+}
+
+/// The transfers submitted by the guest of an instance. Handles are only valid for the instance
+/// that submitted them; completions are released by [`Transfers::take`] or expire
+/// [`COMPLETION_TTL`] after completing, and all of them go with the instance.
+#[derive(Debug, Clone)]
+pub struct Transfers {
+    registry: Arc<Registry>,
+}
+
+impl Default for Transfers {
+    fn default() -> Self {
+        Transfers {
+            registry: Arc::new(Registry {
+                next_handle: AtomicU32::new(1),
+                completions: Mutex::new(HashMap::new()),
+                completed: Condvar::new(),
+            }),
+        }
+    }
+}
+
+impl Transfers {
+    /// Runs `transfer` on the worker pool and returns the handle of its completion.
+    pub fn submit<F>(&self, transfer: F) -> u32
+    where
+        F: FnOnce() -> Result<Vec<u8>, TransferError> + Send + 'static,
+    {
+        let handle = self.registry.next_handle.fetch_add(1, Ordering::SeqCst);
+        let mut completions = self.registry.completions.lock().unwrap();
+        expire(&mut completions);
+        completions.insert(handle, (None, None));
+        drop(completions);
+
+        let registry = self.registry.clone();
+        WORKERS.spawn(move || {
+            let result = transfer().map_err(|err| {
+                log::error!("Transfer {} failed: {}", handle, err);
+                err.code()
+            });
+            registry.completions.lock().unwrap().insert(handle, (Some(result), Some(Instant::now())));
+            registry.completed.notify_all();
+        });
+        handle
+    }
+
+    /// Returns the completion of `handle` without blocking, `None` while it is in flight.
+    /// Unknown handles complete with [`TransferError::UnknownHandle`].
+    pub fn poll(&self, handle: u32) -> Completion {
+        let mut completions = self.registry.completions.lock().unwrap();
+        expire(&mut completions);
+        match completions.get(&handle) {
+            Some((completion, _)) => completion.clone(),
+            None => Some(Err(TransferError::UnknownHandle(handle).code())),
+        }
+    }
+
+    /// Blocks until one of `handles` has completed and returns its index. Unknown handles count
+    /// as completed so that callers never wait forever.
+    pub fn wait_any(&self, handles: &[u32]) -> Option<usize> {
+        if handles.is_empty() {
+            return None;
+        }
+        let mut completions = self.registry.completions.lock().unwrap();
+        loop {
+            let ready = handles.iter().position(|handle| {
+                completions.get(handle).map_or(true, |(completion, _)| completion.is_some())
+            });
+            if ready.is_some() {
+                return ready;
+            }
+            completions = self.registry.completed.wait(completions).unwrap();
+        }
+    }
+
+    /// Removes and returns the completion of `handle` once it has completed.
+    pub fn take(&self, handle: u32) -> Completion {
+        let mut completions = self.registry.completions.lock().unwrap();
+        expire(&mut completions);
+        match completions.get(&handle) {
+            Some((None, _)) => None,
+            Some((Some(_), _)) => completions.remove(&handle).and_then(|(completion, _)| completion),
+            None => Some(Err(TransferError::UnknownHandle(handle).code())),
+        }
+    }
+}
+
+/// Drops the completions that were not read within [`COMPLETION_TTL`].
+fn expire(completions: &mut HashMap<u32, Entry>) {
+    completions.retain(|_, (_, completed_at)| completed_at.map_or(true, |at| at.elapsed() < COMPLETION_TTL));
+}
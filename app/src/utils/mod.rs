@@ -1,3 +1,5 @@
 pub mod oci_utils;
 pub mod snapshot_utils;
+pub mod module_utils;
+pub mod socket_utils;
 
@@ -0,0 +1,87 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Error};
+use log::{info, warn};
+use wasmparser::{Parser, Payload};
+use crate::utils::snapshot_utils;
+
+/// Import modules provided by the host (WasmEdge, its plugins or the shim itself).
+/// They are never looked up in the rootfs.
+pub const HOST_MODULES: [&str; 6] = [
+    "wasi_snapshot_preview1",
+    "wasi_unstable",
+    "wasi_export",
+    "wasmedge_process",
+    "wasi_ephemeral_nn",
+    "wasi_ephemeral_crypto",
+];
+
+/// A library module imported by a function, registered under its import name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleDependency {
+    pub import_name: String,
+    pub path: String,
+}
+
+/// Returns the distinct module names of the import section, in order of first appearance.
+pub fn imported_modules(wasm: &[u8]) -> Result<Vec<String>, Error> {
+    let mut modules: Vec<String> = vec![];
+    for payload in Parser::new(0).parse_all(wasm) {
+        if let Payload::ImportSection(reader) = payload? {
+            for import in reader {
+                let module = import?.module.to_string();
+                if !modules.contains(&module) {
+                    modules.push(module);
+                }
+            }
+        }
+    }
+    Ok(modules)
+}
+
+/// Resolves the library modules imported by the module at `module_path`, transitively, by locating
+/// `<import name>.wasm` under `rootfs`. Dependencies are returned in registration order, i.e. each
+/// module comes after the modules it imports from. Imports that are neither host modules nor found
+/// in the rootfs are left to WasmEdge to resolve.
+pub fn resolve_dependencies(module_path: &Path, rootfs: &Path) -> Result<Vec<ModuleDependency>, Error> {
+    let mut resolved: Vec<ModuleDependency> = vec![];
+    let mut visiting: Vec<String> = vec![];
+    resolve_into(module_path, rootfs, &mut resolved, &mut visiting)?;
+    info!("resolved module dependencies {:#?}", resolved);
+    Ok(resolved)
+}
+
+fn resolve_into(module_path: &Path, rootfs: &Path, resolved: &mut Vec<ModuleDependency>, visiting: &mut Vec<String>) -> Result<(), Error> {
+    let wasm = fs::read(module_path)?;
+    for import_name in imported_modules(&wasm)? {
+        if HOST_MODULES.contains(&import_name.as_str()) || resolved.iter().any(|dep| dep.import_name == import_name) {
+            continue;
+        }
+        if visiting.contains(&import_name) {
+            return Err(anyhow!("cyclic import of module `{}`", import_name));
+        }
+        let path = match locate_module(&import_name, rootfs) {
+            Some(path) => path,
+            None => {
+                warn!("imported module `{}` not found in {}", import_name, rootfs.display());
+                continue;
+            }
+        };
+        visiting.push(import_name.clone());
+        resolve_into(Path::new(&path), rootfs, resolved, visiting)?;
+        visiting.pop();
+        resolved.push(ModuleDependency { import_name, path });
+    }
+    Ok(())
+}
+
+fn locate_module(import_name: &str, rootfs: &Path) -> Option<String> {
+    let file_name = if import_name.ends_with(".wasm") {
+        import_name.to_string()
+    } else {
+        format!("{}.wasm", import_name)
+    };
+    snapshot_utils::get_existing_image(vec![file_name], rootfs.to_str()?.to_string())
+        .into_iter()
+        .next()
+}
@@ -1,8 +1,8 @@
-use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use containerd_shim_wasm::sandbox::{Error, oci};
 use oci_spec::runtime::Spec;
+use crate::utils::socket_utils;
 
 
 pub fn load_spec(bundle: String) -> Result<oci::Spec, Error> {
@@ -36,6 +36,16 @@ pub fn arg_to_wasi(spec: &Spec) -> Vec<String> {
     args.to_vec()
 }
 
+/// Name of the wasm module run by the container, i.e. `argv[0]` without path separators.
+pub fn get_module_name(spec: &Spec) -> String {
+    arg_to_wasi(spec).first().map(|arg| arg.replace("/", "")).unwrap_or_default()
+}
+
+/// Resolves a path as seen from inside the container against the container's rootfs.
+pub fn resolve_in_rootfs(spec: &Spec, path: &str) -> PathBuf {
+    oci::get_root(spec).join(path.trim_start_matches('/'))
+}
+
 pub fn get_wasm_mounts(spec: &Spec) -> Vec<&str> {
     let mounts: Vec<&str> = match spec.mounts() {
         Some(mounts) => mounts
@@ -67,20 +77,6 @@ pub fn get_wasm_annotations(spec: &Spec,annotation_key: &str) -> String {
 
 
 pub fn delete(bundle_path: String) -> Result<(), Error> {
-    let socket_path = format!("{}.sock", bundle_path);
-
-    if Path::new(&socket_path).exists() {
-        println!("Attempting to delete: {}", socket_path);
-
-        if let Err(e) = fs::remove_file(&socket_path) {
-            eprintln!("Failed to remove socket: {}", e);
-            return Err(Error::Others("Failed to remove socket".to_string()));
-        }
-
-        println!("Socket deleted successfully.");
-    } else {
-        println!("Socket not found: {}", socket_path);
-    }
-
+    socket_utils::remove(&format!("{}.sock", bundle_path));
     Ok(())
 }
\ No newline at end of file
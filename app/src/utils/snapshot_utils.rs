@@ -1,8 +1,8 @@
-use std::path::Path;
 use log::info;
+use oci_spec::runtime::Spec;
 use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
 use walkdir::WalkDir;
-use crate::utils::oci_utils;
+use crate::utils::{oci_utils, socket_utils};
 
 pub fn get_existing_image(image_names: Vec<String>,snapshot: String) -> Vec<String>{
     let mut images_path: Vec<String> = vec![];
@@ -18,9 +18,15 @@ pub fn get_existing_image(image_names: Vec<String>,snapshot: String) -> Vec<Stri
 
 
 pub fn find_container_path_parallel(path: &str, function_name: &str) -> String {
-    let paths: Vec<_> = WalkDir::new(path)
-        .into_iter()
-        .filter_map(|file| file.ok())
+    find_container_path_scoped(&[path.to_string()], function_name, &|_| true)
+}
+
+/// Finds the bundle of a container running `function_name` under any of `roots`, among the
+/// containers `admits` accepts.
+pub fn find_container_path_scoped(roots: &[String], function_name: &str, admits: &(dyn Fn(&Spec) -> bool + Sync)) -> String {
+    let paths: Vec<_> = roots
+        .iter()
+        .flat_map(|root| WalkDir::new(root).into_iter().filter_map(|file| file.ok()))
         .collect();
 
     paths.par_iter().find_map_any(|file| {
@@ -32,7 +38,7 @@ pub fn find_container_path_parallel(path: &str, function_name: &str) -> String {
                 let spec = oci_utils::load_spec(c_path.clone()).ok()?;
                 let args = oci_utils::arg_to_wasi(&spec);
                 let c_path_formatted = args.first()?.to_string().replace("/", "");
-                if c_path_formatted == function_name && Path::new(&(c_path.clone() + ".sock")).exists() {
+                if c_path_formatted == function_name && admits(&spec) && socket_utils::exists(&(c_path.clone() + ".sock")) {
                     return Some(c_path);
                 }
             }
@@ -40,3 +46,28 @@ pub fn find_container_path_parallel(path: &str, function_name: &str) -> String {
         None
     }).unwrap_or_else(|| String::new())
 }
+
+/// Finds the bundles of all containers running `function_name` under any of `roots`, among the
+/// containers `admits` accepts, sorted by path.
+pub fn find_container_paths_scoped(roots: &[String], function_name: &str, admits: &(dyn Fn(&Spec) -> bool + Sync)) -> Vec<String> {
+    let paths: Vec<_> = roots
+        .iter()
+        .flat_map(|root| WalkDir::new(root).into_iter().filter_map(|file| file.ok()))
+        .filter(|file| file.file_name() == "config.json")
+        .collect();
+
+    let mut containers: Vec<String> = paths.par_iter().filter_map(|file| {
+        if !file.metadata().ok()?.is_file() {
+            return None;
+        }
+        let c_path = file.path().display().to_string().replace("/config.json", "");
+        let spec = oci_utils::load_spec(c_path.clone()).ok()?;
+        let module = oci_utils::arg_to_wasi(&spec).first()?.replace("/", "");
+        if module == function_name && admits(&spec) && socket_utils::exists(&(c_path.clone() + ".sock")) {
+            return Some(c_path);
+        }
+        None
+    }).collect();
+    containers.sort();
+    containers
+}
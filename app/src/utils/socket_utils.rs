@@ -0,0 +1,150 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::fs::FileTypeExt;
+use std::os::unix::net::{SocketAddr, UnixListener, UnixStream};
+use std::path::Path;
+use std::time::Duration;
+use log::{info, warn};
+use oci_spec::runtime::Spec;
+use sha2::{Digest, Sha256};
+use walkdir::WalkDir;
+use crate::utils::oci_utils;
+
+/// Annotation binding the socket of a function in the Linux abstract namespace instead of as a
+/// `<bundle>.sock` file. Abstract sockets vanish with the listening process, so a crashed shim
+/// leaves nothing behind for discovery to trip over.
+pub const ABSTRACT_ANNOTATION: &str = "socket.abstract";
+
+/// Listening flag (`__SO_ACCEPTCON`) of a socket in `/proc/net/unix`.
+const SO_ACCEPTCON: u32 = 0x10000;
+
+/// Age below which a socket file is never swept: a shim may have bound it without being listed
+/// yet, e.g. from another network namespace.
+pub const STALE_GRACE: Duration = Duration::from_secs(60);
+
+/// Whether the container binds its socket in the abstract namespace.
+pub fn is_abstract(spec: &Spec) -> bool {
+    oci_utils::get_wasm_annotations(spec, ABSTRACT_ANNOTATION) == "true"
+}
+
+/// Name in the abstract namespace standing in for the socket file `socket_path`. Bundle paths
+/// easily exceed the 107 bytes of a socket address, so the path is hashed.
+pub fn abstract_name(socket_path: &str) -> String {
+    let digest = Sha256::digest(socket_path.as_bytes());
+    let hex: String = digest.iter().take(16).map(|byte| format!("{:02x}", byte)).collect();
+    format!("roadrunner/{}", hex)
+}
+
+/// Binds the socket of a function, at `socket_path` or under its abstract name. A socket file left
+/// by a previous listener is replaced.
+pub fn bind(socket_path: &str, abstract_namespace: bool) -> io::Result<UnixListener> {
+    if abstract_namespace {
+        let address = SocketAddr::from_abstract_name(abstract_name(socket_path).as_bytes())?;
+        return UnixListener::bind_addr(&address);
+    }
+    remove(socket_path);
+    UnixListener::bind(socket_path)
+}
+
+/// Connects to the function listening at `socket_path`, falling back to its abstract name when
+/// there is no socket file.
+pub fn connect(socket_path: &str) -> io::Result<UnixStream> {
+    match UnixStream::connect(socket_path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            let address = SocketAddr::from_abstract_name(abstract_name(socket_path).as_bytes())?;
+            UnixStream::connect_addr(&address).map_err(|_| e)
+        }
+        result => result,
+    }
+}
+
+/// Whether a function can be reached at `socket_path`, through a socket file or a listener bound
+/// under its abstract name.
+pub fn exists(socket_path: &str) -> bool {
+    Path::new(socket_path).exists()
+        || listening_sockets().map_or(false, |listening| listening.contains(&format!("@{}", abstract_name(socket_path))))
+}
+
+/// Removes the socket file `socket_path`, if any.
+pub fn remove(socket_path: &str) {
+    match fs::remove_file(socket_path) {
+        Ok(_) => info!("Socket {} deleted", socket_path),
+        Err(e) if e.kind() == ErrorKind::NotFound => {}
+        Err(e) => warn!("Failed to remove socket {}: {}", socket_path, e),
+    }
+}
+
+/// Returns the addresses of the Unix sockets listening in the network namespace of the shim,
+/// abstract ones prefixed with `@`, as listed by `/proc/net/unix`.
+pub fn listening_sockets() -> io::Result<HashSet<String>> {
+    Ok(parse_listening(&fs::read_to_string("/proc/net/unix")?))
+}
+
+/// Returns the addresses of the Unix sockets bound in the network namespace of the shim, whether
+/// they listen yet or not, as listed by `/proc/net/unix`.
+pub fn bound_sockets() -> io::Result<HashSet<String>> {
+    Ok(parse_bound(&fs::read_to_string("/proc/net/unix")?))
+}
+
+/// Parses the listening sockets out of `/proc/net/unix` lines of the form
+/// `Num RefCount Protocol Flags Type St Inode Path`.
+pub fn parse_listening(table: &str) -> HashSet<String> {
+    parse_table(table, |flags| flags & SO_ACCEPTCON != 0)
+}
+
+/// Parses the bound sockets out of `/proc/net/unix`, listening or not.
+pub fn parse_bound(table: &str) -> HashSet<String> {
+    parse_table(table, |_| true)
+}
+
+fn parse_table(table: &str, selects: impl Fn(u32) -> bool) -> HashSet<String> {
+    table
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let flags = u32::from_str_radix(fields.get(3)?, 16).ok()?;
+            let path = fields.get(7)?;
+            selects(flags).then(|| path.to_string())
+        })
+        .collect()
+}
+
+/// Removes the `.sock` files under `root` no process holds any more, left behind by shims that
+/// crashed before cleaning up. Returns the number of sockets removed. Sockets are never connected
+/// to, so live listeners do not see the sweep.
+pub fn sweep_stale(root: &str) -> usize {
+    match bound_sockets() {
+        Ok(bound) => sweep_stale_except(root, &bound, STALE_GRACE),
+        Err(e) => {
+            warn!("Skipping the stale socket sweep, the socket table cannot be read: {}", e);
+            0
+        }
+    }
+}
+
+/// Removes the `.sock` files under `root` whose path is not in `bound` and that are older than
+/// `grace`. Sockets of instances binding or listening concurrently are left alone.
+pub fn sweep_stale_except(root: &str, bound: &HashSet<String>, grace: Duration) -> usize {
+    let stale: Vec<String> = WalkDir::new(root)
+        .max_depth(3)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_socket() && entry.file_name().to_string_lossy().ends_with(".sock"))
+        .filter(|entry| {
+            let modified = entry.metadata().ok().and_then(|metadata| metadata.modified().ok());
+            modified.and_then(|modified| modified.elapsed().ok()).map_or(false, |age| age >= grace)
+        })
+        .map(|entry| entry.path().display().to_string())
+        .filter(|path| !bound.contains(path))
+        .collect();
+    for path in &stale {
+        remove(path);
+    }
+    if !stale.is_empty() {
+        info!("Removed {} stale sockets under {}", stale.len(), root);
+    }
+    stale.len()
+}
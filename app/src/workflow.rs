@@ -0,0 +1,248 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::path::{Path, PathBuf};
+use anyhow::{anyhow, Error};
+use log::info;
+use oci_spec::runtime::Spec;
+use serde::Deserialize;
+use crate::balancer::Strategy;
+use crate::compression::Codec;
+use crate::utils::oci_utils;
+
+/// Annotation referencing the workflow manifest, either a file mounted into the container
+/// (resolved against the rootfs) or a path on the host.
+pub const WORKFLOW_ANNOTATION: &str = "workflow.manifest";
+/// Annotation naming the function of the workflow run by the container, defaults to its module name.
+pub const WORKFLOW_FUNCTION_ANNOTATION: &str = "workflow.function";
+
+/// How a payload is delivered to a target function.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Transport {
+    /// Linear memory if the target is hosted in the same VM, the Unix socket otherwise,
+    /// and the network transfer as a last resort.
+    #[default]
+    Auto,
+    Memory,
+    Unix,
+    Network,
+}
+
+/// A function of the workflow.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct FunctionSpec {
+    pub name: String,
+    /// Wasm module run by the function, defaults to `name`.
+    #[serde(default)]
+    pub module: Option<String>,
+    /// Transport used to deliver payloads to this function.
+    #[serde(default)]
+    pub transport: Transport,
+    /// Address used by the network transport.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Export invoked on payloads delivered through linear memory.
+    #[serde(default)]
+    pub entrypoint: Option<String>,
+    /// Codec preferred for payloads sent to this function over the network transport.
+    #[serde(default)]
+    pub compression: Option<Codec>,
+    /// Addresses of further replicas served over the network transport.
+    #[serde(default)]
+    pub replicas: Vec<String>,
+    /// How transfers are spread over the replicas, the sender's `target.balancing` otherwise.
+    #[serde(default)]
+    pub balancing: Option<Strategy>,
+}
+
+impl FunctionSpec {
+    pub fn module_name(&self) -> String {
+        self.module.clone().unwrap_or_else(|| self.name.clone()).replace("/", "")
+    }
+}
+
+/// Edge from one function to one or more functions (`A -> {C, D}` fans out).
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Edge {
+    pub from: String,
+    #[serde(deserialize_with = "one_or_many")]
+    pub to: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct Workflow {
+    #[serde(default)]
+    pub name: String,
+    pub functions: Vec<FunctionSpec>,
+    #[serde(default)]
+    pub edges: Vec<Edge>,
+    /// Transfers allowed between functions, unrestricted when empty. Edges of the workflow are
+    /// not allowed implicitly.
+    #[serde(default)]
+    pub policy: Vec<Edge>,
+}
+
+/// The wiring of one function of a validated workflow.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WorkflowNode {
+    pub function: FunctionSpec,
+    /// Functions receiving this function's output, more than one fans out.
+    pub targets: Vec<FunctionSpec>,
+    /// Functions whose outputs this function receives, more than one fans in.
+    pub sources: Vec<FunctionSpec>,
+}
+
+impl WorkflowNode {
+    pub fn fan_in(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn fan_out(&self) -> usize {
+        self.targets.len()
+    }
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(String),
+        Many(Vec<String>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(name) => vec![name],
+        OneOrMany::Many(names) => names,
+    })
+}
+
+impl Workflow {
+    /// Parses a manifest, as YAML for `.yaml`/`.yml` files and as JSON otherwise.
+    pub fn from_file(path: &Path) -> Result<Workflow, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| anyhow!("could not read workflow manifest {}: {}", path.display(), e))?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml") | Some("yml") => Workflow::from_yaml(&content),
+            _ => Workflow::from_json(&content),
+        }
+    }
+
+    pub fn from_json(content: &str) -> Result<Workflow, Error> {
+        Ok(serde_json::from_str(content)?)
+    }
+
+    pub fn from_yaml(content: &str) -> Result<Workflow, Error> {
+        Ok(serde_yaml::from_str(content)?)
+    }
+
+    pub fn function(&self, name: &str) -> Option<&FunctionSpec> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    /// Checks that function names are unique, that edges only reference declared functions,
+    /// and that the edges form a DAG.
+    pub fn validate(&self) -> Result<(), Error> {
+        let mut in_degree: HashMap<&str, usize> = HashMap::new();
+        for function in &self.functions {
+            if in_degree.insert(function.name.as_str(), 0).is_some() {
+                return Err(anyhow!("function `{}` is declared more than once", function.name));
+            }
+            if function.transport == Transport::Network && function.address.is_none() {
+                return Err(anyhow!("function `{}` uses the network transport without an address", function.name));
+            }
+        }
+
+        let mut successors: HashMap<&str, Vec<&str>> = HashMap::new();
+        for edge in &self.edges {
+            for name in std::iter::once(&edge.from).chain(edge.to.iter()) {
+                if !in_degree.contains_key(name.as_str()) {
+                    return Err(anyhow!("edge references missing function `{}`", name));
+                }
+            }
+            for to in &edge.to {
+                successors.entry(edge.from.as_str()).or_default().push(to.as_str());
+                *in_degree.get_mut(to.as_str()).unwrap() += 1;
+            }
+        }
+
+        // Kahn's algorithm, functions left with incoming edges are part of a cycle
+        let mut queue: VecDeque<&str> = in_degree.iter().filter(|(_, d)| **d == 0).map(|(n, _)| *n).collect();
+        let mut visited = 0;
+        while let Some(name) = queue.pop_front() {
+            visited += 1;
+            for successor in successors.get(name).into_iter().flatten() {
+                let degree = in_degree.get_mut(*successor).unwrap();
+                *degree -= 1;
+                if *degree == 0 {
+                    queue.push_back(*successor);
+                }
+            }
+        }
+        if visited != self.functions.len() {
+            let mut cyclic: Vec<&str> = in_degree.iter().filter(|(_, d)| **d > 0).map(|(n, _)| *n).collect();
+            cyclic.sort();
+            return Err(anyhow!("workflow contains a cycle through {:?}", cyclic));
+        }
+        Ok(())
+    }
+
+    /// Returns the wiring of the function `name`.
+    pub fn node(&self, name: &str) -> Option<WorkflowNode> {
+        let function = self.function(name)?.clone();
+        let targets = self.edges.iter()
+            .filter(|edge| edge.from == name)
+            .flat_map(|edge| edge.to.iter())
+            .filter_map(|to| self.function(to).cloned())
+            .collect();
+        let sources = self.edges.iter()
+            .filter(|edge| edge.to.iter().any(|to| to == name))
+            .filter_map(|edge| self.function(&edge.from).cloned())
+            .collect();
+        Some(WorkflowNode { function, targets, sources })
+    }
+}
+
+fn manifest_path(spec: &Spec, manifest: &str) -> PathBuf {
+    let mounted = oci_utils::resolve_in_rootfs(spec, manifest);
+    if mounted.exists() {
+        mounted
+    } else {
+        PathBuf::from(manifest)
+    }
+}
+
+/// Loads and validates the workflow referenced by the container's annotations. Returns `Ok(None)`
+/// if no workflow is configured.
+pub fn load_workflow(spec: &Spec) -> Result<Option<Workflow>, Error> {
+    let manifest = oci_utils::get_wasm_annotations(spec, WORKFLOW_ANNOTATION);
+    if manifest.is_empty() {
+        return Ok(None);
+    }
+    let workflow = Workflow::from_file(&manifest_path(spec, &manifest))?;
+    workflow.validate()?;
+    Ok(Some(workflow))
+}
+
+/// Loads and validates the workflow referenced by the container's annotations and returns the
+/// node of the function it runs. Returns `Ok(None)` if no workflow is configured.
+pub fn load_node(spec: &Spec) -> Result<Option<WorkflowNode>, Error> {
+    let workflow = match load_workflow(spec)? {
+        Some(workflow) => workflow,
+        None => return Ok(None),
+    };
+
+    let mut function_name = oci_utils::get_wasm_annotations(spec, WORKFLOW_FUNCTION_ANNOTATION);
+    if function_name.is_empty() {
+        let module_name = oci_utils::get_module_name(spec);
+        function_name = workflow.functions.iter()
+            .find(|function| function.module_name() == module_name)
+            .map(|function| function.name.clone())
+            .unwrap_or(module_name);
+    }
+    let node = workflow.node(&function_name)
+        .ok_or_else(|| anyhow!("function `{}` is not part of workflow `{}`", function_name, workflow.name))?;
+    info!("workflow {} node {} targets {} sources {}", workflow.name, node.function.name, node.fan_out(), node.fan_in());
+    Ok(Some(node))
+}
@@ -0,0 +1,62 @@
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use tempfile::tempdir;
+    use roadrunner::advertise::{self, bind, is_dynamic, resolve, PortRange};
+    use roadrunner::compression::CompressionPolicy;
+    use roadrunner::framing;
+    use roadrunner::remote_transfer::net_transfer_serve;
+
+    #[test]
+    fn test_port_range() {
+        assert_eq!(PortRange::parse("30000-30010"), Some(PortRange { first: 30000, last: 30010 }));
+        assert_eq!(PortRange::parse("30010-30000"), None);
+        assert_eq!(PortRange::parse("30000"), None);
+        assert!(is_dynamic("127.0.0.1:0", None));
+        assert!(is_dynamic("127.0.0.1:8080", PortRange::parse("30000-30010")));
+        assert!(!is_dynamic("127.0.0.1:8080", None));
+    }
+
+    #[test]
+    fn test_bind() {
+        let ephemeral = bind("127.0.0.1:0", None).unwrap();
+        assert_ne!(ephemeral.local_addr().unwrap().port(), 0);
+
+        // a range starting at a taken port moves on to the next free one
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = taken.local_addr().unwrap().port();
+        let range = PortRange { first: port, last: port.saturating_add(20) };
+        let listener = bind("127.0.0.1:8080", Some(range)).unwrap();
+        let bound = listener.local_addr().unwrap().port();
+        assert!(bound > port && bound <= range.last);
+
+        let exhausted = bind("127.0.0.1:8080", Some(PortRange { first: port, last: port }));
+        assert!(exhausted.is_err());
+    }
+
+    #[test]
+    fn test_advertised_transfer() {
+        let dir = tempdir().unwrap();
+        let bundle = dir.path().join("sender").display().to_string();
+        assert_eq!(resolve(&bundle), None);
+
+        let listener = bind("127.0.0.1:0", None).unwrap();
+        advertise::advertise(&bundle, listener.local_addr().unwrap()).unwrap();
+        let address = resolve(&bundle).expect("address advertised next to the bundle");
+        assert_eq!(address, listener.local_addr().unwrap().to_string());
+
+        let payload = b"advertised payload".to_vec();
+        let sender = thread::spawn(move || net_transfer_serve(listener, 0, &[payload.as_slice()], CompressionPolicy::default(), None));
+        let mut client = TcpStream::connect(&address).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        drop(client);
+        sender.join().unwrap().unwrap();
+        assert_eq!(framing::split_header(received).unwrap(), (0, b"advertised payload".to_vec()));
+
+        advertise::withdraw(&bundle);
+        assert_eq!(resolve(&bundle), None);
+    }
+}
@@ -0,0 +1,86 @@
+#[cfg(test)]
+mod tests {
+    use roadrunner::balancer::{in_flight, is_healthy, select, Replica, Strategy, UNHEALTHY_AFTER};
+    use roadrunner::workflow::Workflow;
+
+    fn remotes(prefix: &str, count: usize) -> Vec<Replica> {
+        (0..count).map(|i| Replica::Remote(format!("{}-{}:9000", prefix, i))).collect()
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let replicas = remotes("rr", 3);
+        let picked: Vec<Replica> = (0..6)
+            .map(|_| select("rr", &replicas, Strategy::RoundRobin).unwrap().replica().clone())
+            .collect();
+        assert_eq!(&picked[..3], &replicas[..]);
+        assert_eq!(&picked[3..], &replicas[..]);
+    }
+
+    #[test]
+    fn test_local_replicas_preferred() {
+        let mut replicas = remotes("mixed", 2);
+        replicas.push(Replica::Local("/run/k8s.io/mixed".to_string()));
+        for _ in 0..4 {
+            let selection = select("mixed", &replicas, Strategy::Random).unwrap();
+            assert!(selection.replica().is_local());
+        }
+        assert!(select("none", &[], Strategy::RoundRobin).is_none());
+    }
+
+    #[test]
+    fn test_least_in_flight() {
+        let replicas = remotes("lif", 2);
+        let first = select("lif", &replicas, Strategy::LeastInFlight).unwrap();
+        assert_eq!(in_flight(first.replica()), 1);
+        let second = select("lif", &replicas, Strategy::LeastInFlight).unwrap();
+        assert_ne!(first.replica(), second.replica(), "the idle replica is picked");
+        let busy = first.replica().clone();
+        drop(first);
+        assert_eq!(in_flight(&busy), 0);
+        let third = select("lif", &replicas, Strategy::LeastInFlight).unwrap();
+        assert_eq!(third.replica(), &busy);
+    }
+
+    #[test]
+    fn test_unhealthy_replicas_skipped() {
+        let replicas = remotes("health", 2);
+        for _ in 0..UNHEALTHY_AFTER {
+            let selection = select("health-probe", &replicas[..1], Strategy::RoundRobin).unwrap();
+            selection.failed();
+        }
+        assert!(!is_healthy(&replicas[0]));
+        for _ in 0..4 {
+            assert_eq!(select("health", &replicas, Strategy::RoundRobin).unwrap().replica(), &replicas[1]);
+        }
+        // with no healthy replica left, unhealthy ones are tried again
+        assert_eq!(select("health-probe", &replicas[..1], Strategy::RoundRobin).unwrap().replica(), &replicas[0]);
+        select("health-probe", &replicas[..1], Strategy::RoundRobin).unwrap().succeeded();
+        assert!(is_healthy(&replicas[0]));
+    }
+
+    #[test]
+    fn test_reported_outcome() {
+        let replica = Replica::Local("/run/k8s.io/reported".to_string());
+        assert_eq!(replica.socket_path(), Some("/run/k8s.io/reported.sock".to_string()));
+        assert_eq!(remotes("reported", 1)[0].socket_path(), None);
+        for _ in 0..UNHEALTHY_AFTER {
+            select("reported", &[replica.clone()], Strategy::RoundRobin).unwrap().report(false);
+        }
+        assert!(!is_healthy(&replica));
+        select("reported", &[replica.clone()], Strategy::RoundRobin).unwrap().report(true);
+        assert!(is_healthy(&replica));
+    }
+
+    #[test]
+    fn test_strategy_configuration() {
+        assert_eq!(Strategy::parse("least-in-flight"), Some(Strategy::LeastInFlight));
+        assert_eq!(Strategy::parse("sticky"), None);
+        let workflow = Workflow::from_yaml(
+            "functions:\n  - name: resize\n    address: 10.0.0.2:9000\n    replicas: [10.0.0.3:9000]\n    balancing: random\n",
+        ).unwrap();
+        let resize = workflow.function("resize").unwrap();
+        assert_eq!(resize.balancing, Some(Strategy::Random));
+        assert_eq!(resize.replicas, vec!["10.0.0.3:9000".to_string()]);
+    }
+}
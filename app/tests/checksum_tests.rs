@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use roadrunner::checksum::{self, is_mismatch, seal, verify, Algorithm, ACK_MISMATCH, TRAILER_LEN};
+    use roadrunner::error::TransferError;
+    use roadrunner::remote_transfer::handle_client_vectored;
+
+    #[test]
+    fn test_seal_and_verify() {
+        for algorithm in [Algorithm::Crc32c, Algorithm::Xxh3] {
+            let sealed = seal(b"payload".to_vec(), Some(algorithm));
+            assert_eq!(sealed.len(), b"payload".len() + TRAILER_LEN);
+            assert_eq!(verify(sealed.clone()).unwrap(), b"payload".to_vec());
+
+            let mut corrupted = sealed;
+            corrupted[0] ^= 0xff;
+            let err = verify(corrupted).expect_err("corruption must be detected");
+            assert!(is_mismatch(&err));
+            assert!(matches!(TransferError::from(err), TransferError::ChecksumMismatch));
+        }
+        // A payload announced with a checksum but sent without a trailer does not match
+        assert!(is_mismatch(&verify(seal(b"payload".to_vec(), None)).unwrap_err()));
+    }
+
+    #[test]
+    fn test_trailer_covers_gathered_segments() {
+        let trailer = checksum::trailer(Algorithm::Xxh3, &[b"head".as_slice(), b"body".as_slice()]);
+        let mut received = b"headbody".to_vec();
+        received.extend_from_slice(&trailer);
+        assert_eq!(verify(received).unwrap(), b"headbody".to_vec());
+    }
+
+    #[test]
+    fn test_sender_learns_about_mismatch() {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let address = listener.local_addr().unwrap();
+
+        let sender = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept connection");
+            let trailer = checksum::trailer(Algorithm::Crc32c, &[b"expected".as_slice()]);
+            handle_client_vectored(stream, &[b"tampered".as_slice(), trailer.as_slice()])
+        });
+
+        // Receiver verifies the payload and acknowledges like `connect_to_source`
+        let mut client = TcpStream::connect(address).expect("Failed to connect to sender");
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert!(is_mismatch(&verify(received).unwrap_err()));
+        client.write_all(&[ACK_MISMATCH]).unwrap();
+        drop(client);
+
+        let err = sender.join().unwrap().expect_err("sender must see the mismatch");
+        assert!(is_mismatch(&err));
+    }
+}
@@ -0,0 +1,85 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use roadrunner::compression::{self, compress, decompress, hello, parse_hello, stats, Codec, CompressionPolicy, MAX_DECOMPRESSED_LEN};
+    use roadrunner::framing;
+    use roadrunner::remote_transfer::net_transfer_bind_compressed;
+    use roadrunner::workflow::Workflow;
+
+    fn get_free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .expect("Failed to bind to get free port")
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[test]
+    fn test_compress_roundtrip() {
+        let payload = b"columnar,columnar,columnar,".repeat(1000);
+        for codec in [Codec::Lz4, Codec::Zstd] {
+            let frame = compress(codec, &[&payload[..10], &payload[10..]]).unwrap();
+            assert!(frame.len() < payload.len(), "{:?} should shrink repetitive data", codec);
+            assert_eq!(decompress(frame).unwrap(), payload);
+            assert!(stats(codec).ratio() > 1.0);
+        }
+        assert!(decompress(b"uncompressed".to_vec()).is_err(), "only frames are decompressed");
+    }
+
+    #[test]
+    fn test_oversized_frame_rejected() {
+        let mut frame = compress(Codec::Lz4, &[b"small".as_slice()]).unwrap();
+        frame[5..13].copy_from_slice(&(MAX_DECOMPRESSED_LEN as u64 + 1).to_le_bytes());
+        let err = decompress(frame).expect_err("length above the limit must be rejected");
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_negotiation_and_threshold() {
+        assert_eq!(parse_hello(&hello(&[Codec::Zstd])), Some(vec![Codec::Zstd]));
+        assert_eq!(parse_hello(b"data"), None);
+
+        let policy = CompressionPolicy { codec: Some(Codec::Lz4), threshold: 1024 };
+        assert!(!policy.applies(1023));
+        assert!(policy.applies(1024));
+        assert_eq!(policy.negotiate(&[Codec::Zstd]), None, "receiver must accept the codec");
+        assert_eq!(policy.with_codec(Some(Codec::Zstd)).negotiate(&[Codec::Zstd]), Some(Codec::Zstd));
+        assert!(!CompressionPolicy::default().applies(usize::MAX));
+
+        let workflow = Workflow::from_yaml("functions:\n  - name: a\n    address: 10.0.0.2:9000\n    compression: zstd\n").unwrap();
+        assert_eq!(workflow.function("a").unwrap().compression, Some(Codec::Zstd));
+    }
+
+    #[test]
+    fn test_compressed_network_transfer() {
+        let address = format!("127.0.0.1:{}", get_free_port());
+        let payload = vec![42u8; 256 * 1024];
+        let expected = payload.clone();
+        let server_address = address.clone();
+        let sender = thread::spawn(move || {
+            let policy = CompressionPolicy { codec: Some(Codec::Zstd), threshold: 1024 };
+            net_transfer_bind_compressed(&[payload.as_slice()], server_address, policy)
+        });
+
+        // Receiver advertises its codecs like `connect_to_source`
+        let mut client = loop {
+            match TcpStream::connect(&address) {
+                Ok(client) => break client,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        client.write_all(&hello(&compression::SUPPORTED)).unwrap();
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        assert!(received.len() < expected.len(), "payload should travel compressed");
+        let (flags, frame) = framing::split_header(received).unwrap();
+        assert!(framing::has(flags, framing::COMPRESSED), "compression is announced in the header");
+        assert_eq!(decompress(frame).unwrap(), expected);
+        drop(client);
+
+        sender.join().unwrap().expect("transfer should succeed");
+    }
+}
@@ -7,7 +7,12 @@ mod tests {
     use std::path::Path;
     use tempfile::tempdir;
     use wasmedge_sdk::{Vm, WasmValue, Caller, Store, CallingFrame};
-    use roadrunner::data_hose::{find_function_metadata, read_memory_host, transfer_data_within_wasm_vm};
+    use std::collections::HashMap;
+    use oci_spec::runtime::Spec;
+    use roadrunner::data_hose::{find_function_metadata, read_memory_host, read_memory_intra_vm, transfer_data_within_wasm_vm, IntraVmCall, DEFAULT_ENTRYPOINT};
+    use roadrunner::host_context::HostContext;
+    use roadrunner::transfers;
+    use roadrunner::error::TransferError;
     use wasmedge_sdk::error::HostFuncError;
 
     #[test]
@@ -49,12 +54,46 @@ mod tests {
     #[test]
     fn test_transfer_data_within_wasm_vm() {
         let vm_shared = Arc::new(Mutex::new(Vm::new(None).expect("Failed to create VM")));
-        let source_function_name = "test_module".to_string();
+        let call = IntraVmCall::new("test_module".to_string(), String::new());
         let address = 0;
         let len = 10;
 
-        let result = transfer_data_within_wasm_vm(&vm_shared, &source_function_name, address, len);
+        let result = transfer_data_within_wasm_vm(&vm_shared, "main", &call, address, len);
 
-        assert!(result.is_ok(), "transfer_data_within_wasm_vm should execute successfully");
+        assert!(
+            matches!(result, Err(TransferError::ModuleNotFound(_))),
+            "transfer_data_within_wasm_vm should fail for modules not registered in the VM"
+        );
+    }
+
+    #[test]
+    fn test_failed_intra_vm_transfer_returns_status() {
+        let vm_shared = Arc::new(Mutex::new(Vm::new(None).expect("Failed to create VM")));
+        let context = HostContext::new(Spec::default(), "/run/bundle/sender", None);
+        let call = IntraVmCall::new("test_module".to_string(), String::new());
+        let input = [WasmValue::from_i32(0), WasmValue::from_i32(10)];
+
+        // the guest gets the status of the error instead of trapping
+        let result = read_memory_intra_vm(&context, &vm_shared, "main", &call, &input).expect("guest must not trap");
+        let expected = transfers::error_status(TransferError::ModuleNotFound(String::new()).code());
+        assert_eq!(result[0].to_i32(), expected);
+    }
+
+    #[test]
+    fn test_intra_vm_call_from_spec() {
+        let mut annotations = HashMap::new();
+        annotations.insert("target.module".to_string(), "/alice-lib".to_string());
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations.clone()));
+
+        let call = IntraVmCall::from_spec(&spec).expect("call should be configured");
+        assert_eq!(call.target, "alice-lib");
+        assert_eq!(call.entrypoint, DEFAULT_ENTRYPOINT);
+
+        annotations.insert("target.entrypoint".to_string(), "hello_greet".to_string());
+        spec.set_annotations(Some(annotations));
+        assert_eq!(IntraVmCall::from_spec(&spec).unwrap().entrypoint, "hello_greet");
+
+        assert!(IntraVmCall::from_spec(&Spec::default()).is_none());
     }
 }
\ No newline at end of file
@@ -0,0 +1,74 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs::{self, File};
+    use std::path::Path;
+    use oci_spec::runtime::Spec;
+    use tempfile::tempdir;
+    use roadrunner::data_hose::find_function_metadata_scoped;
+    use roadrunner::discovery::{DiscoveryScope, Scope, NAMESPACES_ANNOTATION, SANDBOX_ID_ANNOTATION, SCOPE_ANNOTATION};
+
+    fn spec_with(annotations: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> =
+            annotations.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    /// Creates a bundle advertising `function` with its socket next to it.
+    fn create_bundle(root: &Path, namespace: &str, id: &str, function: &str, sandbox_id: &str) {
+        let bundle = root.join(namespace).join(id);
+        fs::create_dir_all(&bundle).unwrap();
+        let config = format!(
+            r#"{{"annotations": {{"target.function": "{}", "target.address": "127.0.0.1:8080", "{}": "{}"}}}}"#,
+            function, SANDBOX_ID_ANNOTATION, sandbox_id
+        );
+        fs::write(bundle.join("config.json"), config).unwrap();
+        File::create(root.join(namespace).join(format!("{}.sock", id))).unwrap();
+    }
+
+    #[test]
+    fn test_scope_from_spec() {
+        let scope = DiscoveryScope::from_spec(&spec_with(&[]), "/run/containerd/io.containerd.runtime.v2.task/k8s.io/abc");
+        assert_eq!(scope.scope, Scope::Namespace);
+        assert_eq!(scope.namespace, "k8s.io");
+        assert_eq!(scope.sandbox_id, None);
+        assert_eq!(scope.roots("/state/"), vec!["/state/k8s.io".to_string()]);
+
+        let spec = spec_with(&[(SCOPE_ANNOTATION, "pod"), (NAMESPACES_ANNOTATION, "shared, k8s.io"), (SANDBOX_ID_ANNOTATION, "s1")]);
+        let scope = DiscoveryScope::from_spec(&spec, "/state/k8s.io/abc");
+        assert_eq!(scope.scope, Scope::Pod);
+        assert_eq!(scope.roots("/state"), vec!["/state/k8s.io".to_string(), "/state/shared".to_string()]);
+        assert!(scope.admits(&spec_with(&[(SANDBOX_ID_ANNOTATION, "s1")])));
+        assert!(!scope.admits(&spec_with(&[(SANDBOX_ID_ANNOTATION, "s2")])));
+
+        let scope = DiscoveryScope::from_spec(&spec_with(&[(SCOPE_ANNOTATION, "node")]), "/state/k8s.io/abc");
+        assert_eq!(scope.roots("/state"), vec!["/state".to_string()]);
+    }
+
+    #[test]
+    fn test_scoped_discovery() {
+        let state = tempdir().unwrap();
+        create_bundle(state.path(), "tenant-b", "other", "alice-lib", "s2");
+        let root = state.path().to_str().unwrap();
+        let own_bundle = state.path().join("tenant-a").join("self");
+        fs::create_dir_all(&own_bundle).unwrap();
+
+        let scope = DiscoveryScope::from_spec(&spec_with(&[]), own_bundle.to_str().unwrap());
+        assert!(find_function_metadata_scoped(&scope.roots(root), &|spec| scope.admits(spec)).is_none(),
+            "functions of other namespaces are not discovered by default");
+
+        let opted_in = DiscoveryScope::from_spec(&spec_with(&[(NAMESPACES_ANNOTATION, "tenant-b")]), own_bundle.to_str().unwrap());
+        let (_, name, _) = find_function_metadata_scoped(&opted_in.roots(root), &|spec| opted_in.admits(spec)).unwrap();
+        assert_eq!(name, "alice-lib");
+
+        create_bundle(state.path(), "tenant-a", "neighbour", "bob-lib", "s3");
+        let pod = DiscoveryScope::from_spec(&spec_with(&[(SCOPE_ANNOTATION, "pod"), (SANDBOX_ID_ANNOTATION, "s1")]), own_bundle.to_str().unwrap());
+        assert!(find_function_metadata_scoped(&pod.roots(root), &|spec| pod.admits(spec)).is_none(),
+            "functions of other pods are not discovered in the pod scope");
+        create_bundle(state.path(), "tenant-a", "sidecar", "carol-lib", "s1");
+        let (_, name, _) = find_function_metadata_scoped(&pod.roots(root), &|spec| pod.admits(spec)).unwrap();
+        assert_eq!(name, "carol-lib");
+    }
+}
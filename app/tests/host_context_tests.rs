@@ -0,0 +1,106 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use std::sync::Arc;
+    use oci_spec::runtime::Spec;
+    use roadrunner::balancer::Strategy;
+    use roadrunner::host_context::{state_root, HostContext, TransferConfig};
+    use roadrunner::pod::{get_module, register_module, unregister_module};
+    use roadrunner::tls::{CERT_ANNOTATION, KEY_ANNOTATION};
+    use tempfile::tempdir;
+
+    fn spec_with(annotations: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> =
+            annotations.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    #[test]
+    fn test_state_root() {
+        assert_eq!(state_root("/run/containerd/io.containerd.runtime.v2.task/default/abc"), "/run/containerd/io.containerd.runtime.v2.task/");
+        assert_eq!(state_root("/run/containerd/io.containerd.runtime.v2.task/default/abc/"), "/run/containerd/io.containerd.runtime.v2.task/");
+        assert_eq!(state_root("abc"), "");
+    }
+
+    #[test]
+    fn test_config_from_spec() {
+        let spec = spec_with(&[
+            ("target.function", "resize"),
+            ("target.balancing", "random"),
+            ("target.routes", "resize:v1"),
+            ("transfer.policy", "resize"),
+        ]);
+        let context = HostContext::new(spec, "/run/containerd/io.containerd.runtime.v2.task/default/abc", None);
+        assert_eq!(context.config.function_name, "resize");
+        assert_eq!(context.config.balancing, Strategy::Random);
+        assert_eq!(context.scope.namespace, "default");
+        // invalid routes are ignored, an invalid policy denies all transfers
+        assert!(context.config.routes.is_none());
+        assert!(context.config.policy.as_ref().map_or(false, |policy| policy.edges.is_empty()));
+        assert!(context.registry.is_none());
+    }
+
+    #[test]
+    fn test_node_policy_cannot_be_lifted() {
+        let dir = tempfile::tempdir().unwrap();
+        let node_policy = dir.path().join("transfer.policy");
+        std::fs::write(&node_policy, "ingest->resize\nresize->store\n").unwrap();
+
+        // the container allows any transfer, the node does not
+        let config = TransferConfig::with_node_policy(&spec_with(&[("transfer.policy", "*->*")]), &node_policy);
+        assert!(config.restricts());
+        assert!(config.authorize("ingest", "resize").is_ok());
+        assert_eq!(config.authorize("ingest", "store").unwrap_err().code(), 12);
+
+        // the container restricts the node policy further
+        let config = TransferConfig::with_node_policy(&spec_with(&[("transfer.policy", "resize->store")]), &node_policy);
+        assert!(config.authorize("ingest", "resize").is_err());
+        assert!(config.authorize("resize", "store").is_ok());
+
+        let config = TransferConfig::with_node_policy(&spec_with(&[]), &dir.path().join("missing"));
+        assert!(!config.restricts());
+        assert!(config.authorize("any", "thing").is_ok());
+    }
+
+    #[test]
+    fn test_contexts_are_per_module() {
+        let first = Arc::new(HostContext::new(spec_with(&[("target.function", "first")]), "/run/ns/first", None));
+        let second = Arc::new(HostContext::new(spec_with(&[("transfer.checksum", "xxh3")]), "/run/ns/second", None));
+        register_module("context-first.wasm", first.clone());
+        register_module("context-second.wasm", second.clone());
+
+        let resolved = get_module("context-first.wasm").unwrap().context;
+        assert!(Arc::ptr_eq(&resolved, &first));
+        assert!(resolved.config.checksum.is_none());
+        let resolved = get_module("context-second.wasm").unwrap().context;
+        assert!(Arc::ptr_eq(&resolved, &second));
+        assert!(resolved.config.checksum.is_some());
+
+        unregister_module("context-first.wasm", "/run/ns/first");
+        unregister_module("context-second.wasm", "/run/ns/second");
+    }
+
+    #[test]
+    fn test_tls_config_loaded_once() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let cert = temp_dir.path().join("localhost.pem");
+        let key = temp_dir.path().join("localhost.key");
+        let certs = format!("{}/tests/certs", env!("CARGO_MANIFEST_DIR"));
+        fs::copy(format!("{}/localhost.pem", certs), &cert).unwrap();
+        fs::copy(format!("{}/localhost.key", certs), &key).unwrap();
+        let spec = spec_with(&[(CERT_ANNOTATION, &cert.display().to_string()), (KEY_ANNOTATION, &key.display().to_string())]);
+        let context = HostContext::new(spec, "/run/containerd/io.containerd.runtime.v2.task/default/tls", None);
+
+        // transfers reuse the configuration loaded with the context
+        fs::remove_file(&cert).unwrap();
+        fs::remove_file(&key).unwrap();
+        let config = context.tls_config().unwrap().expect("sender TLS enabled");
+        assert!(Arc::ptr_eq(&config, &context.tls_config().unwrap().unwrap()));
+
+        let spec = spec_with(&[(CERT_ANNOTATION, &cert.display().to_string()), (KEY_ANNOTATION, &key.display().to_string())]);
+        assert!(HostContext::new(spec, "/run/containerd/io.containerd.runtime.v2.task/default/tls", None).tls_config().is_err());
+    }
+}
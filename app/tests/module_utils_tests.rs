@@ -0,0 +1,70 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use tempfile::tempdir;
+    use roadrunner::utils::module_utils::{imported_modules, resolve_dependencies, ModuleDependency};
+
+    /// Builds a minimal wasm binary importing one `() -> ()` function per `(module, name)` pair.
+    fn module_importing(imports: &[(&str, &str)]) -> Vec<u8> {
+        let mut wasm = vec![0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00];
+        // type section: one `() -> ()` function type
+        wasm.extend_from_slice(&[0x01, 0x04, 0x01, 0x60, 0x00, 0x00]);
+
+        let mut section = vec![imports.len() as u8];
+        for (module, name) in imports {
+            section.push(module.len() as u8);
+            section.extend_from_slice(module.as_bytes());
+            section.push(name.len() as u8);
+            section.extend_from_slice(name.as_bytes());
+            section.extend_from_slice(&[0x00, 0x00]);
+        }
+        wasm.push(0x02);
+        wasm.push(section.len() as u8);
+        wasm.extend_from_slice(&section);
+        wasm
+    }
+
+    #[test]
+    fn test_imported_modules() {
+        let wasm = module_importing(&[
+            ("wasi_snapshot_preview1", "fd_write"),
+            ("alice-lib", "hello"),
+            ("alice-lib", "allocate"),
+        ]);
+
+        let modules = imported_modules(&wasm).expect("import section should parse");
+        assert_eq!(modules, vec!["wasi_snapshot_preview1".to_string(), "alice-lib".to_string()]);
+    }
+
+    #[test]
+    fn test_resolve_dependencies() {
+        let rootfs = tempdir().expect("Failed to create temp directory");
+        let main_path = rootfs.path().join("main.wasm");
+        let lib_path = rootfs.path().join("lib").join("alice-lib.wasm");
+        let base_path = rootfs.path().join("base.wasm");
+
+        fs::create_dir_all(lib_path.parent().unwrap()).unwrap();
+        fs::write(&main_path, module_importing(&[("wasi_export", "read_memory_host"), ("alice-lib", "hello")])).unwrap();
+        fs::write(&lib_path, module_importing(&[("base", "init")])).unwrap();
+        fs::write(&base_path, module_importing(&[])).unwrap();
+
+        let dependencies = resolve_dependencies(&main_path, rootfs.path()).expect("dependencies should resolve");
+
+        assert_eq!(dependencies, vec![
+            ModuleDependency { import_name: "base".to_string(), path: base_path.display().to_string() },
+            ModuleDependency { import_name: "alice-lib".to_string(), path: lib_path.display().to_string() },
+        ]);
+    }
+
+    #[test]
+    fn test_resolve_dependencies_cycle() {
+        let rootfs = tempdir().expect("Failed to create temp directory");
+        let main_path = rootfs.path().join("main.wasm");
+
+        fs::write(&main_path, module_importing(&[("a", "f")])).unwrap();
+        fs::write(rootfs.path().join("a.wasm"), module_importing(&[("b", "f")])).unwrap();
+        fs::write(rootfs.path().join("b.wasm"), module_importing(&[("a", "f")])).unwrap();
+
+        assert!(resolve_dependencies(&main_path, rootfs.path()).is_err(), "cyclic imports must be rejected");
+    }
+}
@@ -0,0 +1,111 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read};
+    use std::os::unix::net::UnixListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use tempfile::tempdir;
+    use std::io::ErrorKind;
+    use roadrunner::mux::{is_unsent, read_frame, request, request_vectored, serve, write_frame, write_frame_vectored, MAX_FRAME_LEN, MUX_MAGIC};
+
+    #[test]
+    fn test_frame_roundtrip() {
+        let mut buffer = Vec::new();
+        write_frame(&mut buffer, 42, 0, b"payload").unwrap();
+        write_frame(&mut buffer, 43, 1, b"").unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((42, 0, b"payload".to_vec())));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((43, 1, Vec::new())));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+    }
+
+    #[test]
+    fn test_vectored_frame_gathers_segments() {
+        let mut buffer = Vec::new();
+        write_frame_vectored(&mut buffer, 7, 0, &[b"header|", b"", b"body"]).unwrap();
+
+        let mut reader = buffer.as_slice();
+        assert_eq!(read_frame(&mut reader).unwrap(), Some((7, 0, b"header|body".to_vec())));
+    }
+
+    #[test]
+    fn test_oversized_frame_is_rejected_before_allocation() {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(&1u64.to_le_bytes());
+        buffer.push(0);
+        buffer.extend_from_slice(&u32::MAX.to_le_bytes());
+
+        let err = read_frame(&mut buffer.as_slice()).expect_err("frame should be rejected");
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+        assert!((u32::MAX as usize) > MAX_FRAME_LEN);
+    }
+
+    #[test]
+    fn test_concurrent_requests_share_one_connection() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let socket_path = temp_dir.path().join("target.sock").display().to_string();
+        let listener = UnixListener::bind(&socket_path).expect("Failed to bind test socket");
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let server_accepted = accepted.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut socket = stream.unwrap();
+                server_accepted.fetch_add(1, Ordering::SeqCst);
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(socket.try_clone().unwrap());
+                    let mut magic = [0u8; 4];
+                    reader.read_exact(&mut magic).unwrap();
+                    assert_eq!(&magic, MUX_MAGIC);
+                    serve(&mut reader, &mut socket, |_, input| {
+                        let mut reply = b"echo:".to_vec();
+                        reply.extend_from_slice(&input);
+                        Ok(reply)
+                    }).unwrap();
+                });
+            }
+        });
+
+        let handles: Vec<_> = (0..100)
+            .map(|task| {
+                let path = socket_path.clone();
+                thread::spawn(move || {
+                    let payload = format!("task={}", task);
+                    let reply = request(&path, payload.as_bytes()).expect("request failed");
+                    assert_eq!(reply, format!("echo:{}", payload).into_bytes());
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().expect("task panicked");
+        }
+
+        let reply = request_vectored(&socket_path, &[b"col1,", b"col2"]).expect("vectored request failed");
+        assert_eq!(reply, b"echo:col1,col2".to_vec());
+
+        assert_eq!(accepted.load(Ordering::SeqCst), 1, "all tasks should share one connection");
+    }
+
+    #[test]
+    fn test_failures_after_sending_are_not_unsent() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let missing = temp_dir.path().join("missing.sock").display().to_string();
+        assert!(is_unsent(&request(&missing, b"payload").unwrap_err()));
+
+        // a request answered with an error was delivered, the target may have run it
+        let socket_path = temp_dir.path().join("failing.sock").display().to_string();
+        let listener = UnixListener::bind(&socket_path).expect("Failed to bind test socket");
+        thread::spawn(move || {
+            let mut socket = listener.incoming().next().unwrap().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic).unwrap();
+            serve(&mut reader, &mut socket, |_, _| Err(std::io::Error::new(ErrorKind::Other, "entrypoint failed"))).unwrap();
+        });
+        let err = request(&socket_path, b"payload").unwrap_err();
+        assert!(!is_unsent(&err));
+        assert!(!is_unsent(&request_vectored(&socket_path, &[&vec![0u8; MAX_FRAME_LEN + 1]]).unwrap_err()));
+    }
+}
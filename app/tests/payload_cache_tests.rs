@@ -0,0 +1,49 @@
+#[cfg(test)]
+mod tests {
+    use roadrunner::payload_cache::{lease, materialize, read, set_capacity, usage, CacheRef, DEFAULT_CAPACITY};
+
+    #[test]
+    fn test_reference_roundtrip() {
+        let reference = CacheRef { digest: [7u8; 32], pid: 4242, fd: 17, len: 1 << 20 };
+        let encoded = reference.encode();
+        assert_eq!(CacheRef::decode(&encoded), Some(reference));
+        assert_eq!(CacheRef::decode(&encoded[1..]), None);
+        assert!(materialize(b"plain payload", 4242).is_err(), "only references are materialized");
+    }
+
+    // Cache state is process-wide, deduplication and eviction are checked in one test
+    #[test]
+    fn test_dedup_refcount_and_eviction() {
+        let payload = vec![1u8; 128 * 1024];
+        let first = lease(&payload).expect("payload should be cached");
+        let second = lease(&payload).expect("payload should be cached");
+        assert_eq!(first.reference(), second.reference(), "same content must share one copy");
+        assert_eq!(usage(), (1, payload.len() as u64));
+
+        // Receivers materialize the payload from the memfd of the sending process only
+        let pid = std::process::id();
+        assert_eq!(materialize(&first.reference().encode(), pid).unwrap(), payload);
+        assert!(materialize(&first.reference().encode(), pid + 1).is_err(), "references to other processes are rejected");
+        let oversized = CacheRef { len: u64::MAX, ..first.reference().clone() };
+        assert!(materialize(&oversized.encode(), pid).is_err(), "lengths beyond the payload are rejected");
+        let proc_path = format!("/proc/{}/fd/{}", first.reference().pid, first.reference().fd);
+        assert_eq!(std::fs::read(proc_path).unwrap(), payload);
+
+        // Referenced payloads survive eviction until their last lease is released
+        set_capacity(0);
+        assert_eq!(usage().0, 1);
+        drop(first);
+        let reference = second.reference().clone();
+        assert_eq!(read(&reference).unwrap(), payload);
+        drop(second);
+        assert_eq!(usage(), (0, 0));
+        assert!(read(&reference).is_err(), "evicted payloads cannot be read");
+
+        set_capacity(DEFAULT_CAPACITY);
+        let other = vec![2u8; 64 * 1024];
+        let leased = lease(&other).unwrap();
+        drop(leased);
+        assert_eq!(usage(), (1, other.len() as u64), "unreferenced payloads stay cached within capacity");
+        set_capacity(0);
+    }
+}
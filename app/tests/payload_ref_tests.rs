@@ -0,0 +1,89 @@
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use tempfile::{tempdir, TempDir};
+    use roadrunner::error::TransferError;
+    use roadrunner::host_context::HostContext;
+    use roadrunner::payload_ref::{self, close, fetch, publish, read_range, release, PayloadRef};
+
+    /// Context of an instance whose bundle is in a fresh directory, where its range server binds.
+    fn instance(name: &str) -> (TempDir, HostContext) {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let bundle_path = temp_dir.path().join(name).display().to_string();
+        (temp_dir, HostContext::new(Spec::default(), &bundle_path, None))
+    }
+
+    /// Sends a range request to the socket of `reference` as a receiver in another shim would.
+    fn remote_fetch(reference: &PayloadRef, socket_path: &str, offset: u64, len: u32) -> std::io::Result<Vec<u8>> {
+        roadrunner::mux::request(socket_path, &[
+            reference.id.to_le_bytes().as_slice(),
+            offset.to_le_bytes().as_slice(),
+            len.to_le_bytes().as_slice(),
+        ].concat())
+    }
+
+    #[test]
+    fn test_reference_roundtrip() {
+        let reference = PayloadRef { socket_path: "/run/function.refs.sock".to_string(), id: u128::MAX - 42, len: 1 << 40 };
+        let encoded = reference.encode();
+        assert_eq!(PayloadRef::decode(&encoded), Some(reference));
+        assert_eq!(PayloadRef::decode(&encoded[..encoded.len() - 1]), None);
+        assert_eq!(PayloadRef::decode(b"plain payload, not a reference"), None);
+    }
+
+    #[test]
+    fn test_fetch_ranges_of_published_payload() {
+        let (_dir, context) = instance("publisher");
+        let payload: Vec<u8> = (0..=255u8).cycle().take(10_000).collect();
+        let reference = publish(payload.clone(), Duration::from_secs(60), &context).unwrap();
+        assert_eq!(reference.len, 10_000);
+        assert_eq!(reference.socket_path, payload_ref::socket_path(&context.bundle_path));
+
+        assert_eq!(fetch(&reference, 0, 16).unwrap(), payload[..16].to_vec());
+        assert_eq!(fetch(&reference, 9_990, 100).unwrap(), payload[9_990..].to_vec());
+        assert!(fetch(&reference, 20_000, 10).unwrap().is_empty());
+
+        // A receiver in another shim fetches over the range socket
+        let remote = reference.clone();
+        let handle = thread::spawn(move || remote_fetch(&remote, &remote.socket_path, 100, 8));
+        assert_eq!(handle.join().unwrap().unwrap(), payload[100..108].to_vec());
+
+        release(reference.id);
+        assert!(matches!(fetch(&reference, 0, 16), Err(TransferError::PayloadExpired(_))));
+        close(&reference.socket_path);
+    }
+
+    #[test]
+    fn test_published_payload_expires() {
+        let (_dir, context) = instance("short-lived");
+        let reference = publish(b"short lived".to_vec(), Duration::from_millis(20), &context).unwrap();
+        assert_eq!(read_range(reference.id, 0, 5).unwrap(), b"short".to_vec());
+
+        thread::sleep(Duration::from_millis(50));
+        assert!(matches!(read_range(reference.id, 0, 5), Err(TransferError::PayloadExpired(id)) if id == reference.id));
+        close(&reference.socket_path);
+    }
+
+    #[test]
+    fn test_payloads_are_only_served_by_their_publisher() {
+        let (_first_dir, first) = instance("first");
+        let (_second_dir, second) = instance("second");
+        let reference = publish(b"first payload".to_vec(), Duration::from_secs(60), &first).unwrap();
+        let other = publish(b"second payload".to_vec(), Duration::from_secs(60), &second).unwrap();
+        assert_ne!(reference.id, other.id);
+
+        // the range server of another instance does not serve the payload
+        assert!(remote_fetch(&reference, &other.socket_path, 0, 5).is_err());
+        assert_eq!(remote_fetch(&reference, &reference.socket_path, 0, 5).unwrap(), b"first".to_vec());
+
+        // closing the server of an instance drops its payloads and removes its socket
+        close(&reference.socket_path);
+        assert!(!Path::new(&reference.socket_path).exists());
+        assert!(matches!(read_range(reference.id, 0, 5), Err(TransferError::PayloadExpired(_))));
+        assert_eq!(read_range(other.id, 0, 6).unwrap(), b"second".to_vec());
+        close(&other.socket_path);
+    }
+}
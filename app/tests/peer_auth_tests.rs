@@ -0,0 +1,61 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::os::unix::net::UnixStream;
+    use oci_spec::runtime::{LinuxBuilder, Spec};
+    use roadrunner::peer_auth::{cgroup_matches, peer_credentials, process_cgroups, sandbox_cgroup, PeerPolicy, PEER_CGROUPS_ANNOTATION};
+
+    #[test]
+    fn test_peer_credentials() {
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        let credentials = peer_credentials(&socket).unwrap();
+        assert_eq!(credentials.pid, std::process::id());
+        assert_eq!(credentials.uid, unsafe { libc::geteuid() });
+        assert!(PeerPolicy { uids: vec![], cgroups: vec!["/nowhere".to_string()] }.authorize(&socket).is_ok(),
+            "connections from the shim itself are accepted");
+        assert!(!process_cgroups(std::process::id()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_cgroup_matches() {
+        let path = "/kubepods/besteffort/pod1234/abcd";
+        assert!(cgroup_matches(path, "/kubepods/besteffort/pod1234"));
+        assert!(cgroup_matches(path, "/kubepods/besteffort/pod1234/"));
+        assert!(!cgroup_matches(path, "/kubepods/besteffort/pod12"));
+        assert!(!cgroup_matches("/kubepods/besteffort/pod5678/efgh", "/kubepods/besteffort/pod1234"));
+        let systemd = "/kubepods.slice/kubepods-besteffort.slice/kubepods-besteffort-pod1234.slice/cri-containerd-abcd.scope";
+        assert!(cgroup_matches(systemd, "kubepods-besteffort-pod1234.slice"));
+        assert!(!cgroup_matches(systemd, "kubepods-besteffort-pod5678.slice"));
+    }
+
+    #[test]
+    fn test_sandbox_cgroup() {
+        assert_eq!(sandbox_cgroup("/kubepods/besteffort/pod1234/abcd"), Some("/kubepods/besteffort/pod1234".to_string()));
+        assert_eq!(sandbox_cgroup("kubepods-pod1234.slice:cri-containerd:abcd"), Some("kubepods-pod1234.slice".to_string()));
+        assert_eq!(sandbox_cgroup("/abcd"), None);
+    }
+
+    #[test]
+    fn test_policy_from_spec() {
+        let mut spec = Spec::default();
+        spec.set_linux(Some(LinuxBuilder::default().cgroups_path("/kubepods/pod1234/abcd").build().unwrap()));
+        let policy = PeerPolicy::from_spec(&spec);
+        assert_eq!(policy.cgroups, vec!["/kubepods/pod1234".to_string()]);
+        assert!(policy.uids.contains(&0));
+
+        let mut annotations = HashMap::new();
+        annotations.insert(PEER_CGROUPS_ANNOTATION.to_string(), "/system.slice, tenant-a.slice".to_string());
+        spec.set_annotations(Some(annotations));
+        assert_eq!(PeerPolicy::from_spec(&spec).cgroups, vec!["/system.slice".to_string(), "tenant-a.slice".to_string()]);
+    }
+
+    #[test]
+    fn test_policy_without_cgroup_denies() {
+        let policy = PeerPolicy::from_spec(&Spec::default());
+        assert!(policy.cgroups.is_empty());
+        assert!(!policy.admits_cgroups(&["/kubepods/pod1234/abcd".to_string()]), "no cgroup is allowed");
+
+        let (socket, _peer) = UnixStream::pair().unwrap();
+        assert!(policy.authorize(&socket).is_ok(), "connections from the shim itself are still accepted");
+    }
+}
@@ -0,0 +1,75 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+    use oci_spec::runtime::Spec;
+    use wasmedge_sdk::Vm;
+    use roadrunner::error::TransferError;
+    use roadrunner::host_context::HostContext;
+    use roadrunner::pod::{enter, find_target_module, get_module, is_pod_shared, is_registered, register_module, unregister_module};
+
+    fn spec_with_annotations(entries: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> = entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    #[test]
+    fn test_is_pod_shared() {
+        assert!(is_pod_shared(&spec_with_annotations(&[("pod.shared", "true")])));
+        assert!(!is_pod_shared(&spec_with_annotations(&[("pod.shared", "false")])));
+        assert!(!is_pod_shared(&spec_with_annotations(&[])));
+    }
+
+    #[test]
+    fn test_find_target_module() {
+        let sender = spec_with_annotations(&[("pod.shared", "true")]);
+        let receiver = spec_with_annotations(&[("pod.shared", "true"), ("target.function", "/alice-lib.wasm")]);
+
+        register_module("fanout.wasm", Arc::new(HostContext::new(sender, "/run/bundle/fanout", None)));
+        register_module("alice-lib.wasm", Arc::new(HostContext::new(receiver, "/run/bundle/alice", None)));
+
+        let target = find_target_module("fanout.wasm").expect("target module should be found");
+        assert_eq!(target.module_name, "alice-lib.wasm");
+        assert_eq!(target.function_name, "alice-lib.wasm");
+        assert!(find_target_module("alice-lib.wasm").is_none(), "sender must not be its own target");
+
+        // a stale bundle must not remove a module registered again by a restarted container
+        unregister_module("alice-lib.wasm", "/run/bundle/stale");
+        assert!(is_registered("alice-lib.wasm"));
+
+        unregister_module("alice-lib.wasm", "/run/bundle/alice");
+        unregister_module("fanout.wasm", "/run/bundle/fanout");
+        assert!(find_target_module("fanout.wasm").is_none());
+    }
+
+    #[test]
+    fn test_target_and_wasi_config_per_module() {
+        let sender = spec_with_annotations(&[("pod.shared", "true")]);
+        let first = spec_with_annotations(&[("pod.shared", "true"), ("target.function", "/resize")]);
+        let second = spec_with_annotations(&[("pod.shared", "true"), ("target.function", "/store")]);
+
+        register_module("zz-sender.wasm", Arc::new(HostContext::new(sender, "/run/bundle/sender", None)));
+        register_module("zz-store.wasm", Arc::new(HostContext::new(second, "/run/bundle/store", None)));
+        register_module("zz-resize.wasm", Arc::new(HostContext::new(first, "/run/bundle/resize", None)));
+        for _ in 0..10 {
+            assert_eq!(find_target_module("zz-sender.wasm").unwrap().module_name, "zz-resize.wasm");
+        }
+
+        // every module keeps the WASI configuration of its own container
+        let wasi = get_module("zz-store.wasm").unwrap().wasi;
+        assert!(wasi.preopens[0].starts_with("/:"));
+        assert_eq!(wasi.args, get_module("zz-resize.wasm").unwrap().wasi.args);
+
+        for (module, bundle) in [("zz-sender.wasm", "/run/bundle/sender"), ("zz-store.wasm", "/run/bundle/store"), ("zz-resize.wasm", "/run/bundle/resize")] {
+            unregister_module(module, bundle);
+        }
+        let mut vm = Vm::new(None).expect("Failed to create VM");
+        assert!(matches!(enter(&mut vm, "zz-store.wasm"), Err(TransferError::ModuleNotFound(_))),
+            "unregistered modules must not enter the VM");
+    }
+}
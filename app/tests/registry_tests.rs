@@ -0,0 +1,105 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::thread;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use roadrunner::registry::{instance_name, reachable_address, Registry, DEFAULT_TTL, REGISTRY_ANNOTATION, REGISTRY_TTL_ANNOTATION};
+
+    fn spec_with(annotations: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> =
+            annotations.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    /// Connects to the redis-server at `REDIS_URL` or on the default local port, `None` if no
+    /// server is running.
+    fn local_registry(ttl: Duration) -> Option<Registry> {
+        let url = std::env::var("REDIS_URL").unwrap_or_else(|_| "redis://127.0.0.1:6379/".to_string());
+        let registry = Registry::new(&url, ttl).unwrap();
+        match registry.resolve("probe") {
+            Ok(_) => Some(registry),
+            Err(e) => {
+                eprintln!("skipping, no redis-server at {}: {}", url, e);
+                None
+            }
+        }
+    }
+
+    fn unique(name: &str) -> String {
+        format!("{}-{}", name, uuid::Uuid::new_v4())
+    }
+
+    #[test]
+    fn test_reachable_address() {
+        assert_eq!(reachable_address("10.0.0.1:8080".parse().unwrap()), "10.0.0.1:8080");
+        let host = hostname::get().unwrap().to_string_lossy().to_string();
+        assert_eq!(reachable_address("0.0.0.0:8080".parse().unwrap()), format!("{}:8080", host));
+    }
+
+    #[test]
+    fn test_from_spec() {
+        assert!(Registry::from_spec(&Spec::default()).unwrap().is_none());
+
+        let spec = spec_with(&[(REGISTRY_ANNOTATION, "redis://127.0.0.1:6379/")]);
+        assert_eq!(Registry::from_spec(&spec).unwrap().unwrap().ttl(), DEFAULT_TTL);
+
+        let spec = spec_with(&[(REGISTRY_ANNOTATION, "redis://127.0.0.1:6379/"), (REGISTRY_TTL_ANNOTATION, "5")]);
+        assert_eq!(Registry::from_spec(&spec).unwrap().unwrap().ttl(), Duration::from_secs(5));
+
+        let spec = spec_with(&[(REGISTRY_ANNOTATION, "redis://127.0.0.1:6379/"), (REGISTRY_TTL_ANNOTATION, "soon")]);
+        assert!(Registry::from_spec(&spec).is_err());
+        assert!(Registry::from_spec(&spec_with(&[(REGISTRY_ANNOTATION, "not a url")])).is_err());
+    }
+
+    #[test]
+    fn test_instance_name() {
+        assert!(instance_name("/run/containerd/io.containerd.runtime.v2.task/default/abc/").ends_with(":abc"));
+    }
+
+    #[test]
+    fn test_publish_resolve_withdraw() {
+        let registry = match local_registry(DEFAULT_TTL) {
+            Some(registry) => registry,
+            None => return,
+        };
+        let function = unique("resize");
+        registry.publish(&function, "node-a:1", "10.0.0.1:8080").unwrap();
+        registry.publish(&function, "node-b:2", "10.0.0.2:8080").unwrap();
+        assert_eq!(registry.resolve(&function).unwrap(), vec!["10.0.0.1:8080", "10.0.0.2:8080"]);
+
+        registry.withdraw(&function, "node-a:1").unwrap();
+        assert_eq!(registry.resolve(&function).unwrap(), vec!["10.0.0.2:8080"]);
+        registry.withdraw(&function, "node-b:2").unwrap();
+        assert!(registry.resolve(&function).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_address_expires_without_heartbeat() {
+        let registry = match local_registry(Duration::from_secs(1)) {
+            Some(registry) => registry,
+            None => return,
+        };
+        let function = unique("crashed");
+        registry.publish(&function, "node-a:1", "10.0.0.1:8080").unwrap();
+        thread::sleep(Duration::from_millis(2100));
+        assert!(registry.resolve(&function).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_heartbeat_keeps_address_alive() {
+        let registry = match local_registry(Duration::from_secs(1)) {
+            Some(registry) => registry,
+            None => return,
+        };
+        let function = unique("alive");
+        let heartbeat = registry.heartbeat(&function, "node-a:1", "10.0.0.1:8080");
+        thread::sleep(Duration::from_millis(2500));
+        assert_eq!(registry.resolve(&function).unwrap(), vec!["10.0.0.1:8080"]);
+
+        drop(heartbeat);
+        assert!(registry.resolve(&function).unwrap().is_empty());
+    }
+}
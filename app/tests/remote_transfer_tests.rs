@@ -5,7 +5,7 @@ mod tests {
     use std::net::{TcpListener, TcpStream};
     use std::thread;
     use std::time::Duration;
-    use roadrunner::remote_transfer::{handle_client, net_transfer_bind};
+    use roadrunner::remote_transfer::{handle_client, handle_client_vectored, net_transfer_bind};
 
     fn get_free_port() -> u16 {
         TcpListener::bind("127.0.0.1:0")
@@ -40,8 +40,9 @@ mod tests {
         let mut received_data = Vec::new();
         client.read_to_end(&mut received_data).expect("Failed to read data");
 
-        // Validate received data
-        assert_eq!(received_data, test_payload, "Received data does not match expected payload");
+        // Validate received data, preceded by the header byte without flags
+        assert_eq!(received_data[0], 0);
+        assert_eq!(received_data[1..], test_payload[..], "Received data does not match expected payload");
 
         // Cleanup
         client.shutdown(std::net::Shutdown::Both).unwrap();
@@ -81,4 +82,34 @@ mod tests {
         client.shutdown(std::net::Shutdown::Both).unwrap();
         server_thread.join().expect("Server thread panicked");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_handle_client_vectored() {
+        let header = b"header|".to_vec();
+        let body = vec![7u8; 200_000];
+        let mut expected = header.clone();
+        expected.extend_from_slice(&body);
+
+        let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind test listener");
+        let test_address = listener.local_addr().unwrap();
+
+        // Spawn server thread
+        let server_thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().expect("Failed to accept connection");
+            handle_client_vectored(stream, &[header.as_slice(), &[], body.as_slice()]).expect("Failed to handle client transfer");
+        });
+
+        // Connect as a client
+        let mut client = TcpStream::connect(test_address).expect("Failed to connect to server");
+        let mut received_data = Vec::new();
+        client.read_to_end(&mut received_data).expect("Failed to read data");
+
+        // Validate received data
+        assert_eq!(received_data.len(), expected.len());
+        assert_eq!(received_data, expected, "Gathered segments do not match expected payload");
+
+        // Cleanup
+        drop(client);
+        server_thread.join().expect("Server thread panicked");
+    }
+}
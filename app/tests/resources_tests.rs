@@ -0,0 +1,83 @@
+#[cfg(test)]
+mod tests {
+    use std::fs::{self, File, OpenOptions};
+    use std::os::unix::io::{AsRawFd, IntoRawFd};
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use oci_spec::runtime::Spec;
+    use tempfile::tempdir;
+    use roadrunner::advertise;
+    use roadrunner::resources::InstanceResources;
+
+    fn write_fd(fd: i32, data: &[u8]) {
+        assert_eq!(unsafe { libc::write(fd, data.as_ptr() as *const libc::c_void, data.len()) }, data.len() as isize);
+    }
+
+    #[test]
+    fn test_redirect_and_restore_stdio() {
+        let dir = tempdir().unwrap();
+        let original_path = dir.path().join("original.log");
+        let container_path = dir.path().join("container.log");
+        // a descriptor standing in for the shim's stdout
+        let original = File::create(&original_path).unwrap();
+        let target = original.as_raw_fd();
+        let opened = OpenOptions::new().write(true).create(true).open(&container_path).unwrap().into_raw_fd();
+
+        let mut resources = InstanceResources::new();
+        resources.redirect_stdio(target, opened).unwrap();
+        write_fd(target, b"container");
+        resources.restore_stdio();
+        write_fd(target, b"shim");
+
+        assert_eq!(fs::read_to_string(&container_path).unwrap(), "container");
+        assert_eq!(fs::read_to_string(&original_path).unwrap(), "shim");
+    }
+
+    #[test]
+    fn test_release_once() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("bundle.sock").display().to_string();
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+
+        let mut resources = InstanceResources::new();
+        resources.track_socket(socket_path.clone());
+        // a spec without a cgroups path maps to a no-op cgroup
+        resources.track_cgroup(Spec::default());
+        assert!(!resources.is_released());
+        resources.release().unwrap();
+        assert!(resources.is_released());
+        assert!(!Path::new(&socket_path).exists());
+
+        // a socket bound again by a later instance is not torn down by a second release
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        resources.release().unwrap();
+        assert!(Path::new(&socket_path).exists());
+    }
+
+    #[test]
+    fn test_release_withdraws_advertisement() {
+        let dir = tempdir().unwrap();
+        let bundle_path = dir.path().join("bundle").display().to_string();
+        let listener = advertise::bind("127.0.0.1:0", None).unwrap();
+        advertise::advertise(&bundle_path, listener.local_addr().unwrap()).unwrap();
+
+        // an instance exiting while serving a transfer leaves its address behind
+        let mut resources = InstanceResources::new();
+        resources.track_advertisement(bundle_path.clone());
+        assert!(advertise::resolve(&bundle_path).is_some());
+        resources.release().unwrap();
+        assert_eq!(advertise::resolve(&bundle_path), None);
+    }
+
+    #[test]
+    fn test_release_on_drop() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("bundle.sock").display().to_string();
+        let _listener = UnixListener::bind(&socket_path).unwrap();
+        {
+            let mut resources = InstanceResources::new();
+            resources.track_socket(socket_path.clone());
+        }
+        assert!(!Path::new(&socket_path).exists());
+    }
+}
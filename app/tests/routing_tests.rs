@@ -0,0 +1,68 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use roadrunner::routing::{record, stats, RouteSet, RouteStats, WeightedRoute, ROUTES_ANNOTATION};
+
+    #[test]
+    fn test_parse_routes() {
+        let routes = RouteSet::parse("resize:v1=90, resize:v2=10").unwrap();
+        assert_eq!(routes.routes, vec![
+            WeightedRoute { function: "resize:v1".to_string(), weight: 90 },
+            WeightedRoute { function: "resize:v2".to_string(), weight: 10 },
+        ]);
+        assert!(RouteSet::parse("resize:v1").is_err());
+        assert!(RouteSet::parse("resize:v1=ninety").is_err());
+        assert!(RouteSet::parse("resize:v1=0").is_err());
+
+        let mut spec = Spec::default();
+        assert_eq!(RouteSet::from_spec(&spec).unwrap(), None);
+        let mut annotations = HashMap::new();
+        annotations.insert(ROUTES_ANNOTATION.to_string(), "resize:v2=1".to_string());
+        spec.set_annotations(Some(annotations));
+        assert_eq!(RouteSet::from_spec(&spec).unwrap().unwrap().choose(), "resize:v2");
+    }
+
+    #[test]
+    fn test_weighted_choice() {
+        let routes = RouteSet::parse("resize:v1=90,resize:v2=10").unwrap();
+        assert_eq!(routes.choose_at(0), "resize:v1");
+        assert_eq!(routes.choose_at(89), "resize:v1");
+        assert_eq!(routes.choose_at(90), "resize:v2");
+        assert_eq!(routes.choose_at(99), "resize:v2");
+
+        let canary = (0..10_000).filter(|_| routes.choose() == "resize:v2").count();
+        assert!((700..1300).contains(&canary), "canary got {} of 10000 transfers", canary);
+    }
+
+    #[test]
+    fn test_choice_among_live_versions() {
+        let routes = RouteSet::parse("resize:v1=90,resize:v2=10,resize:v3=0").unwrap();
+        // versions without live replicas are left out of the roll
+        assert!((0..100).all(|_| routes.choose_among(|version| version != "resize:v1") == Some("resize:v2")));
+        assert_eq!(routes.choose_among(|version| version == "resize:v3"), None);
+        assert_eq!(routes.choose_among(|_| false), None);
+
+        // weights near the limit neither overflow when summed nor when rolled over
+        let heavy = RouteSet::parse(&format!("resize:v1={0},resize:v2={0}", u32::MAX)).unwrap();
+        assert_eq!(heavy.choose_at(u32::MAX as u64), "resize:v2");
+        assert!(heavy.choose_among(|_| true).is_some());
+    }
+
+    #[test]
+    fn test_per_version_stats() {
+        record("thumbnail:v3", 100, Duration::from_millis(4), true);
+        record("thumbnail:v3", 300, Duration::from_millis(8), false);
+        let v3 = stats("thumbnail:v3");
+        assert_eq!(v3.transfers, 2);
+        assert_eq!(v3.failures, 1);
+        assert_eq!(v3.bytes, 400);
+        assert_eq!(v3.mean_latency(), Duration::from_millis(6));
+        assert_eq!(v3.error_rate(), 0.5);
+        assert_eq!(stats("thumbnail:v4").transfers, 0);
+
+        let many = RouteStats { transfers: u32::MAX as u64 * 2, latency: Duration::from_secs(u32::MAX as u64 * 2), ..RouteStats::default() };
+        assert_eq!(many.mean_latency(), Duration::from_secs(1));
+    }
+}
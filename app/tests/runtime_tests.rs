@@ -0,0 +1,282 @@
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use tempfile::tempdir;
+    use wasmedge_sdk::config::{CommonConfigOptions, ConfigBuilder, HostRegistrationConfigOptions};
+    use wasmedge_sdk::{params, Caller, ImportObjectBuilder, Vm, WasmVal};
+    use roadrunner::error::TransferError;
+    use roadrunner::host_context::HostContext;
+    use roadrunner::checksum::{self, Algorithm, TRAILER_LEN};
+    use roadrunner::{data_hose, framing, mux, payload_cache, pod, transfers};
+    use roadrunner::utils::oci_utils;
+    use roadrunner::runtime::{self, Runtime};
+    use roadrunner::stream::Streams;
+
+    /// Function returning the length of its input.
+    const INPUT_LEN: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (func (export "allocate_memory") (param i32) (result i32) i32.const 1024)
+          (func (export "deallocate_memory") (param i32))
+          (func (export "start") (param i32 i32) (result i64) local.get 1 i64.extend_i32_u))
+    "#;
+
+    /// Program whose `_start` only returns once its `on_shutdown` hook ran.
+    const UNTIL_SHUTDOWN: &str = r#"
+        (module
+          (global $stopped (mut i32) (i32.const 0))
+          (func (export "_start") (loop global.get $stopped i32.eqz br_if 0))
+          (func (export "on_shutdown") i32.const 1 global.set $stopped))
+    "#;
+
+    /// Module of the pod-shared VM whose `_start` sends "hello" with `send_vectored` and traps
+    /// unless the target answered with the length of the payload.
+    const POD_SENDER: &str = r#"
+        (module
+          (import "wasi_export" "send_vectored" (func $send_vectored (param i32 i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "hello")
+          (data (i32.const 16) "\00\00\00\00\05\00\00\00")
+          (data (i32.const 24) "\40\00\00\00\08\00\00\00")
+          (func (export "_start")
+            (if (i32.ne (call $send_vectored (i32.const 16) (i32.const 1) (i32.const 24) (i32.const 1)) (i32.const 8))
+              (then unreachable))
+            (if (i64.ne (i64.load (i32.const 64)) (i64.const 5))
+              (then unreachable))))
+    "#;
+
+    /// Like [`POD_SENDER`], sending with `send_async` and waiting for the result with `wait_any`.
+    const POD_ASYNC_SENDER: &str = r#"
+        (module
+          (import "wasi_export" "send_async" (func $send_async (param i32 i32) (result i32)))
+          (import "wasi_export" "wait_any" (func $wait_any (param i32 i32) (result i32)))
+          (import "wasi_export" "read_result" (func $read_result (param i32 i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (data (i32.const 0) "hello")
+          (func (export "_start")
+            (i32.store (i32.const 32) (call $send_async (i32.const 0) (i32.const 5)))
+            (if (i32.ne (call $wait_any (i32.const 32) (i32.const 1)) (i32.const 0))
+              (then unreachable))
+            (if (i32.ne (call $read_result (i32.load (i32.const 32)) (i32.const 64) (i32.const 8)) (i32.const 8))
+              (then unreachable))
+            (if (i64.ne (i64.load (i32.const 64)) (i64.const 5))
+              (then unreachable))))
+    "#;
+
+    /// Stream entrypoint ending the incoming stream itself before answering `42`.
+    const STREAM_CLOSER: &str = r#"
+        (module
+          (import "wasi_export" "close" (func $close (param i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "on_stream") (param i32) (result i64)
+            (drop (call $close (local.get 0)))
+            i64.const 42))
+    "#;
+
+    /// Module passing its arguments to `read_memory_host` and returning its status.
+    const HOST_SENDER: &str = r#"
+        (module
+          (import "wasi_export" "read_memory_host" (func $send (param i32 i32) (result i32)))
+          (memory (export "memory") 1)
+          (func (export "send") (param i32 i32) (result i32)
+            (call $send (local.get 0) (local.get 1))))
+    "#;
+
+    fn function_vm() -> Vm {
+        module_vm(INPUT_LEN)
+    }
+
+    fn module_vm(wat: &str) -> Vm {
+        let config = ConfigBuilder::new(CommonConfigOptions::default())
+            .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(true))
+            .build()
+            .expect("Failed to build config");
+        Vm::new(Some(config))
+            .expect("Failed to create VM")
+            .register_module_from_bytes("main", wat::parse_str(wat).unwrap())
+            .expect("Failed to register module")
+    }
+
+    #[test]
+    fn test_pooled_requests_to_listener() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let bundle_path = temp_dir.path().join("default").join("function").display().to_string();
+        let socket_path = format!("{}.sock", bundle_path);
+        std::fs::create_dir_all(temp_dir.path().join("default")).unwrap();
+
+        let context = Arc::new(HostContext::new(Spec::default(), &bundle_path, None));
+        let mut runtime = Runtime::new(context.clone(), function_vm());
+        let listener = thread::spawn(move || runtime.create_server_socket().map_err(|e| e.to_string()));
+        while !Path::new(&socket_path).exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let connection = mux::pooled_connection(&socket_path).expect("connect failed");
+        assert_eq!(mux::request(&socket_path, b"first").unwrap(), 5i64.to_le_bytes().to_vec());
+        assert_eq!(mux::request(&socket_path, b"second").unwrap(), 6i64.to_le_bytes().to_vec());
+        assert!(
+            Arc::ptr_eq(&connection, &mux::pooled_connection(&socket_path).unwrap()),
+            "both requests should be served over the pooled connection"
+        );
+
+        // cache references are only resolved when announced by the sender
+        let lease = payload_cache::lease(&[7u8; 100]).unwrap();
+        let reference = lease.reference().encode();
+        let resolved = mux::request_flagged(&socket_path, framing::CACHE_REF, &[reference.as_slice()]).unwrap();
+        assert_eq!(resolved, 100i64.to_le_bytes().to_vec());
+        assert_eq!(mux::request(&socket_path, &reference).unwrap(), (reference.len() as i64).to_le_bytes().to_vec());
+
+        // trailers are only stripped and verified when announced by the sender
+        let trailer = checksum::trailer(Algorithm::Crc32c, &[b"payload".as_slice()]);
+        let sealed = [b"payload".as_slice(), trailer.as_slice()];
+        assert_eq!(mux::request_flagged(&socket_path, framing::CHECKSUM, &sealed).unwrap(), 7i64.to_le_bytes().to_vec());
+        assert_eq!(mux::request_vectored(&socket_path, &sealed).unwrap(), (7 + TRAILER_LEN as i64).to_le_bytes().to_vec());
+        let err = mux::request_flagged(&socket_path, framing::CHECKSUM, &[b"payload".as_slice()]).unwrap_err();
+        assert!(checksum::is_mismatch(&err));
+
+        // the listener stops accepting and drains the pooled connection
+        context.shutdown.request();
+        listener.join().unwrap().expect("listener failed");
+        assert!(mux::request(&socket_path, b"third").is_err());
+    }
+
+    #[test]
+    fn test_shutdown_hook_interrupts_start() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let bundle_path = temp_dir.path().join("default").join("program").display().to_string();
+        let context = Arc::new(HostContext::new(Spec::default(), &bundle_path, None));
+
+        let shutdown = context.shutdown.clone();
+        let requester = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            shutdown.request();
+        });
+        runtime::start(context, module_vm(UNTIL_SHUTDOWN), String::from("main")).expect("_start failed");
+        requester.join().unwrap();
+    }
+
+    #[test]
+    fn test_stream_closed_by_guest_is_answered() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let bundle_path = temp_dir.path().join("default").join("stream").display().to_string();
+        let socket_path = format!("{}.sock", bundle_path);
+        std::fs::create_dir_all(temp_dir.path().join("default")).unwrap();
+
+        let context = Arc::new(HostContext::new(Spec::default(), &bundle_path, None));
+        let import = data_hose::with_transfer_functions(ImportObjectBuilder::new(), Some(context.clone()))
+            .unwrap()
+            .build("wasi_export")
+            .unwrap();
+        let config = ConfigBuilder::new(CommonConfigOptions::default())
+            .with_host_registration_config(HostRegistrationConfigOptions::default().wasi(true))
+            .build()
+            .expect("Failed to build config");
+        let vm = Vm::new(Some(config))
+            .and_then(|vm| vm.register_import_module(import))
+            .and_then(|vm| vm.register_module_from_bytes("main", wat::parse_str(STREAM_CLOSER).unwrap()))
+            .expect("Failed to create VM");
+        let mut runtime = Runtime::new(context.clone(), vm);
+        let listener = thread::spawn(move || runtime.create_server_socket().map_err(|e| e.to_string()));
+        while !Path::new(&socket_path).exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let streams = Streams::default();
+        let id = streams.open(&socket_path).expect("open failed");
+        streams.write(id, b"chunk").unwrap();
+        assert_eq!(streams.close(id).unwrap(), 42i64.to_le_bytes().to_vec());
+
+        context.shutdown.request();
+        listener.join().unwrap().expect("listener failed");
+    }
+
+    /// Writes the bundle of a pod-shared container and returns its spec.
+    fn pod_bundle(bundle_path: &str, annotations: &str) -> Spec {
+        fs::create_dir_all(Path::new(bundle_path).join("rootfs")).unwrap();
+        let config = format!(r#"{{"root": {{"path": "rootfs"}}, "annotations": {{"pod.shared": "true"{}}}}}"#, annotations);
+        fs::write(Path::new(bundle_path).join("config.json"), config).unwrap();
+        oci_utils::load_spec(bundle_path.to_string()).expect("Failed to load spec")
+    }
+
+    /// Runs the `_start` of `sender_wat` as module `sender` of a pod-shared VM, next to a module
+    /// `target` answering with the length of its input, which is served by its listener.
+    fn run_pod_pair(sender_wat: &str, sender: &str, target: &str) -> Result<(), String> {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let sender_bundle = temp_dir.path().join("default").join(sender).display().to_string();
+        let target_bundle = temp_dir.path().join("default").join(target).display().to_string();
+        let sender_context = Arc::new(HostContext::new(pod_bundle(&sender_bundle, ""), &sender_bundle, None));
+        let annotations = format!(r#", "target.function": "/{}", "target.address": "127.0.0.1:0""#, target);
+        let target_context = Arc::new(HostContext::new(pod_bundle(&target_bundle, &annotations), &target_bundle, None));
+
+        let import = data_hose::with_transfer_functions(ImportObjectBuilder::new(), None)
+            .unwrap()
+            .build("wasi_export")
+            .unwrap();
+        let vm = module_vm(INPUT_LEN)
+            .register_import_module(import)
+            .unwrap()
+            .register_module_from_bytes(target, wat::parse_str(INPUT_LEN).unwrap())
+            .unwrap()
+            .register_module_from_bytes(sender, wat::parse_str(sender_wat).unwrap())
+            .unwrap();
+        pod::register_module(target, target_context.clone());
+        pod::register_module(sender, sender_context.clone());
+
+        let mut listener = Runtime::new(target_context.clone(), vm.clone()).with_module(target.to_string());
+        let serving = thread::spawn(move || listener.create_server_socket().map_err(|e| e.to_string()));
+        while !Path::new(&format!("{}.sock", target_bundle)).exists() {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        let started = runtime::start(sender_context, vm, sender.to_string()).map_err(|e| e.to_string());
+
+        target_context.shutdown.request();
+        serving.join().unwrap().expect("listener failed");
+        pod::unregister_module(target, &target_bundle);
+        pod::unregister_module(sender, &sender_bundle);
+        started
+    }
+
+    #[test]
+    fn test_pod_shared_start_sends_within_vm() {
+        // the target is served by its listener while `_start` waits for the reply
+        run_pod_pair(POD_SENDER, "rt-sender", "rt-echo").expect("_start failed");
+    }
+
+    #[test]
+    fn test_pod_shared_start_waits_for_async_transfer_within_vm() {
+        run_pod_pair(POD_ASYNC_SENDER, "rt-async-sender", "rt-async-echo").expect("_start failed");
+    }
+
+    #[test]
+    fn test_failed_host_transfer_returns_status() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let bundle_path = temp_dir.path().join("default").join("host-sender").display().to_string();
+        let context = Arc::new(HostContext::new(Spec::default(), &bundle_path, None));
+        let sender = context.clone();
+        let import = ImportObjectBuilder::new()
+            .with_func::<(i32, i32), i32>("read_memory_host", move |frame, input| {
+                data_hose::read_memory_host(&sender, Caller::new(frame), input)
+            })
+            .unwrap()
+            .build("wasi_export")
+            .unwrap();
+        let vm = module_vm(INPUT_LEN)
+            .register_import_module(import)
+            .unwrap()
+            .register_module_from_bytes("host-sender", wat::parse_str(HOST_SENDER).unwrap())
+            .unwrap();
+
+        // neither a missing target nor a payload outside of the memory traps the guest
+        let status = |ptr: i32, len: i32| {
+            vm.run_func(Some("host-sender"), "send", params!(ptr, len)).expect("guest must not trap")[0].to_i32()
+        };
+        assert_eq!(status(0, 5), transfers::error_status(TransferError::TargetNotFound(String::new()).code()));
+        assert!(status(70_000, 5) < 0);
+    }
+}
@@ -0,0 +1,36 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use roadrunner::host_context::HostContext;
+    use roadrunner::shutdown::{grace_period, Shutdown, DEFAULT_GRACE_PERIOD, GRACE_PERIOD_ANNOTATION};
+
+    fn spec_with_annotations(entries: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> = entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    #[test]
+    fn test_grace_period() {
+        assert_eq!(grace_period(&spec_with_annotations(&[])), DEFAULT_GRACE_PERIOD);
+        assert_eq!(grace_period(&spec_with_annotations(&[(GRACE_PERIOD_ANNOTATION, "3")])), Duration::from_secs(3));
+        assert_eq!(grace_period(&spec_with_annotations(&[(GRACE_PERIOD_ANNOTATION, "soon")])), DEFAULT_GRACE_PERIOD);
+    }
+
+    #[test]
+    fn test_shutdown_shared_with_context() {
+        let shutdown = Shutdown::default();
+        let context = HostContext::new(Spec::default(), "/run/bundle/alice", None).with_shutdown(shutdown.clone());
+        assert!(!context.shutdown.is_requested());
+
+        shutdown.request();
+        assert!(context.shutdown.is_requested());
+        assert!(!Shutdown::default().is_requested(), "a request must only reach its own instance");
+    }
+}
@@ -0,0 +1,130 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::Path;
+    use std::thread;
+    use std::time::Duration;
+    use tempfile::tempdir;
+    use roadrunner::utils::socket_utils::{
+        abstract_name, bind, bound_sockets, connect, exists, listening_sockets, parse_bound, parse_listening, sweep_stale,
+        sweep_stale_except, STALE_GRACE,
+    };
+
+    #[test]
+    fn test_abstract_name() {
+        let name = abstract_name("/run/containerd/io.containerd.runtime.v2.task/default/abc.sock");
+        assert!(name.starts_with("roadrunner/"));
+        assert!(name.len() < 107);
+        assert_eq!(name, abstract_name("/run/containerd/io.containerd.runtime.v2.task/default/abc.sock"));
+        assert_ne!(name, abstract_name("/run/containerd/io.containerd.runtime.v2.task/default/abd.sock"));
+    }
+
+    #[test]
+    fn test_parse_listening() {
+        let table = "Num       RefCount Protocol Flags    Type St Inode Path\n\
+            0000000000000000: 00000002 00000000 00010000 0001 01 1001 /run/a.sock\n\
+            0000000000000000: 00000002 00000000 00000000 0001 03 1002 /run/b.sock\n\
+            0000000000000000: 00000002 00000000 00010000 0001 01 1003 @roadrunner/00ff\n\
+            0000000000000000: 00000003 00000000 00000000 0001 03 1004\n";
+        let listening = parse_listening(table);
+        assert_eq!(listening, HashSet::from(["/run/a.sock".to_string(), "@roadrunner/00ff".to_string()]));
+        // sockets bound but not listening yet are still in use
+        let bound = parse_bound(table);
+        assert_eq!(bound, HashSet::from(["/run/a.sock".to_string(), "/run/b.sock".to_string(), "@roadrunner/00ff".to_string()]));
+    }
+
+    #[test]
+    fn test_abstract_socket_roundtrip() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("abstract.sock").display().to_string();
+        let listener = bind(&socket_path, true).unwrap();
+        // nothing is created on the filesystem, yet the function is discoverable
+        assert!(!Path::new(&socket_path).exists());
+        if listening_sockets().is_ok() {
+            assert!(exists(&socket_path));
+        }
+
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).unwrap();
+            stream.write_all(&request).unwrap();
+        });
+        let mut client = connect(&socket_path).unwrap();
+        client.write_all(b"ping").unwrap();
+        let mut response = [0u8; 4];
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"ping");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_bind_replaces_stale_file() {
+        let dir = tempdir().unwrap();
+        let socket_path = dir.path().join("stale.sock").display().to_string();
+        drop(UnixListener::bind(&socket_path).unwrap());
+        assert!(Path::new(&socket_path).exists());
+
+        let _listener = bind(&socket_path, false).unwrap();
+        assert!(connect(&socket_path).is_ok());
+        assert!(connect(&dir.path().join("missing.sock").display().to_string()).is_err());
+    }
+
+    #[test]
+    fn test_sweep_stale() {
+        let dir = tempdir().unwrap();
+        let namespace = dir.path().join("default");
+        std::fs::create_dir_all(&namespace).unwrap();
+        let live = namespace.join("live.sock").display().to_string();
+        let stale = namespace.join("stale.sock").display().to_string();
+        let plain = namespace.join("plain.sock").display().to_string();
+        let _listener = UnixListener::bind(&live).unwrap();
+        drop(UnixListener::bind(&stale).unwrap());
+        // only sockets are swept, other files are left alone
+        File::create(&plain).unwrap();
+
+        // sockets younger than the grace period may be in use by a shim the table does not list
+        let root = dir.path().display().to_string();
+        assert_eq!(sweep_stale_except(&root, &HashSet::new(), STALE_GRACE), 0);
+        assert!(Path::new(&stale).exists());
+
+        assert_eq!(sweep_stale_except(&root, &HashSet::from([live.clone()]), Duration::ZERO), 1);
+        assert!(Path::new(&live).exists());
+        assert!(!Path::new(&stale).exists());
+        assert!(Path::new(&plain).exists());
+        assert_eq!(sweep_stale(&root), 0);
+        assert!(Path::new(&live).exists());
+    }
+
+    #[test]
+    fn test_sweep_keeps_bound_sockets() {
+        let dir = tempdir().unwrap();
+        let namespace = dir.path().join("default");
+        std::fs::create_dir_all(&namespace).unwrap();
+        let binding = namespace.join("binding.sock").display().to_string();
+
+        // a sibling that bound its socket but does not listen yet is listed in the socket table
+        let socket = unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) };
+        assert!(socket >= 0);
+        let mut address: libc::sockaddr_un = unsafe { std::mem::zeroed() };
+        address.sun_family = libc::AF_UNIX as libc::sa_family_t;
+        for (slot, byte) in address.sun_path.iter_mut().zip(binding.as_bytes()) {
+            *slot = *byte as libc::c_char;
+        }
+        let bound = unsafe {
+            libc::bind(socket, &address as *const _ as *const libc::sockaddr, std::mem::size_of::<libc::sockaddr_un>() as libc::socklen_t)
+        };
+        assert_eq!(bound, 0);
+        if let Ok(table) = bound_sockets() {
+            assert!(table.contains(&binding));
+            assert!(!listening_sockets().unwrap().contains(&binding));
+            let root = dir.path().display().to_string();
+            assert_eq!(sweep_stale_except(&root, &table, Duration::ZERO), 0);
+            assert!(Path::new(&binding).exists());
+        }
+        unsafe { libc::close(socket) };
+    }
+}
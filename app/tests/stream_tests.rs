@@ -0,0 +1,192 @@
+#[cfg(test)]
+mod tests {
+    use std::io::{BufReader, Read, Write};
+    use std::os::unix::net::{UnixListener, UnixStream};
+    use std::path::Path;
+    use std::thread;
+    use tempfile::tempdir;
+    use roadrunner::stream::{self, write_chunk_frame, ChunkReader, Streams, STREAM_MAGIC};
+
+    #[test]
+    fn test_chunk_reader_splits_large_chunks() {
+        let mut buffer = Vec::new();
+        write_chunk_frame(&mut buffer, b"0123456789").unwrap();
+        write_chunk_frame(&mut buffer, b"abc").unwrap();
+        write_chunk_frame(&mut buffer, b"").unwrap();
+
+        let mut reader = ChunkReader::new(buffer.as_slice());
+        let mut chunk = [0u8; 4];
+        let mut received = Vec::new();
+        loop {
+            let read = reader.read(&mut chunk).unwrap();
+            if read == 0 {
+                break;
+            }
+            assert!(read <= chunk.len());
+            received.extend_from_slice(&chunk[..read]);
+        }
+        assert!(reader.is_finished());
+        assert_eq!(received, b"0123456789abc".to_vec());
+    }
+
+    #[test]
+    fn test_chunk_reader_rejects_truncated_stream() {
+        let mut buffer = Vec::new();
+        write_chunk_frame(&mut buffer, b"0123456789").unwrap();
+        buffer.truncate(8);
+
+        let mut received = Vec::new();
+        let err = ChunkReader::new(buffer.as_slice()).read_to_end(&mut received).expect_err("stream has no end");
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn test_stream_through_fixed_size_buffers() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let socket_path = temp_dir.path().join("target.sock").display().to_string();
+        let listener = UnixListener::bind(&socket_path).expect("Failed to bind test socket");
+        const CHUNKS: usize = 256;
+        const CHUNK_SIZE: usize = 64 * 1024;
+
+        // The receiver drains the stream through a buffer smaller than a chunk
+        let receiver = thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic).unwrap();
+            assert_eq!(&magic, STREAM_MAGIC);
+
+            let streams = Streams::default();
+            let id = streams.accept(reader);
+            let mut buffer = [0u8; 1000];
+            let mut total: i64 = 0;
+            loop {
+                let read = streams.read(id, &mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                total += buffer[..read].iter().map(|b| *b as i64).sum::<i64>();
+            }
+            streams.close(id).unwrap();
+            socket.write_all(&total.to_le_bytes()).unwrap();
+        });
+
+        let streams = Streams::default();
+        let id = streams.open(&socket_path).expect("stream should open");
+        let chunk = vec![1u8; CHUNK_SIZE];
+        for _ in 0..CHUNKS {
+            streams.write(id, &chunk).unwrap();
+        }
+        assert!(streams.read(id, &mut [0u8; 4]).is_err(), "outgoing streams are write-only");
+        assert!(Streams::default().write(id, b"foreign").is_err(), "streams belong to their instance");
+        let response = streams.close(id).unwrap();
+        assert_eq!(i64::from_le_bytes(response.try_into().unwrap()), (CHUNKS * CHUNK_SIZE) as i64);
+        assert!(streams.write(id, b"late").is_err(), "closed streams are unknown");
+
+        receiver.join().expect("receiver panicked");
+    }
+
+    /// Accepts one stream on `listener`, sums its bytes and answers with the sum.
+    fn spawn_summing_receiver(listener: UnixListener) -> thread::JoinHandle<()> {
+        thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic).unwrap();
+            assert_eq!(&magic, STREAM_MAGIC);
+            let mut payload = Vec::new();
+            ChunkReader::new(reader).read_to_end(&mut payload).unwrap();
+            let sum: i64 = payload.iter().map(|b| *b as i64).sum();
+            socket.write_all(&sum.to_le_bytes()).unwrap();
+        })
+    }
+
+    fn accept_stream(listener: &UnixListener) -> BufReader<UnixStream> {
+        let (socket, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(socket);
+        let mut magic = [0u8; 4];
+        reader.read_exact(&mut magic).unwrap();
+        reader
+    }
+
+    fn bind(dir: &Path, name: &str) -> (String, UnixListener) {
+        let path = dir.join(name).display().to_string();
+        let listener = UnixListener::bind(&path).expect("Failed to bind test socket");
+        (path, listener)
+    }
+
+    #[test]
+    fn test_tee_forwards_chunks_to_next_hop() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let (hop_b, listener_b) = bind(temp_dir.path(), "b.sock");
+        let (hop_c, listener_c) = bind(temp_dir.path(), "c.sock");
+        let receiver_c = spawn_summing_receiver(listener_c);
+
+        let sender = thread::spawn(move || {
+            let streams = Streams::default();
+            let id = streams.open(&hop_b).unwrap();
+            for _ in 0..10 {
+                streams.write(id, &[2u8; 1000]).unwrap();
+            }
+            streams.close(id).unwrap()
+        });
+
+        // B reads only a part of the stream, the rest is forwarded when it closes
+        let streams = Streams::default();
+        let id = streams.accept_forwarding(accept_stream(&listener_b), &[hop_c]).unwrap();
+        let mut buffer = [0u8; 100];
+        assert_eq!(streams.read(id, &mut buffer).unwrap(), 100);
+        let response = streams.close(id).unwrap();
+        assert_eq!(i64::from_le_bytes(response.try_into().unwrap()), 20_000);
+
+        receiver_c.join().expect("receiver panicked");
+        assert!(sender.join().expect("sender panicked").is_empty());
+    }
+
+    #[test]
+    fn test_relay_passes_stream_through() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let (hop_b, listener_b) = bind(temp_dir.path(), "b.sock");
+        let (hop_c, listener_c) = bind(temp_dir.path(), "c.sock");
+        let receiver_c = spawn_summing_receiver(listener_c);
+
+        let relay = thread::spawn(move || {
+            let (mut socket, _) = listener_b.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut magic = [0u8; 4];
+            reader.read_exact(&mut magic).unwrap();
+            let response = stream::relay(reader, &[hop_c]).unwrap();
+            socket.write_all(&response).unwrap();
+        });
+
+        let streams = Streams::default();
+        let id = streams.open(&hop_b).unwrap();
+        for _ in 0..64 {
+            streams.write(id, &[1u8; 64 * 1024]).unwrap();
+        }
+        let response = streams.close(id).unwrap();
+        assert_eq!(i64::from_le_bytes(response.try_into().unwrap()), 64 * 64 * 1024);
+
+        relay.join().expect("relay panicked");
+        receiver_c.join().expect("receiver panicked");
+    }
+
+    #[test]
+    fn test_clear_drops_streams_left_open() {
+        let temp_dir = tempdir().expect("Failed to create temp directory");
+        let (path, listener) = bind(temp_dir.path(), "target.sock");
+        let receiver = thread::spawn(move || {
+            let mut payload = Vec::new();
+            ChunkReader::new(accept_stream(&listener)).read_to_end(&mut payload)
+        });
+
+        let streams = Streams::default();
+        let id = streams.open(&path).unwrap();
+        streams.write(id, b"partial").unwrap();
+        streams.clear();
+
+        assert!(streams.write(id, b"more").is_err(), "cleared streams are unknown");
+        let received = receiver.join().expect("receiver panicked");
+        assert_eq!(received.expect_err("stream has no end").kind(), std::io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,101 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::Duration;
+    use oci_spec::runtime::Spec;
+    use roadrunner::{checksum, framing};
+    use roadrunner::compression::{self, Codec, CompressionPolicy};
+    use roadrunner::remote_transfer::net_transfer_bind_secure;
+    use roadrunner::tls::{self, CA_ANNOTATION, CERT_ANNOTATION, KEY_ANNOTATION};
+
+    fn get_free_port() -> u16 {
+        TcpListener::bind("127.0.0.1:0")
+            .expect("Failed to bind to get free port")
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    fn cert_path(name: &str) -> String {
+        format!("{}/tests/certs/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
+    fn spec_with(annotations: &[(&str, String)]) -> Spec {
+        let annotations: HashMap<String, String> =
+            annotations.iter().map(|(key, value)| (key.to_string(), value.clone())).collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec
+    }
+
+    /// Receives a payload over TLS like `connect_to_source`.
+    fn receive(address: &str) -> Vec<u8> {
+        let spec = spec_with(&[(CA_ANNOTATION, cert_path("localhost.pem"))]);
+        let (config, server_name) = tls::client_config(&spec).unwrap().expect("receiver TLS enabled");
+        let stream = loop {
+            match TcpStream::connect(address) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        let mut stream = tls::connect(stream, config, server_name).unwrap();
+        stream.write_all(&compression::hello(&compression::SUPPORTED)).unwrap();
+        stream.flush().unwrap();
+        let mut received = Vec::new();
+        tls::read_to_end(&mut stream, &mut received).unwrap();
+        stream.write_all(&[checksum::ACK_OK]).unwrap();
+        stream.flush().unwrap();
+        match framing::split_header(received).unwrap() {
+            (flags, frame) if framing::has(flags, framing::COMPRESSED) => compression::decompress(frame).unwrap(),
+            (_, payload) => payload,
+        }
+    }
+
+    fn send(payload: Vec<u8>, policy: CompressionPolicy) -> Vec<u8> {
+        let spec = spec_with(&[
+            (CERT_ANNOTATION, cert_path("localhost.pem")),
+            (KEY_ANNOTATION, cert_path("localhost.key")),
+        ]);
+        let config = tls::server_config(&spec).unwrap();
+        assert!(config.is_some(), "sender TLS enabled");
+
+        let address = format!("127.0.0.1:{}", get_free_port());
+        let server_address = address.clone();
+        let sender = thread::spawn(move || {
+            net_transfer_bind_secure(0, &[&payload[..7], &payload[7..]], server_address, policy, config)
+        });
+        let received = receive(&address);
+        sender.join().unwrap().unwrap();
+        received
+    }
+
+    #[test]
+    fn test_tls_disabled_without_annotations() {
+        let spec = spec_with(&[]);
+        assert!(tls::server_config(&spec).unwrap().is_none());
+        assert!(tls::client_config(&spec).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_tls_network_transfer() {
+        // spliced through kTLS where the kernel supports it, encrypted by rustls otherwise
+        let payload: Vec<u8> = (0..300 * 1024).map(|i| (i % 251) as u8).collect();
+        assert_eq!(send(payload.clone(), CompressionPolicy::default()), payload);
+    }
+
+    #[test]
+    fn test_tls_compressed_network_transfer() {
+        let payload = vec![7u8; 128 * 1024];
+        let policy = CompressionPolicy { codec: Some(Codec::Lz4), threshold: 1024 };
+        assert_eq!(send(payload.clone(), policy), payload);
+    }
+
+    #[test]
+    fn test_tls_missing_ca_file() {
+        let spec = spec_with(&[(CA_ANNOTATION, cert_path("missing.pem"))]);
+        assert!(tls::client_config(&spec).is_err());
+    }
+}
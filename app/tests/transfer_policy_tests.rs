@@ -0,0 +1,64 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use oci_spec::runtime::{ProcessBuilder, Spec};
+    use roadrunner::error::TransferError;
+    use roadrunner::transfer_policy::{authorize, function_name, TransferPolicy, POLICY_ANNOTATION};
+    use roadrunner::workflow::Workflow;
+
+    fn spec_with(annotations: &[(&str, &str)]) -> Spec {
+        let annotations: HashMap<String, String> =
+            annotations.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect();
+        let mut spec = Spec::default();
+        spec.set_annotations(Some(annotations));
+        spec.set_process(Some(ProcessBuilder::default().args(vec!["/resize.wasm".to_string()]).build().unwrap()));
+        spec
+    }
+
+    #[test]
+    fn test_parse_and_allows() {
+        let policy = TransferPolicy::parse("ingest->resize, resize -> store, *->audit").unwrap();
+        assert!(policy.allows("ingest", "resize"));
+        assert!(policy.allows("resize", "store"));
+        assert!(policy.allows("store", "audit"));
+        assert!(!policy.allows("ingest", "store"), "edges are not transitive");
+        assert!(!policy.allows("resize", "ingest"), "edges are directed");
+
+        let lines = TransferPolicy::parse("ingest->resize\nresize->store\n").unwrap();
+        assert_eq!(lines.edges.len(), 2);
+
+        assert!(TransferPolicy::parse("ingest").is_err());
+        assert!(TransferPolicy::parse("->resize").is_err());
+    }
+
+    #[test]
+    fn test_authorize() {
+        assert!(authorize(None, "any", "thing").is_ok(), "transfers are unrestricted without a policy");
+        let policy = TransferPolicy::parse("ingest->resize").unwrap();
+        assert!(authorize(Some(&policy), "ingest", "resize").is_ok());
+        let denied = authorize(Some(&policy), "intruder", "resize").unwrap_err();
+        assert!(matches!(&denied, TransferError::TransferDenied(source, target) if source == "intruder" && target == "resize"));
+        assert_eq!(denied.code(), 12);
+    }
+
+    #[test]
+    fn test_policy_from_spec() {
+        let spec = spec_with(&[(POLICY_ANNOTATION, "ingest->resize")]);
+        assert_eq!(TransferPolicy::from_spec(&spec).unwrap(), Some(TransferPolicy::parse("ingest->resize").unwrap()));
+        assert_eq!(TransferPolicy::from_spec(&spec_with(&[])).unwrap(), None);
+        assert!(TransferPolicy::from_spec(&spec_with(&[(POLICY_ANNOTATION, "nonsense")])).is_err());
+
+        let workflow = Workflow::from_yaml(
+            "functions:\n  - name: a\n  - name: b\n  - name: c\nedges:\n  - from: a\n    to: [b, c]\npolicy:\n  - from: a\n    to: [b, c]\n",
+        ).unwrap();
+        assert_eq!(workflow.policy.len(), 1);
+        assert_eq!(workflow.policy[0].to, vec!["b".to_string(), "c".to_string()]);
+    }
+
+    #[test]
+    fn test_function_name() {
+        assert_eq!(function_name(&spec_with(&[])), "resize.wasm");
+        assert_eq!(function_name(&spec_with(&[("target.function", "/resize")])), "resize");
+        assert_eq!(function_name(&spec_with(&[("target.function", "resize"), ("workflow.function", "thumbnail")])), "thumbnail");
+    }
+}
@@ -0,0 +1,65 @@
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::channel;
+    use roadrunner::error::TransferError;
+    use roadrunner::transfers::{error_status, Transfers, PENDING};
+
+    #[test]
+    fn test_error_status_is_distinct_from_pending() {
+        assert_eq!(error_status(0), -1);
+        assert_eq!(error_status(TransferError::TargetNotFound(String::new()).code()), -6);
+        assert_ne!(error_status(TransferError::UnknownHandle(0).code()), PENDING);
+    }
+
+    #[test]
+    fn test_poll_and_take_completed_transfer() {
+        let transfers = Transfers::default();
+        let (release, released) = channel::<()>();
+        let handle = transfers.submit(move || {
+            released.recv().unwrap();
+            Ok(b"done".to_vec())
+        });
+        assert_eq!(transfers.poll(handle), None, "transfer should still be in flight");
+        assert_eq!(transfers.take(handle), None, "in-flight transfers must not be removed");
+
+        release.send(()).unwrap();
+        assert_eq!(transfers.wait_any(&[handle]), Some(0));
+        assert_eq!(transfers.poll(handle), Some(Ok(b"done".to_vec())));
+        assert_eq!(transfers.take(handle), Some(Ok(b"done".to_vec())));
+
+        let unknown = TransferError::UnknownHandle(handle).code();
+        assert_eq!(transfers.take(handle), Some(Err(unknown)), "handles can only be taken once");
+    }
+
+    #[test]
+    fn test_wait_any_returns_first_completed() {
+        let transfers = Transfers::default();
+        let (release, released) = channel::<()>();
+        let slow = transfers.submit(move || {
+            released.recv().unwrap();
+            Ok(Vec::new())
+        });
+        let failing = transfers.submit(|| Err(TransferError::TargetNotFound("missing".to_string())));
+
+        assert_eq!(transfers.wait_any(&[slow, failing]), Some(1));
+        let code = TransferError::TargetNotFound(String::new()).code();
+        assert_eq!(transfers.take(failing), Some(Err(code)));
+
+        release.send(()).unwrap();
+        assert_eq!(transfers.wait_any(&[slow]), Some(0));
+        assert!(transfers.wait_any(&[]).is_none());
+    }
+
+    #[test]
+    fn test_handles_are_scoped_to_their_instance() {
+        let transfers = Transfers::default();
+        let other = Transfers::default();
+        let handle = transfers.submit(|| Ok(b"mine".to_vec()));
+        assert_eq!(transfers.wait_any(&[handle]), Some(0));
+
+        let unknown = TransferError::UnknownHandle(handle).code();
+        assert_eq!(other.poll(handle), Some(Err(unknown)), "other instances must not see the handle");
+        assert_eq!(other.take(handle), Some(Err(unknown)));
+        assert_eq!(transfers.take(handle), Some(Ok(b"mine".to_vec())));
+    }
+}
@@ -0,0 +1,117 @@
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::fs;
+    use oci_spec::runtime::{Process, Spec};
+    use tempfile::tempdir;
+    use roadrunner::workflow::{load_node, Transport, Workflow};
+
+    const DIAMOND_YAML: &str = r#"
+name: diamond
+functions:
+  - name: a
+    module: /fanout-wasi.wasm
+  - name: c
+    transport: unix
+  - name: d
+    transport: network
+    address: 127.0.0.1:9000
+  - name: e
+    address: 127.0.0.1:9001
+edges:
+  - from: a
+    to: [c, d]
+  - from: c
+    to: e
+  - from: d
+    to: e
+"#;
+
+    #[test]
+    fn test_parse_json_and_yaml() {
+        let json = r#"{
+            "name": "chain",
+            "functions": [{"name": "a"}, {"name": "b", "transport": "memory", "entrypoint": "hello_greet"}],
+            "edges": [{"from": "a", "to": "b"}]
+        }"#;
+        let workflow = Workflow::from_json(json).expect("JSON manifest should parse");
+        assert_eq!(workflow.edges[0].to, vec!["b".to_string()]);
+        assert_eq!(workflow.function("b").unwrap().transport, Transport::Memory);
+        assert_eq!(workflow.function("a").unwrap().transport, Transport::Auto);
+        assert!(workflow.validate().is_ok());
+
+        let workflow = Workflow::from_yaml(DIAMOND_YAML).expect("YAML manifest should parse");
+        assert_eq!(workflow.functions.len(), 4);
+        assert_eq!(workflow.function("a").unwrap().module_name(), "fanout-wasi.wasm");
+        assert!(workflow.validate().is_ok());
+    }
+
+    #[test]
+    fn test_fan_out_and_fan_in() {
+        let workflow = Workflow::from_yaml(DIAMOND_YAML).unwrap();
+
+        let a = workflow.node("a").unwrap();
+        assert_eq!(a.fan_out(), 2);
+        assert_eq!(a.fan_in(), 0);
+        let targets: Vec<&str> = a.targets.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(targets, vec!["c", "d"]);
+
+        let e = workflow.node("e").unwrap();
+        assert_eq!(e.fan_out(), 0);
+        assert_eq!(e.fan_in(), 2);
+
+        assert!(workflow.node("missing").is_none());
+    }
+
+    #[test]
+    fn test_validate_rejects_cycles() {
+        let json = r#"{
+            "functions": [{"name": "a"}, {"name": "b"}, {"name": "c"}],
+            "edges": [{"from": "a", "to": "b"}, {"from": "b", "to": "c"}, {"from": "c", "to": "b"}]
+        }"#;
+        let err = Workflow::from_json(json).unwrap().validate().expect_err("cycle must be rejected");
+        assert!(err.to_string().contains("cycle"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn test_validate_rejects_missing_functions() {
+        let json = r#"{
+            "functions": [{"name": "a"}],
+            "edges": [{"from": "a", "to": ["b"]}]
+        }"#;
+        let err = Workflow::from_json(json).unwrap().validate().expect_err("missing function must be rejected");
+        assert!(err.to_string().contains("`b`"), "unexpected error: {}", err);
+
+        let json = r#"{ "functions": [{"name": "a"}, {"name": "a"}] }"#;
+        assert!(Workflow::from_json(json).unwrap().validate().is_err(), "duplicate functions must be rejected");
+
+        let json = r#"{ "functions": [{"name": "a", "transport": "network"}] }"#;
+        assert!(Workflow::from_json(json).unwrap().validate().is_err(), "network transport requires an address");
+    }
+
+    #[test]
+    fn test_load_node_from_mounted_manifest() {
+        let bundle = tempdir().expect("Failed to create temp directory");
+        let rootfs = bundle.path().join("rootfs");
+        fs::create_dir_all(rootfs.join("etc")).unwrap();
+        fs::write(rootfs.join("etc").join("workflow.yaml"), DIAMOND_YAML).unwrap();
+
+        let mut process = Process::default();
+        process.set_args(Some(vec!["/fanout-wasi.wasm".to_string()]));
+        let mut root = oci_spec::runtime::Root::default();
+        root.set_path(rootfs.clone());
+        let mut annotations = HashMap::new();
+        annotations.insert("workflow.manifest".to_string(), "/etc/workflow.yaml".to_string());
+
+        let mut spec = Spec::default();
+        spec.set_process(Some(process));
+        spec.set_root(Some(root));
+        spec.set_annotations(Some(annotations));
+
+        let node = load_node(&spec).expect("workflow should load").expect("node should be configured");
+        assert_eq!(node.function.name, "a");
+        assert_eq!(node.fan_out(), 2);
+
+        assert!(load_node(&Spec::default()).unwrap().is_none());
+    }
+}